@@ -0,0 +1,324 @@
+/* Copyright 2019, 2020 Torbjørn Birch Moltu
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! The `bench` subcommand: measure throughput and latency of a temporary queue.
+
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
+use std::io::{stdout, ErrorKind, Write};
+use std::process::{self, Child, Command, Stdio};
+use std::str;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Instant;
+
+use posixmq::{InheritQueues, OpenOptions, PosixMq, remove_queue};
+
+use crate::{fail, fail_with, to_str};
+
+/// Messages start with a CLOCK_MONOTONIC timestamp in nanoseconds as a
+/// big-endian u64, for measuring latency.
+/// Unlike `Instant`s, these can be compared between processes.
+const STAMP_LEN: usize = 8;
+
+/// The name the queue is passed to worker processes with.
+const INHERITED_NAME: &str = "bench";
+
+struct Config {
+    messages: usize,
+    size: usize,
+    capacity: usize,
+    producers: usize,
+    consumers: usize,
+    priorities: u32,
+    nonblocking: bool,
+    processes: bool,
+}
+
+fn monotonic_nanos() -> u64 {
+    let mut now = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    now.tv_sec as u64 * 1_000_000_000 + now.tv_nsec as u64
+}
+
+/// Send the messages with the indexes `claims` yields.
+///
+/// The index decides the priority, so that the mix of priorities is the same
+/// regardless of how messages are split between producers.
+fn produce<I: Iterator<Item=usize>>(mq: &PosixMq,  size: usize,  priorities: u32,  claims: I) {
+    let mut msg = vec![0; size];
+    for n in claims {
+        let stamp = monotonic_nanos();
+        for (i, byte) in msg[..STAMP_LEN].iter_mut().enumerate() {
+            *byte = (stamp >> (56 - 8*i)) as u8;
+        }
+        let priority = (n % priorities as usize) as u32;
+        loop {
+            match mq.send(priority, &msg) {
+                Ok(()) => break,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => thread::yield_now(),
+                Err(e) => fail_with("sending", e),
+            }
+        }
+    }
+}
+
+/// Receive one message per item `claims` yields, and return their latencies
+/// in nanoseconds.
+fn consume<I: Iterator>(mq: &PosixMq,  size: usize,  claims: I) -> Vec<u64> {
+    let mut buf = vec![0; size];
+    let mut latencies = Vec::new();
+    for _ in claims {
+        let len = loop {
+            match mq.recv(&mut buf) {
+                Ok((_, len)) => break len,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => thread::yield_now(),
+                Err(e) => fail_with("receiving", e),
+            }
+        };
+        let now = monotonic_nanos();
+        let stamp = buf[..STAMP_LEN.min(len)].iter().fold(0, |stamp, &byte| stamp << 8 | byte as u64 );
+        latencies.push(now.saturating_sub(stamp));
+    }
+    latencies
+}
+
+/// Run producers and consumers as threads, which claim messages from shared
+/// counters.
+fn run_threads(mq: &PosixMq,  config: &Arc<Config>) -> Vec<u64> {
+    let sent = Arc::new(AtomicUsize::new(0));
+    let received = Arc::new(AtomicUsize::new(0));
+    let mut producers = Vec::with_capacity(config.producers);
+    for _ in 0..config.producers {
+        let mq = mq.try_clone().unwrap_or_else(|e| fail_with("cloning descriptor", e) );
+        let (config, sent) = (config.clone(), sent.clone());
+        producers.push(thread::spawn(move|| {
+            let claims = (0..).map(|_| sent.fetch_add(1, Ordering::Relaxed) )
+                .take_while(|&n| n < config.messages );
+            produce(&mq, config.size, config.priorities, claims)
+        }));
+    }
+    let mut consumers = Vec::with_capacity(config.consumers);
+    for _ in 0..config.consumers {
+        let mq = mq.try_clone().unwrap_or_else(|e| fail_with("cloning descriptor", e) );
+        let (config, received) = (config.clone(), received.clone());
+        consumers.push(thread::spawn(move|| {
+            let claims = (0..).take_while(|_| received.fetch_add(1, Ordering::Relaxed) < config.messages );
+            consume(&mq, config.size, claims)
+        }));
+    }
+    for producer in producers {
+        producer.join().unwrap_or_else(|_| fail("producer thread panicked") );
+    }
+    let mut latencies = Vec::with_capacity(config.messages);
+    for consumer in consumers {
+        let mut consumed = consumer.join().unwrap_or_else(|_| fail("consumer thread panicked") );
+        latencies.append(&mut consumed);
+    }
+    latencies
+}
+
+fn spawn_worker(mq: &PosixMq,  args: &[String]) -> Child {
+    let exe = env::current_exe().unwrap_or_else(|e| fail_with("finding posixmq executable", e) );
+    Command::new(exe)
+        .arg("bench-worker")
+        .args(args)
+        .inherit_queues(&[(INHERITED_NAME, mq)])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap_or_else(|e| fail_with("starting worker process", e) )
+}
+
+/// Run producers and consumers as processes, with the messages split
+/// evenly between them in advance.
+fn run_processes(mq: &PosixMq,  config: &Config) -> Vec<u64> {
+    let mut producers = Vec::with_capacity(config.producers);
+    for i in 0..config.producers {
+        producers.push(spawn_worker(mq, &[
+            "produce".to_string(),
+            config.size.to_string(),
+            config.priorities.to_string(),
+            i.to_string(),
+            config.producers.to_string(),
+            config.messages.to_string(),
+        ]));
+    }
+    let mut consumers = Vec::with_capacity(config.consumers);
+    for i in 0..config.consumers {
+        let share = config.messages / config.consumers
+                  + if i < config.messages % config.consumers {1} else {0};
+        consumers.push(spawn_worker(mq, &[
+            "consume".to_string(),
+            config.size.to_string(),
+            share.to_string(),
+        ]));
+    }
+    for producer in producers {
+        let output = producer.wait_with_output().unwrap_or_else(|e| fail_with("waiting for producer", e) );
+        if !output.status.success() {
+            fail("producer process failed");
+        }
+    }
+    let mut latencies = Vec::with_capacity(config.messages);
+    for consumer in consumers {
+        let output = consumer.wait_with_output().unwrap_or_else(|e| fail_with("waiting for consumer", e) );
+        if !output.status.success() {
+            fail("consumer process failed");
+        }
+        for stamp in output.stdout.chunks(STAMP_LEN) {
+            latencies.push(stamp.iter().fold(0, |stamp, &byte| stamp << 8 | byte as u64 ));
+        }
+    }
+    latencies
+}
+
+/// The `bench-worker` subcommand that `bench --processes` starts.
+///
+/// Consumers write the latencies as big-endian u64s to stdout.
+pub fn worker(args: &[OsString]) {
+    let args = args.iter().map(|arg| to_str(arg, "argument") ).collect::<Vec<_>>();
+    // `min` also rejects a zero step or priorities, and too short messages
+    let number = |i: usize,  min: usize| -> usize {
+        match args.get(i).and_then(|arg| arg.parse().ok() ) {
+            Some(n) if n >= min => n,
+            _ => fail("Invalid worker arguments"),
+        }
+    };
+    match args.first().cloned() {
+        Some("produce") if args.len() == 6 => {
+            let (size, step) = (number(1, STAMP_LEN), number(4, 1));
+            let priorities = args[2].parse::<u32>().ok().filter(|&p| p != 0 )
+                .unwrap_or_else(|| fail("Invalid worker arguments") );
+            let claims = (number(3, 0)..number(5, 0)).step_by(step);
+            let mq = inherited_queue();
+            produce(&mq, size, priorities, claims);
+        }
+        Some("consume") if args.len() == 3 => {
+            let (size, share) = (number(1, STAMP_LEN), number(2, 0));
+            let mq = inherited_queue();
+            let latencies = consume(&mq, size, 0..share);
+            let mut output = Vec::with_capacity(latencies.len() * STAMP_LEN);
+            for latency in latencies {
+                for i in 0..STAMP_LEN {
+                    output.push((latency >> (56 - 8*i)) as u8);
+                }
+            }
+            stdout().write_all(&output).unwrap_or_else(|e| fail_with("writing to stdout", e) );
+        }
+        _ => fail("Invalid worker arguments"),
+    }
+}
+
+fn inherited_queue() -> PosixMq {
+    PosixMq::from_inherited(INHERITED_NAME)
+        .unwrap_or_else(|e| fail_with("getting queue from parent", e) )
+}
+
+/// Nearest-rank percentile of sorted values.
+fn percentile(sorted: &[u64],  p: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = (p * sorted.len() as f64).ceil() as usize;
+    sorted[rank.max(1) - 1]
+}
+
+fn show_nanos(nanos: u64) -> String {
+    if nanos >= 10_000_000 {
+        format!("{}ms", nanos / 1_000_000)
+    } else if nanos >= 10_000 {
+        format!("{}us", nanos / 1_000)
+    } else {
+        format!("{}ns", nanos)
+    }
+}
+
+fn number<N: str::FromStr>(arg: &OsString,  value: &str) -> N where N::Err: fmt::Display {
+    value.parse().unwrap_or_else(|e| fail(&format!("Invalid {}: {}", arg.to_string_lossy(), e)) )
+}
+
+pub fn bench(args: &[OsString]) {
+    let mut config = Config {
+        messages: 100_000,
+        size: 64,
+        capacity: 10,
+        producers: 1,
+        consumers: 1,
+        priorities: 1,
+        nonblocking: false,
+        processes: false,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--nonblocking" {
+            config.nonblocking = true;
+            continue;
+        } else if arg == "--processes" {
+            config.processes = true;
+            continue;
+        }
+        let value = match args.next() {
+            Some(value) => to_str(value, "number"),
+            None => fail(&format!("{} requires a number", arg.to_string_lossy())),
+        };
+        match to_str(arg, "argument") {
+            "--messages" => config.messages = number(arg, value),
+            "--size" => config.size = number(arg, value),
+            "--capacity" => config.capacity = number(arg, value),
+            "--producers" => config.producers = number(arg, value),
+            "--consumers" => config.consumers = number(arg, value),
+            "--priorities" => config.priorities = number(arg, value),
+            option => fail(&format!("Unknown option {}", option)),
+        }
+    }
+    if config.size < STAMP_LEN {
+        fail(&format!("--size must be at least {} to hold the timestamp", STAMP_LEN));
+    } else if config.producers == 0  ||  config.consumers == 0  ||  config.priorities == 0 {
+        fail("--producers, --consumers and --priorities must be at least 1");
+    }
+
+    let name = format!("/posixmq_bench_{}", process::id());
+    let mut opts = OpenOptions::readwrite();
+    opts.capacity(config.capacity).max_msg_len(config.size).create_new();
+    if config.nonblocking {
+        opts.nonblocking();
+    }
+    let mq = opts.open(&name).unwrap_or_else(|e| fail_with("creating temporary queue", e) );
+    let _ = remove_queue(&name);
+
+    let config = Arc::new(config);
+    let start = Instant::now();
+    let mut latencies = if config.processes {
+        run_processes(&mq, &config)
+    } else {
+        run_threads(&mq, &config)
+    };
+    let elapsed = start.elapsed();
+    latencies.sort();
+
+    let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+    let secs = secs.max(1e-9); // avoid dividing by zero
+    println!("messages: {} of {} bytes, capacity {}, {} producers, {} consumers, {} priorities, {}, {}",
+        config.messages, config.size, config.capacity, config.producers, config.consumers,
+        config.priorities, if config.nonblocking {"nonblocking"} else {"blocking"},
+        if config.processes {"processes"} else {"threads"},
+    );
+    println!("elapsed: {:.3}s", secs);
+    println!("throughput: {:.0} msgs/s, {:.0} bytes/s",
+        config.messages as f64 / secs,
+        (config.messages * config.size) as f64 / secs,
+    );
+    println!("latency: p50 {}, p99 {}, p999 {}, max {}",
+        show_nanos(percentile(&latencies, 0.5)),
+        show_nanos(percentile(&latencies, 0.99)),
+        show_nanos(percentile(&latencies, 0.999)),
+        show_nanos(latencies.last().cloned().unwrap_or(0)),
+    );
+}
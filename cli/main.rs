@@ -0,0 +1,257 @@
+/* Copyright 2019, 2020 Torbjørn Birch Moltu
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! A command line program for interacting with posix message queues.
+//!
+//! A port of tests/mq.c which uses this crate instead of calling the C
+//! functions directly. Build with `cargo build --features cli`.
+
+#![allow(clippy::manual_strip)] // MSRV, strip_suffix() requires 1.45
+
+extern crate libc;
+extern crate posixmq;
+
+mod bench;
+mod send;
+mod tail;
+mod watch;
+
+use std::env::args_os;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, stdout, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::process::exit;
+use std::time::Duration;
+
+use posixmq::{OpenOptions, PosixMq};
+
+const USAGE: &str = "\
+posixmq - work with POSIX message queues
+Usage:
+\tposixmq ls : list all existing queues
+\t\t(uses /dev/mqueue/)
+\tposixmq rm /mqname... : delete queues
+\tposixmq stat (/mqname openmode)... : show attributes of queues
+\tposixmq read /mqname openmode [timeout] : receive one message
+\t\tprints priority before the message content
+\tposixmq write /mqname openmode priority message [timeout] : send one message
+\tposixmq watch [--interval dur] [--stuck dur] [--once] [/prefix] : show a table of queues
+\t\trefreshed every --interval (1s), marks queues full for --stuck (10s) as stuck
+\tposixmq tail /mqname [--hex|--utf8|--json] [--count n] [--timeout dur] [--nonblocking]
+\t\t: print messages as they arrive, until --count or a timeout
+\tposixmq send /mqname [options] [file...] : send stdin or files as messages
+\t\tstdin split by --newline (default), --nul or --length-prefixed (u32 BE),
+\t\twith --priority n or --tab-priority (priority<TAB>message),
+\t\toptions: --timeout dur --nonblocking --create --create-new --mode perms
+\t\t         --capacity n --max-msg-len n
+\tposixmq bench [options] : measure throughput and latency of a temporary queue
+\t\toptions: --messages n --size bytes --capacity n --producers n --consumers n
+\t\t         --priorities n --nonblocking --processes (instead of threads)
+openmode format: flags[perms][,capacity,size]
+\tflags: r=read, w=write, b or d=read and write, c=create, e=exclusive,
+\t       n=nonblocking, s=close-on-exec (always set)
+\tIf there is only a single number it is used for permissions,
+\tif there are two they are used for capacity and size limit.
+\tExamples: 'd' 'wcn8,1024' 'rce700' 'rce733,10,200'
+timeout format: a number of seconds, or a number followed by s or ms.
+\tExamples: '5' '10s' '200ms'
+aliases: unlink=rm, getattr=stat, receive=read, send=write (with openmode and priority)";
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    exit(1);
+}
+
+/// Print a message to stderr and exit.
+fn fail(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    exit(1);
+}
+
+fn fail_with(action: &str,  error: io::Error) -> ! {
+    fail(&format!("{} failed: {}", action, error));
+}
+
+/// Parse the mq.c open mode syntax.
+fn parse_open_mode(mode: &str) -> Result<OpenOptions, String> {
+    let flags_end = mode.find(|c: char| !"rwbdcens".contains(c) ).unwrap_or(mode.len());
+    let (flags, numbers) = mode.split_at(flags_end);
+    let (read, write) = (flags.contains('r'), flags.contains('w'));
+    let mut opts = if flags.contains('b')  ||  flags.contains('d')  ||  (read && write) {
+        OpenOptions::readwrite()
+    } else if write {
+        OpenOptions::writeonly()
+    } else {
+        OpenOptions::readonly()
+    };
+    opts.mode(0o640);
+    if flags.contains('c') && flags.contains('e') {
+        opts.create_new();
+    } else if flags.contains('c') {
+        opts.create();
+    }
+    if flags.contains('n') {
+        opts.nonblocking();
+    }
+
+    if numbers.is_empty() {
+        return Ok(opts);
+    }
+    let numbers = numbers.split(',').collect::<Vec<_>>();
+    if numbers.len() > 3 {
+        return Err("Too many numbers in open options".to_string());
+    }
+    for number in &numbers {
+        if number.is_empty() {
+            return Err("Empty number in open options".to_string());
+        } else if !number.bytes().all(|b| b.is_ascii_digit() ) {
+            return Err(format!("Invalid open mode {:?}", number));
+        }
+    }
+    if numbers.len() % 2 != 0 {
+        let perms = u32::from_str_radix(numbers[0], 8)
+            .map_err(|_| format!("Invalid permissions {:?}", numbers[0]) )?;
+        opts.mode(perms);
+    }
+    if numbers.len() >= 2 {
+        let capacity = numbers[numbers.len()-2].parse::<usize>()
+            .map_err(|e| format!("Invalid capacity: {}", e) )?;
+        let max_msg_len = numbers[numbers.len()-1].parse::<usize>()
+            .map_err(|e| format!("Invalid size limit: {}", e) )?;
+        opts.capacity(capacity).max_msg_len(max_msg_len);
+    }
+    Ok(opts)
+}
+
+/// Parse a timeout like `5`, `10s` or `200ms`.
+fn parse_timeout(timeout: &str) -> Result<Duration, String> {
+    let invalid = |_| format!("Invalid timeout {:?}", timeout);
+    if timeout.ends_with("ms") {
+        timeout[..timeout.len()-2].parse().map(Duration::from_millis).map_err(invalid)
+    } else if timeout.ends_with('s') {
+        timeout[..timeout.len()-1].parse().map(Duration::from_secs).map_err(invalid)
+    } else {
+        timeout.parse().map(Duration::from_secs).map_err(invalid)
+    }
+}
+
+fn to_str<'a>(arg: &'a OsString,  what: &str) -> &'a str {
+    match arg.to_str() {
+        Some(s) => s,
+        None => fail(&format!("{} is not UTF-8", what)),
+    }
+}
+
+fn open(name: &OsString,  mode: &OsString) -> PosixMq {
+    let opts = parse_open_mode(to_str(mode, "open mode")).unwrap_or_else(|e| fail(&e) );
+    opts.open(name.as_bytes()).unwrap_or_else(|e| fail_with("opening", e) )
+}
+
+fn list() {
+    let dir = fs::read_dir("/dev/mqueue").unwrap_or_else(|e| fail_with("opening /dev/mqueue/", e) );
+    for entry in dir {
+        let entry = entry.unwrap_or_else(|e| fail_with("reading /dev/mqueue/", e) );
+        let mut line = b"/".to_vec();
+        line.extend_from_slice(entry.file_name().as_bytes());
+        line.push(b'\n');
+        let _ = stdout().write_all(&line);
+    }
+}
+
+fn remove(names: &[OsString]) {
+    for name in names {
+        posixmq::remove_queue(name.as_bytes()).unwrap_or_else(|e| fail_with("deleting", e) );
+    }
+}
+
+fn stat(args: &[OsString]) {
+    for pair in args.chunks(2) {
+        let mq = open(&pair[0], &pair[1]);
+        let attrs = mq.attributes().unwrap_or_else(|e| fail_with("getting attributes", e) );
+        println!("capacity: {}", attrs.capacity);
+        println!("max_msg_len: {}", attrs.max_msg_len);
+        println!("current_messages: {}", attrs.current_messages);
+        println!("nonblocking: {}", if attrs.nonblocking {"yes"} else {"no"});
+    }
+}
+
+fn read(name: &OsString,  mode: &OsString,  timeout: Option<&OsString>) {
+    let mq = open(name, mode);
+    let mut buf = vec![0; mq.attributes().map(|attrs| attrs.max_msg_len ).unwrap_or(1024*1024)];
+    let received = match timeout {
+        Some(timeout) => {
+            let timeout = parse_timeout(to_str(timeout, "timeout")).unwrap_or_else(|e| fail(&e) );
+            mq.recv_timeout(&mut buf, timeout)
+        }
+        None => mq.recv(&mut buf),
+    };
+    let (priority, len) = received.unwrap_or_else(|e| fail_with("receiving", e) );
+    let mut output = format!("{:2} ", priority).into_bytes();
+    output.extend_from_slice(&buf[..len]);
+    output.push(b'\n');
+    stdout().write_all(&output).unwrap_or_else(|e| fail_with("writing to stdout", e) );
+}
+
+fn write(name: &OsString,  mode: &OsString,  priority: &OsString,  msg: &OsString,
+        timeout: Option<&OsString>) {
+    let priority = to_str(priority, "priority").parse::<u32>()
+        .unwrap_or_else(|e| fail(&format!("Invalid priority: {}", e)) );
+    let mq = open(name, mode);
+    let sent = match timeout {
+        Some(timeout) => {
+            let timeout = parse_timeout(to_str(timeout, "timeout")).unwrap_or_else(|e| fail(&e) );
+            mq.send_timeout(priority, msg.as_bytes(), timeout)
+        }
+        None => mq.send(priority, msg.as_bytes()),
+    };
+    sent.unwrap_or_else(|e| fail_with("sending", e) );
+}
+
+/// Check whether the arguments to `send` are an open mode and a priority
+/// like for `write`, rather than options and files for streaming.
+fn is_write_syntax(args: &[OsString]) -> bool {
+    let mode = args[1].to_str().unwrap_or("");
+    let priority = args[2].to_str().unwrap_or("");
+    !mode.is_empty()  &&  parse_open_mode(mode).is_ok()  &&  priority.parse::<u32>().is_ok()
+}
+
+fn main() {
+    let args = args_os().skip(1).collect::<Vec<_>>();
+    let command = match args.first() {
+        Some(command) => command.to_str().unwrap_or(""),
+        None => usage(),
+    };
+    let args = &args[1..];
+    match (command, args.len()) {
+        ("ls", 0) => list(),
+        ("rm", n) | ("unlink", n) if n > 0 => remove(args),
+        ("stat", n) | ("getattr", n) if n > 0 && n % 2 == 0 => stat(args),
+        ("read", 2) | ("receive", 2) => read(&args[0], &args[1], None),
+        ("read", 3) | ("receive", 3) => read(&args[0], &args[1], Some(&args[2])),
+        ("write", 4) => write(&args[0], &args[1], &args[2], &args[3], None),
+        ("write", 5) => write(&args[0], &args[1], &args[2], &args[3], Some(&args[4])),
+        // mq.c also accepts send as an alias for write
+        ("send", 4) if is_write_syntax(args) => write(&args[0], &args[1], &args[2], &args[3], None),
+        ("send", 5) if is_write_syntax(args) => {
+            write(&args[0], &args[1], &args[2], &args[3], Some(&args[4]))
+        }
+        ("bench", _) => bench::bench(args),
+        ("bench-worker", _) => bench::worker(args),
+        ("send", _) => send::send(args),
+        ("tail", _) => tail::tail(args),
+        ("watch", _) => watch::watch(args),
+        ("help", _) | ("-h", _) | ("--help", _) => {
+            println!("{}", USAGE);
+        }
+        _ => {
+            eprintln!("unknown operation or wrong number of arguments");
+            usage();
+        }
+    }
+}
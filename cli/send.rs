@@ -0,0 +1,159 @@
+/* Copyright 2019, 2020 Torbjørn Birch Moltu
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! The `send` subcommand: send stdin or files as messages.
+//!
+//! With an open mode and a priority as the second and third argument, `send`
+//! is instead an alias for `write`, like in tests/mq.c.
+
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io::{self, stdin, BufRead, ErrorKind, Read};
+use std::os::unix::ffi::OsStrExt;
+use std::str;
+use std::time::Duration;
+
+use posixmq::{OpenOptions, PosixMq};
+
+use crate::{fail, fail_with, parse_timeout, to_str};
+
+/// How stdin is split into messages.
+#[derive(Clone, Copy, PartialEq)]
+enum Split {
+    Newline,
+    Nul,
+    /// 4-byte big-endian length before each message
+    Length,
+}
+
+/// Read the next message from stdin, without the delimiter.
+fn next_record<R: BufRead>(input: &mut R,  split: Split,  record: &mut Vec<u8>)
+-> io::Result<bool> {
+    record.clear();
+    match split {
+        Split::Newline | Split::Nul => {
+            let delimiter = if split == Split::Newline {b'\n'} else {b'\0'};
+            if input.read_until(delimiter, record)? == 0 {
+                return Ok(false);
+            }
+            if record.last() == Some(&delimiter) {
+                record.pop();
+            }
+        }
+        Split::Length => {
+            let mut len = [0; 4];
+            match input.read(&mut len[..1])? {
+                0 => return Ok(false),
+                _ => input.read_exact(&mut len[1..])?,
+            }
+            let len = (len[0] as usize) << 24 | (len[1] as usize) << 16
+                    | (len[2] as usize) << 8 | len[3] as usize;
+            // don't trust the length for allocating
+            if (&mut *input).take(len as u64).read_to_end(record)? != len {
+                return Err(io::Error::new(ErrorKind::UnexpectedEof, "truncated record"));
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Split `priority<TAB>payload` into its parts.
+fn split_priority(record: &[u8]) -> Result<(u32, &[u8]), String> {
+    let tab = match record.iter().position(|&b| b == b'\t' ) {
+        Some(tab) => tab,
+        None => return Err("Missing tab after priority".to_string()),
+    };
+    str::from_utf8(&record[..tab]).ok()
+        .and_then(|priority| priority.parse::<u32>().ok() )
+        .map(|priority| (priority, &record[tab+1..]) )
+        .ok_or_else(|| format!("Invalid priority {:?}", String::from_utf8_lossy(&record[..tab])) )
+}
+
+fn number<N: str::FromStr>(value: &str,  what: &str) -> N where N::Err: fmt::Display {
+    value.parse().unwrap_or_else(|e| fail(&format!("Invalid {}: {}", what, e)) )
+}
+
+fn send_one(mq: &PosixMq,  priority: u32,  msg: &[u8],  timeout: Option<Duration>) {
+    let result = match timeout {
+        Some(timeout) => mq.send_timeout(priority, msg, timeout),
+        None => mq.send(priority, msg),
+    };
+    result.unwrap_or_else(|e| fail_with("sending", e) );
+}
+
+pub fn send(args: &[OsString]) {
+    let mut name = None;
+    let mut files = Vec::new();
+    let mut split = Split::Newline;
+    let mut priority = 0;
+    let mut tab_priority = false;
+    let mut timeout = None;
+    let mut opts = OpenOptions::writeonly();
+    opts.mode(0o640);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |what| match args.next() {
+            Some(value) => to_str(value, what),
+            None => fail(&format!("{} requires a {}", arg.to_string_lossy(), what)),
+        };
+        match to_str(arg, "argument") {
+            "--newline" => split = Split::Newline,
+            "--nul" => split = Split::Nul,
+            "--length-prefixed" => split = Split::Length,
+            "--tab-priority" => tab_priority = true,
+            "--priority" => priority = number(value("priority"), "priority"),
+            "--timeout" => {
+                timeout = Some(parse_timeout(value("duration")).unwrap_or_else(|e| fail(&e) ));
+            }
+            "--nonblocking" => {opts.nonblocking();}
+            "--create" => {opts.create();}
+            "--create-new" => {opts.create_new();}
+            "--capacity" => {opts.capacity(number(value("capacity"), "capacity"));}
+            "--max-msg-len" => {opts.max_msg_len(number(value("size limit"), "size limit"));}
+            "--mode" => {
+                let mode = value("mode");
+                let mode = u32::from_str_radix(mode, 8)
+                    .unwrap_or_else(|_| fail(&format!("Invalid mode {:?}", mode)) );
+                opts.mode(mode);
+            }
+            option if option.starts_with("--") => fail(&format!("Unknown option {}", option)),
+            _ if name.is_none() => name = Some(arg),
+            _ => files.push(arg),
+        }
+    }
+    let name = name.unwrap_or_else(|| fail("Missing queue name") );
+    let mq = opts.open(name.as_bytes()).unwrap_or_else(|e| fail_with("opening", e) );
+
+    if !files.is_empty() {
+        for file in files {
+            let content = fs::read(file)
+                .unwrap_or_else(|e| fail_with(&format!("reading {}", file.to_string_lossy()), e) );
+            send_one(&mq, priority, &content, timeout);
+        }
+        return;
+    }
+
+    let stdin = stdin();
+    let mut stdin = stdin.lock();
+    let mut record = Vec::new();
+    loop {
+        match next_record(&mut stdin, split, &mut record) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => fail("Truncated message in stdin"),
+            Err(e) => fail_with("reading stdin", e),
+        }
+        if tab_priority {
+            let (priority, msg) = split_priority(&record).unwrap_or_else(|e| fail(&e) );
+            send_one(&mq, priority, msg, timeout);
+        } else {
+            send_one(&mq, priority, &record, timeout);
+        }
+    }
+}
@@ -0,0 +1,178 @@
+/* Copyright 2019, 2020 Torbjørn Birch Moltu
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! The `tail` subcommand: receive messages and render them safely.
+
+use std::ffi::OsString;
+use std::fmt::Write as FmtWrite;
+use std::io::{stdout, ErrorKind, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::str;
+
+use posixmq::OpenOptions;
+
+use crate::{fail, fail_with, parse_timeout, to_str};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Hex,
+    Utf8,
+    Json,
+}
+
+/// Write a `hexdump -C`-style dump of the message.
+fn render_hex(msg: &[u8],  out: &mut String) {
+    for (line, chunk) in msg.chunks(16).enumerate() {
+        let _ = write!(out, "{:08x} ", line*16);
+        for i in 0..16 {
+            if i == 8 {
+                out.push(' ');
+            }
+            match chunk.get(i) {
+                Some(byte) => {let _ = write!(out, " {:02x}", byte);}
+                None => out.push_str("   "),
+            }
+        }
+        out.push_str("  |");
+        for &byte in chunk {
+            out.push(if byte.is_ascii_graphic()  ||  byte == b' ' {byte as char} else {'.'});
+        }
+        out.push_str("|\n");
+    }
+}
+
+/// Write the message as UTF-8 with control characters and invalid bytes escaped.
+fn render_escaped(mut msg: &[u8],  out: &mut String) {
+    loop {
+        let (valid, invalid) = match str::from_utf8(msg) {
+            Ok(valid) => (valid, &[][..]),
+            Err(e) => {
+                let valid = str::from_utf8(&msg[..e.valid_up_to()]).unwrap();
+                let invalid_len = e.error_len().unwrap_or(msg.len()-e.valid_up_to());
+                (valid, &msg[e.valid_up_to()..e.valid_up_to()+invalid_len])
+            }
+        };
+        for c in valid.chars() {
+            match c {
+                '\\' => out.push_str("\\\\"),
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                '\t' => out.push_str("\\t"),
+                c if c.is_control() => {let _ = write!(out, "\\u{{{:x}}}", c as u32);}
+                c => out.push(c),
+            }
+        }
+        for byte in invalid {
+            let _ = write!(out, "\\x{:02x}", byte);
+        }
+        msg = &msg[valid.len()+invalid.len()..];
+        if msg.is_empty() {
+            break;
+        }
+    }
+}
+
+/// Standard base64 with padding.
+fn base64(bytes: &[u8],  out: &mut String) {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    for chunk in bytes.chunks(3) {
+        let n = (chunk[0] as u32) << 16
+              | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+              | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6*i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+}
+
+fn render(format: Format,  priority: u32,  msg: &[u8]) -> String {
+    let mut out = String::new();
+    match format {
+        Format::Hex => {
+            let _ = writeln!(out, "priority {}, {} bytes:", priority, msg.len());
+            render_hex(msg, &mut out);
+        }
+        Format::Utf8 => {
+            let _ = write!(out, "{:2} ", priority);
+            render_escaped(msg, &mut out);
+            out.push('\n');
+        }
+        Format::Json => {
+            let _ = write!(out, "{{\"priority\":{},\"len\":{},\"data_b64\":\"", priority, msg.len());
+            base64(msg, &mut out);
+            out.push_str("\"}\n");
+        }
+    }
+    out
+}
+
+pub fn tail(args: &[OsString]) {
+    let mut name = None;
+    let mut format = Format::Utf8;
+    let mut count = None;
+    let mut timeout = None;
+    let mut nonblocking = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |what| match args.next() {
+            Some(value) => to_str(value, what),
+            None => fail(&format!("{} requires a {}", arg.to_string_lossy(), what)),
+        };
+        match to_str(arg, "argument") {
+            "--hex" => format = Format::Hex,
+            "--utf8" => format = Format::Utf8,
+            "--json" => format = Format::Json,
+            "--nonblocking" => nonblocking = true,
+            "--count" => {
+                let n = value("number").parse::<u64>()
+                    .unwrap_or_else(|e| fail(&format!("Invalid count: {}", e)) );
+                count = Some(n);
+            }
+            "--timeout" => {
+                timeout = Some(parse_timeout(value("duration")).unwrap_or_else(|e| fail(&e) ));
+            }
+            option if option.starts_with("--") => fail(&format!("Unknown option {}", option)),
+            _ if name.is_none() => name = Some(arg),
+            _ => fail("Only one queue can be tailed"),
+        }
+    }
+    let name = name.unwrap_or_else(|| fail("Missing queue name") );
+
+    let mut opts = OpenOptions::readonly();
+    if nonblocking {
+        opts.nonblocking();
+    }
+    let mq = opts.open(name.as_bytes()).unwrap_or_else(|e| fail_with("opening", e) );
+    let mut buf = vec![0; mq.attributes().map(|attrs| attrs.max_msg_len ).unwrap_or(1024*1024)];
+    let stdout = stdout();
+    let mut stdout = stdout.lock();
+    let mut received = 0;
+    while count != Some(received) {
+        let result = match timeout {
+            Some(timeout) => mq.recv_timeout(&mut buf, timeout),
+            None => mq.recv(&mut buf),
+        };
+        let (priority, len) = match result {
+            Ok(received) => received,
+            // drained or nothing arrived in time
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock  ||  e.kind() == ErrorKind::TimedOut => break,
+            Err(e) => fail_with("receiving", e),
+        };
+        let rendered = render(format, priority, &buf[..len]);
+        stdout.write_all(rendered.as_bytes()).unwrap_or_else(|e| fail_with("writing to stdout", e) );
+        if format == Format::Json {
+            // make each line available to a consuming program immediately
+            stdout.flush().unwrap_or_else(|e| fail_with("writing to stdout", e) );
+        }
+        received += 1;
+    }
+}
@@ -0,0 +1,201 @@
+/* Copyright 2019, 2020 Torbjørn Birch Moltu
+ *
+ * Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+ * http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+ * http://opensource.org/licenses/MIT>, at your option. This file may not be
+ * copied, modified, or distributed except according to those terms.
+ */
+
+//! The `watch` subcommand: a periodically refreshed table of all queues.
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{stdout, Write};
+use std::os::unix::fs::MetadataExt;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use posixmq::OpenOptions;
+
+use crate::{fail, fail_with, parse_timeout, to_str};
+
+/// One row in the table.
+struct QueueInfo {
+    name: String,
+    /// `None` if the queue couldn't be opened
+    current_messages: Option<usize>,
+    capacity: Option<usize>,
+    max_msg_len: Option<usize>,
+    /// total size of all messages, as reported by the kernel
+    qsize: u64,
+    notify_pid: u64,
+    uid: u32,
+    mode: u32,
+}
+
+impl QueueInfo {
+    fn is_full(&self) -> bool {
+        match (self.current_messages, self.capacity) {
+            (Some(current), Some(capacity)) => current >= capacity,
+            _ => false,
+        }
+    }
+}
+
+/// Parse the `QSIZE:129 NOTIFY:0 SIGNO:0 NOTIFY_PID:0` line Linux exposes
+/// in /dev/mqueue/.
+fn parse_status(status: &str) -> (u64, u64) {
+    let mut qsize = 0;
+    let mut notify_pid = 0;
+    for field in status.split_whitespace() {
+        let mut parts = field.splitn(2, ':');
+        match (parts.next(), parts.next().and_then(|n| n.parse().ok() )) {
+            (Some("QSIZE"), Some(n)) => qsize = n,
+            (Some("NOTIFY_PID"), Some(n)) => notify_pid = n,
+            _ => {}
+        }
+    }
+    (qsize, notify_pid)
+}
+
+fn collect(prefix: &str) -> Vec<QueueInfo> {
+    let dir = fs::read_dir("/dev/mqueue").unwrap_or_else(|e| fail_with("opening /dev/mqueue/", e) );
+    let mut queues = Vec::new();
+    for entry in dir {
+        let entry = entry.unwrap_or_else(|e| fail_with("reading /dev/mqueue/", e) );
+        let name = format!("/{}", entry.file_name().to_string_lossy());
+        if !name.starts_with(prefix) {
+            continue;
+        }
+        // the queue might have been removed since listing the directory
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        let status = fs::read_to_string(entry.path()).unwrap_or_default();
+        let (qsize, notify_pid) = parse_status(&status);
+        // opening a queue doesn't affect it, but requires read or write permission
+        let attrs = OpenOptions::readonly()
+            .nonblocking()
+            .open(&name)
+            .or_else(|_| OpenOptions::writeonly().nonblocking().open(&name) )
+            .and_then(|mq| mq.attributes() )
+            .ok();
+        queues.push(QueueInfo {
+            name,
+            current_messages: attrs.as_ref().map(|attrs| attrs.current_messages ),
+            capacity: attrs.as_ref().map(|attrs| attrs.capacity ),
+            max_msg_len: attrs.as_ref().map(|attrs| attrs.max_msg_len ),
+            qsize,
+            notify_pid,
+            uid: metadata.uid(),
+            mode: metadata.mode() & 0o7777,
+        });
+    }
+    queues.sort_by(|a, b| a.name.cmp(&b.name) );
+    queues
+}
+
+fn show(n: Option<usize>) -> String {
+    match n {
+        Some(n) => n.to_string(),
+        None => "?".to_string(),
+    }
+}
+
+/// Render the table, using `unchanged_since` to detect stuck queues.
+fn render(queues: &[QueueInfo],  unchanged_since: &HashMap<String, (u64, Instant)>,
+        stuck_after: Duration,  color: bool,
+) -> String {
+    let name_width = queues.iter().map(|q| q.name.len() ).max().unwrap_or(0).max(4);
+    let mut table = format!(
+        "{:<w$} {:>9} {:>10} {:>11} {:>6} {:>4} {:>10} {}\n",
+        "NAME", "MESSAGES", "QSIZE", "MAX_MSG_LEN", "OWNER", "MODE", "NOTIFY_PID", "STATE",
+        w = name_width,
+    );
+    for q in queues {
+        let unchanged_for = match unchanged_since.get(&q.name) {
+            Some(&(_, since)) => since.elapsed(),
+            None => Duration::from_secs(0),
+        };
+        let stuck = q.current_messages != Some(0)  &&  q.qsize != 0  &&  unchanged_for >= stuck_after;
+        let state = match (q.is_full(), stuck) {
+            (true, true) => "full,stuck",
+            (true, false) => "full",
+            (false, true) => "stuck",
+            (false, false) => "",
+        };
+        let messages = format!("{}/{}", show(q.current_messages), show(q.capacity));
+        let row = format!(
+            "{:<w$} {:>9} {:>10} {:>11} {:>6} {:>4o} {:>10} {}",
+            q.name, messages, q.qsize, show(q.max_msg_len), q.uid, q.mode, q.notify_pid, state,
+            w = name_width,
+        );
+        match (color, q.is_full(), stuck) {
+            (true, true, _) => table.push_str(&format!("\x1b[1;31m{}\x1b[0m\n", row)),
+            (true, false, true) => table.push_str(&format!("\x1b[1;33m{}\x1b[0m\n", row)),
+            _ => {
+                table.push_str(&row);
+                table.push('\n');
+            }
+        }
+    }
+    table
+}
+
+pub fn watch(args: &[OsString]) {
+    let mut interval = Duration::from_secs(1);
+    let mut stuck_after = Duration::from_secs(10);
+    let mut once = false;
+    let mut prefix = String::from("/");
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match to_str(arg, "argument") {
+            "--once" => once = true,
+            "--interval" | "--stuck" => {
+                let duration = match args.next() {
+                    Some(duration) => to_str(duration, "duration"),
+                    None => fail(&format!("{} requires a duration", arg.to_string_lossy())),
+                };
+                let duration = parse_timeout(duration).unwrap_or_else(|e| fail(&e) );
+                if arg == "--interval" {
+                    interval = duration;
+                } else {
+                    stuck_after = duration;
+                }
+            }
+            option if option.starts_with("--") => fail(&format!("Unknown option {}", option)),
+            filter if filter.starts_with('/') => prefix = filter.to_string(),
+            filter => prefix = format!("/{}", filter),
+        }
+    }
+
+    // only use colors and clear the screen when writing to a terminal
+    let terminal = unsafe { libc::isatty(1) } == 1;
+    let mut unchanged_since = HashMap::<String, (u64, Instant)>::new();
+    loop {
+        let queues = collect(&prefix);
+        let now = Instant::now();
+        let mut updated = HashMap::with_capacity(queues.len());
+        for q in &queues {
+            let since = match unchanged_since.get(&q.name) {
+                Some(&(qsize, since)) if qsize == q.qsize => since,
+                _ => now,
+            };
+            updated.insert(q.name.clone(), (q.qsize, since));
+        }
+        unchanged_since = updated;
+
+        let mut output = String::new();
+        if terminal && !once {
+            output.push_str("\x1b[H\x1b[2J");
+        }
+        output.push_str(&render(&queues, &unchanged_since, stuck_after, terminal));
+        stdout().write_all(output.as_bytes()).unwrap_or_else(|e| fail_with("writing to stdout", e) );
+        if once {
+            break;
+        }
+        sleep(interval);
+    }
+}
@@ -1,78 +1,54 @@
-//! Receive messages from multiple queues and send them to another,
-//! asynchronously.
+//! Receive messages from multiple queues and send them to one or more others.
+//!
+//! Usage: merge source... -- destination...
+//! If there is no `--`, the last queue is the only destination.
 
 extern crate posixmq;
-extern crate mio_07 as mio;
 
 fn main() {
     use std::env::args;
-    use std::io::ErrorKind;
+    use std::process::exit;
 
-    use mio::{Poll, Events, Interest, Token};
-
-    let mut queues = args().skip(1).collect::<Vec<_>>();
-    let dst = queues.pop().expect("arguments required");
-    
-    // open source queues
-    let mut src = Vec::new();
-    for name in queues {
-        match posixmq::OpenOptions::readonly().nonblocking().open(&name) {
-            Ok(mq) => src.push((mq, name)),
-            Err(e) => panic!("Cannot open {:?} for receiving: {}", name, e),
-        }
-    }
-
-    // open destination queue
-    let mut dst = match posixmq::OpenOptions::writeonly().nonblocking().create().open(&dst) {
-        Ok(mq) => (mq, dst),
-        Err(e) => panic!("Cannot open or create {:?} for sending: {}", dst, e),
+    let args = args().skip(1).collect::<Vec<_>>();
+    let (sources, destinations) = match args.iter().position(|arg| arg == "--" ) {
+        Some(separator) => (&args[..separator], &args[separator+1..]),
+        None if !args.is_empty() => (&args[..args.len()-1], &args[args.len()-1..]),
+        None => (&args[..], &args[..]),
     };
-
-    let mut poll = Poll::new().expect("Cannot create selector");
-    poll.registry()
-        .register(&mut dst.0, Token(0), Interest::WRITABLE)
-        .expect("registering destination failed");
-    for (i, &mut(ref mut src, _)) in src.iter_mut().enumerate() {
-        poll.registry()
-            .register(src, Token(i+1), Interest::READABLE)
-            .expect("registering a source failed");
+    if sources.is_empty()  ||  destinations.is_empty() {
+        eprintln!("Usage: merge source... [--] destination...");
+        exit(2);
     }
 
-    let mut unsent = Vec::<(u32, Box<[u8]>, &str)>::new();
-    let mut buf = [0; 8192];
-    let mut events = Events::with_capacity(1024);
-    loop {
-        poll.poll(&mut events, None).expect("Cannot poll selector");
-
-        // receive all available messages from queues that are ready
-        for event in events.iter() {
-            if event.token() == Token(0) {
-                // dst; will try to send below even without this event
-                continue;
-            }
-
-            let &(ref mq, ref name) = &src[event.token().0-1];
-            loop {
-                match mq.recv(&mut buf) {
-                    Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
-                    Err(e) => panic!("Error receiving from {}: {}", name, e),
-                    Ok((priority, len)) => unsent.push((priority, Box::from(&buf[..len]), name)),
-                }
+    let mut forwarder = match posixmq::Forwarder::new() {
+        Ok(forwarder) => forwarder,
+        Err(e) => {
+            eprintln!("Cannot create forwarder: {}", e);
+            exit(1);
+        }
+    };
+    for name in sources {
+        match posixmq::OpenOptions::readonly().open(name) {
+            Ok(mq) => {forwarder.add_source(mq);}
+            Err(e) => {
+                eprintln!("Cannot open {:?} for receiving: {}", name, e);
+                exit(1);
             }
         }
-
-        // send as many messages as possible
-        while let Some(&(priority, ref msg, ref src)) = unsent.last() {
-            if let Err(e) = dst.0.send(priority, msg) {
-                if e.kind() == ErrorKind::WouldBlock {
-                    break;
-                }
-                panic!("Error sending to {}: {}", dst.1, e);
+    }
+    for name in destinations {
+        match posixmq::OpenOptions::writeonly().create().open(name) {
+            Ok(mq) => {forwarder.add_destination(mq);}
+            Err(e) => {
+                eprintln!("Cannot open or create {:?} for sending: {}", name, e);
+                exit(1);
             }
-            println!("message of priority {} with {} bytes from {} sent to {}", 
-                priority, msg.len(), src, dst.1
-            );
-            let _ = unsent.pop();
         }
     }
+
+    // runs until an error happens, as nothing calls shutdown()
+    if let Err(e) = forwarder.run() {
+        eprintln!("Forwarding failed after {} messages: {}", forwarder.received(), e);
+        exit(1);
+    }
 }
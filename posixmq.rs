@@ -127,6 +127,7 @@
 //! mio `Source` & `Evented` | Yes | Yes | unusable | Yes | No | No | No
 //! `FromRawFd`+`IntoRawFd`+[`try_clone()`](struct.PosixMq.html#method.try_clone) | Yes | No | Yes | Yes | No | No | No
 //! `AsRawFd`+[`set_cloexec()`](struct.PosixMq.html#method.set_cloexec) | Yes | Yes | Yes | Yes | No | No | No
+//! [`Selector`](struct.Selector.html) and other `poll()`-based types | Yes | Yes | Untested | Yes | No | No | No
 //! Tested? | Manually+CI | Manually+CI | Manually | Manually | Manually (on OmniOSce) | Cross-`check`ed on CI | No
 //!
 //! This library will fail to compile if the target OS doesn't have posix
@@ -140,6 +141,8 @@
 //!   These impls are only available on OSes where this is known to be the case,
 //!   to increase the likelyhood that the core features will compile on an
 //!   unknown OS.
+//!   [`send_queue_over()`](fn.send_queue_over.html) and
+//!   [`recv_queue_from()`](fn.recv_queue_from.html) have the same requirement.
 //! * `AsRawFd`+[`set_cloexec()`](struct.PosixMq.html#method.set_cloexec):
 //!   Similar to `FromRawFd` and `IntoRawFd`, but FreeBSD 11+ has [a function](https://svnweb.freebsd.org/base/head/include/mqueue.h?revision=306588&view=markup#l54)
 //!   which lets one get a file descriptor from a `mqd_t`.  
@@ -149,6 +152,12 @@
 //!   and returns `true` on OSes where close-on-exec cannot be disabled or one
 //!   cannot `exec()`. (posix message queue descriptors should have
 //!   close-on-exec set by default).
+//! * [`Selector`](struct.Selector.html) and other `poll()`-based types:
+//!   [`Forwarder`](struct.Forwarder.html), [`WorkerPool`](struct.WorkerPool.html),
+//!   [`BufferedSender`](struct.BufferedSender.html), [`CancelToken`](struct.CancelToken.html)
+//!   and the methods that wait for readiness or take a `CancelToken`
+//!   require `AsRawFd`, and that the descriptor works with epoll on Linux or
+//!   `poll()` on other OSes.
 //! * mio `Source` & `Evented`: The impls require both `AsRawFd`
 //!   and that mio compiles on the OS.
 //!   This does not guarantee that the event notification mechanism used by mio
@@ -157,6 +166,8 @@
 //! On Linux, message queues and their permissions can be viewed in
 //! `/dev/mqueue/`. The kernel *can* be compiled to not support posix message
 //! queues, so it's not guaranteed to always work. (such as on Android)
+//! The `shm` feature adds `ShmMq`, which implements queues in shared memory,
+//! and `OpenOptions::open_any()` which falls back to it.
 //!
 //! On FreeBSD, the kernel module responsible for posix message queues
 //! is not loaded by default; Run `kldload mqueuefs` as root to enable it.
@@ -202,6 +213,8 @@
 //!   [`recv()`](struct.PosixMq.html#method.recv) and the timed equivalents
 //!   tries again when EINTR / `ErrorKind::Interrupted` is returned.
 //!   (Consistent with how std does IO)
+//!   This can be disabled per descriptor with
+//!   [`set_retry_interrupted()`](struct.PosixMq.html#method.set_retry_interrupted).
 //! * `open()` and all other methods which take `AsRef<[u8]>` prepends `'/'` to
 //!   the name if missing.
 //!   (They have to copy the name anyway, to append a terminating `'\0'`)
@@ -227,12 +240,37 @@
 #![allow(clippy::needless_return, clippy::redundant_closure, clippy::needless_lifetimes)] // style
 #![allow(clippy::range_plus_one)] // edge case: I think 1..x+1 is clearer than 1..=x
 #![allow(clippy::cast_lossless)] // improves portability when values are limited by the OS anyway
+#![allow(clippy::legacy_numeric_constants, clippy::io_other_error)] // MSRV
+#![allow(clippy::manual_non_exhaustive)] // MSRV, #[non_exhaustive] requires 1.40
+#![allow(clippy::option_as_ref_deref)] // MSRV, .as_deref() requires 1.40
 // feel free to disable more lints
 
 use std::{io, mem, ptr};
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+use std::any::Any;
+use std::cmp;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BinaryHeap, VecDeque};
+#[cfg(any(target_os="linux", target_os="netbsd", target_os="dragonfly"))]
+use std::env;
 use std::ffi::CStr;
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+use std::fs::File;
 use std::io::ErrorKind;
+use std::io::{Read, Write};
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+use std::io::{Seek, SeekFrom};
 use std::fmt::{self, Debug, Formatter};
+use std::ops::BitOr;
 #[cfg(any(
     target_os="linux", target_os="freebsd",
     target_os="netbsd", target_os="dragonfly",
@@ -240,7 +278,36 @@ use std::fmt::{self, Debug, Formatter};
 use std::os::unix::io::{AsRawFd, RawFd};
 #[cfg(any(target_os="linux", target_os="netbsd", target_os="dragonfly"))]
 use std::os::unix::io::{FromRawFd, IntoRawFd};
-use std::time::{Duration, SystemTime};
+#[cfg(all(feature="io_safety", any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+)))]
+use std::os::unix::io::{AsFd, BorrowedFd};
+#[cfg(all(feature="io_safety", any(target_os="linux", target_os="netbsd", target_os="dragonfly")))]
+use std::os::unix::io::OwnedFd;
+#[cfg(all(feature="io_safety", any(target_os="linux", target_os="netbsd", target_os="dragonfly")))]
+use std::convert::TryFrom;
+#[cfg(any(target_os="linux", target_os="netbsd", target_os="dragonfly"))]
+use std::os::unix::net::UnixStream;
+#[cfg(any(target_os="linux", target_os="netbsd", target_os="dragonfly"))]
+use std::os::unix::process::CommandExt;
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+use std::panic::{self, AssertUnwindSafe};
+#[cfg(any(target_os="linux", target_os="netbsd", target_os="dragonfly"))]
+use std::process::Command;
+use std::str;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, atomic::{self, AtomicBool, AtomicUsize}};
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+use std::thread::{self, JoinHandle};
+#[cfg(all(feature="shm", target_os="linux"))]
+use std::sync::atomic::{AtomicU32, AtomicU64};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 extern crate libc;
 use libc::{c_int, c_uint, c_char};
@@ -252,11 +319,30 @@ use libc::{timespec, time_t, mq_timedsend, mq_timedreceive};
 #[cfg(target_os="freebsd")]
 use libc::mq_getfd_np;
 use libc::{mode_t, O_ACCMODE, O_RDONLY, O_WRONLY, O_RDWR, O_CREAT, O_EXCL, O_NONBLOCK};
+use libc::{EAGAIN, EBADF, EINTR, EINVAL, EMSGSIZE, ETIMEDOUT};
 #[cfg(any(
     target_os="linux", target_os="freebsd",
     target_os="netbsd", target_os="dragonfly",
 ))]
 use libc::{fcntl, F_GETFD, FD_CLOEXEC, ioctl, FIOCLEX, FIONCLEX};
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+use libc::{fstat, fchmod, fchown};
+#[cfg(any(target_os="freebsd", target_os="netbsd", target_os="dragonfly"))]
+use libc::F_SETFL;
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+use libc::{c_short, c_void, pollfd, poll, nfds_t, POLLIN, POLLOUT};
+#[cfg(any(target_os="freebsd", target_os="netbsd", target_os="dragonfly"))]
+use libc::{POLLERR, POLLHUP, POLLNVAL};
+#[cfg(target_os="linux")]
+use libc::{epoll_create1, epoll_ctl, epoll_wait, epoll_event, EPOLL_CLOEXEC};
+#[cfg(target_os="linux")]
+use libc::{EPOLL_CTL_ADD, EPOLL_CTL_MOD, EPOLL_CTL_DEL, EPOLLIN, EPOLLOUT, EPOLLERR, EPOLLHUP};
 #[cfg(any(target_os="linux", target_os="netbsd", target_os="dragonfly"))]
 use libc::F_DUPFD_CLOEXEC;
 
@@ -303,6 +389,7 @@ fn with_name_as_cstr<F: FnOnce(&CStr)->Result<R,io::Error>, R>(mut name: &[u8],
 pub struct OpenOptions {
     flags: c_int,
     mode: mode_t,
+    ignore_umask: bool,
     capacity: usize,
     max_msg_len: usize,
 }
@@ -321,6 +408,7 @@ impl Debug for OpenOptions {
             .field("create", &(self.flags & O_CREAT != 0))
             .field("open", &(self.flags & O_EXCL == 0))
             .field("mode", &format_args!("{:03o}", self.mode))
+            .field("ignore_umask", &self.ignore_umask)
             .field("capacity", &self.capacity)
             .field("max_msg_len", &self.max_msg_len)
             .field("nonblocking", &((self.flags & O_NONBLOCK) != 0))
@@ -334,6 +422,7 @@ impl OpenOptions {
             flags,
             // default permissions to only accessible for owner
             mode: 0o600,
+            ignore_umask: false,
             capacity: 0,
             max_msg_len: 0,
         }
@@ -358,6 +447,7 @@ impl OpenOptions {
     ///
     /// Some bits might be cleared by the process's umask when creating the
     /// queue, and unknown bits are ignored.
+    /// Use [`ignore_umask()`](#method.ignore_umask) to get exactly this mode.
     ///
     /// This field is ignored if the queue already exists or should not be created.
     /// If this method is not called, queues are created with mode 600.
@@ -369,6 +459,23 @@ impl OpenOptions {
         return self;
     }
 
+    /// Set the permissions of a created queue to exactly [`mode`](#method.mode)
+    /// after creating it, instead of letting the umask clear some bits.
+    ///
+    /// Queues that already exist are not changed: With
+    /// [`create()`](#method.create) the queue is first attempted created
+    /// exclusively, and opened normally if that fails because it exists.
+    ///
+    /// This method is not available on Illumos, Solaris or VxWorks.
+    #[cfg(any(
+        target_os="linux", target_os="freebsd",
+        target_os="netbsd", target_os="dragonfly",
+    ))]
+    pub fn ignore_umask(&mut self) -> &mut Self {
+        self.ignore_umask = true;
+        return self;
+    }
+
     /// Set the maximum size of each message.
     ///
     /// `recv()` will fail if given a buffer smaller than this value.
@@ -445,7 +552,7 @@ impl OpenOptions {
     /// * Possibly other
     pub fn open<N: AsRef<[u8]> + ?Sized>(&self,  name: &N) -> Result<PosixMq, io::Error> {
         pub fn open_slice(opts: &OpenOptions,  name: &[u8]) -> Result<PosixMq, io::Error> {
-            with_name_as_cstr(name, |name| opts.open_c(&name) )
+            with_name_as_cstr(name, |name| opts.open_c(name) )
         }
         open_slice(self, name.as_ref())
     }
@@ -469,7 +576,44 @@ impl OpenOptions {
     /// * Name is too long (ENAMETOOLONG) => `ErrorKind::Other`
     /// * Unlikely (ENFILE, EMFILE, ENOMEM, ENOSPC) => `ErrorKind::Other`
     /// * Possibly other
+    #[allow(clippy::unnecessary_cast)] // mode_t is u16 on FreeBSD
     pub fn open_c(&self,  name: &CStr) -> Result<PosixMq, io::Error> {
+        #[cfg(any(
+            target_os="linux", target_os="freebsd",
+            target_os="netbsd", target_os="dragonfly",
+        ))]
+        {
+            if self.ignore_umask  &&  self.flags & O_CREAT != 0 {
+                let set_permissions = |mq: PosixMq| match mq.set_permissions(self.mode as u32) {
+                    Ok(()) => Ok(mq),
+                    Err(e) => {
+                        // don't leave the queue with the wrong permissions
+                        unsafe { mq_unlink(name.as_ptr()) };
+                        Err(e)
+                    }
+                };
+                if self.flags & O_EXCL != 0 {
+                    return set_permissions(self.open_with_flags(name, self.flags)?);
+                }
+                // only change the permissions if this call created the queue
+                loop {
+                    match self.open_with_flags(name, self.flags | O_EXCL) {
+                        Ok(mq) => return set_permissions(mq),
+                        Err(ref e) if e.kind() == ErrorKind::AlreadyExists => {}
+                        Err(e) => return Err(e),
+                    }
+                    match self.open_with_flags(name, self.flags & !O_CREAT) {
+                        // removed in between, try creating it again
+                        Err(ref e) if e.kind() == ErrorKind::NotFound => {}
+                        result => return result,
+                    }
+                }
+            }
+        }
+        self.open_with_flags(name, self.flags)
+    }
+
+    fn open_with_flags(&self,  name: &CStr,  flags: c_int) -> Result<PosixMq, io::Error> {
         let opts = self;
 
         // because mq_open is a vararg function, mode_t cannot be passed
@@ -485,12 +629,12 @@ impl OpenOptions {
             ptr::null_mut::<mq_attr>()
         };
 
-        let mqd = unsafe { mq_open(name.as_ptr(), opts.flags, permissions, capacities_ptr) };
+        let mqd = unsafe { mq_open(name.as_ptr(), flags, permissions, capacities_ptr) };
         // even when mqd_t is a pointer, -1 is the return value for error
         if mqd == -1isize as mqd_t {
             return Err(io::Error::last_os_error());
         }
-        let mq = PosixMq{mqd};
+        let mq = PosixMq{mqd, retry_interrupted: AtomicBool::new(true)};
 
         // NetBSD and DragonFly BSD doesn't set cloexec by default and
         // ignores O_CLOEXEC. Setting it with FIOCLEX works though.
@@ -522,7 +666,7 @@ impl OpenOptions {
 /// * Possibly other
 pub fn remove_queue<N: AsRef<[u8]> + ?Sized>(name: &N) -> Result<(), io::Error> {
     fn remove_queue_slice(name: &[u8]) -> Result<(), io::Error> {
-        with_name_as_cstr(name, |name| remove_queue_c(&name) )
+        with_name_as_cstr(name, |name| remove_queue_c(name) )
     }
     remove_queue_slice(name.as_ref())
 }
@@ -591,15 +735,80 @@ impl Debug for Attributes {
     }
 }
 
+/// Ownership, permissions and timestamps of a posix message queue.
+///
+/// Created by [`PosixMq::metadata()`](struct.PosixMq.html#method.metadata).
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+#[derive(Clone,Copy, PartialEq,Eq)]
+pub struct Metadata {
+    /// The user ID of the owner.
+    pub uid: u32,
+    /// The group ID of the owning group.
+    pub gid: u32,
+    /// The permission bits, including setuid, setgid and sticky bits.
+    pub mode: u32,
+    /// When a message was last sent or received.
+    pub modified: SystemTime,
+    /// When the permissions, owner or contents last changed.
+    pub changed: SystemTime,
+    _private: ()
+}
+
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+impl Debug for Metadata {
+    fn fmt(&self,  fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.debug_struct("Metadata")
+            .field("uid", &self.uid)
+            .field("gid", &self.gid)
+            .field("mode", &format_args!("{:03o}", self.mode))
+            .field("modified", &self.modified)
+            .field("changed", &self.changed)
+            .finish()
+    }
+}
+
+/// Convert a timestamp from `struct stat` to `SystemTime`.
+///
+/// The types of the fields vary between operating systems, so take the
+/// widest.
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+fn stat_time(secs: i64,  nsecs: i64) -> SystemTime {
+    if secs >= 0 {
+        UNIX_EPOCH + Duration::new(secs as u64, nsecs as u32)
+    } else {
+        // nsecs is still positive, and moves towards the epoch
+        UNIX_EPOCH - Duration::new((-secs) as u64, 0) + Duration::new(0, nsecs as u32)
+    }
+}
+
+
+// `u32::to_be_bytes()` and `u32::from_be_bytes()` require Rust 1.32
+fn u32_to_be(n: u32) -> [u8; 4] {
+    [(n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8]
+}
+
+fn u32_from_be(bytes: &[u8]) -> u32 {
+    (bytes[0] as u32) << 24 | (bytes[1] as u32) << 16 | (bytes[2] as u32) << 8 | bytes[3] as u32
+}
+
 
-macro_rules! retry_if_interrupted {($call:expr) => {{
-    loop {// catch EINTR and retry
+macro_rules! retry_if_interrupted {($retry:expr, $call:expr) => {{
+    loop {// catch EINTR and retry unless disabled
         let ret = $call;
         if ret != -1 {
             break ret;
         }
         let err = io::Error::last_os_error();
-        if err.kind() != ErrorKind::Interrupted {
+        if err.kind() != ErrorKind::Interrupted  ||  !$retry {
             return Err(err)
         }
     }
@@ -646,6 +855,14 @@ fn deadline_to_realtime(deadline: SystemTime) -> Result<timespec, timespec> {
     }
 }
 
+/// Get a deadline that has already expired, for making `mq_timedsend()` and
+/// `mq_timedreceive()` return immediately.
+fn expired_realtime() -> timespec {
+    match deadline_to_realtime(SystemTime::UNIX_EPOCH) {
+        Ok(epoch) | Err(epoch) => epoch
+    }
+}
+
 /// Returns an error if timeout is not representable or the produced deadline
 /// overflows.
 fn timeout_to_realtime(timeout: Duration) -> Result<timespec, io::Error> {
@@ -687,7 +904,8 @@ fn timeout_to_realtime(timeout: Duration) -> Result<timespec, io::Error> {
 /// See [the documentation in the crate root](index.html) for examples,
 /// portability notes and OS details.
 pub struct PosixMq {
-    mqd: mqd_t
+    mqd: mqd_t,
+    retry_interrupted: AtomicBool,
 }
 
 impl PosixMq {
@@ -720,10 +938,11 @@ impl PosixMq {
     /// * Message is zero-length and the OS doesn't allow this (EMSGSIZE) => `ErrorKind::Other`
     /// * Priority is too high (EINVAL) => `ErrorKind::InvalidInput`
     /// * Queue is opened in read-only mode (EBADF) => `ErrorKind::Other`
+    /// * Interrupted by a signal and retrying is disabled (EINTR) => `ErrorKind::Interrupted`
     /// * Possibly other => `ErrorKind::Other`
     pub fn send(&self,  priority: u32,  msg: &[u8]) -> Result<(), io::Error> {
         let mptr = msg.as_ptr() as *const c_char;
-        retry_if_interrupted!(unsafe { mq_send(self.mqd, mptr, msg.len(), priority as c_uint) });
+        retry_if_interrupted!(self.is_retrying_interrupted(), unsafe { mq_send(self.mqd, mptr, msg.len(), priority as c_uint) });
         Ok(())
     }
 
@@ -736,11 +955,12 @@ impl PosixMq {
     /// * Queue is empty and opened in nonblocking mode (EAGAIN) => `ErrorKind::WouldBlock`
     /// * The receive buffer is smaller than the queue's maximum message size (EMSGSIZE) => `ErrorKind::Other`
     /// * Queue is opened in write-only mode (EBADF) => `ErrorKind::Other`
+    /// * Interrupted by a signal and retrying is disabled (EINTR) => `ErrorKind::Interrupted`
     /// * Possibly other => `ErrorKind::Other`
     pub fn recv(&self,  msgbuf: &mut [u8]) -> Result<(u32, usize), io::Error> {
         let bptr = msgbuf.as_mut_ptr() as *mut c_char;
         let mut priority = 0 as c_uint;
-        let len = retry_if_interrupted!(
+        let len = retry_if_interrupted!(self.is_retrying_interrupted(),
             unsafe { mq_receive(self.mqd, bptr, msgbuf.len(), &mut priority) }
         );
         // c_uint is unlikely to differ from u32, but even if it's bigger, the
@@ -761,7 +981,7 @@ impl PosixMq {
     fn timedsend(&self,  priority: u32,  msg: &[u8],  deadline: &timespec)
     -> Result<(), io::Error> {
         let mptr = msg.as_ptr() as *const c_char;
-        retry_if_interrupted!(unsafe {
+        retry_if_interrupted!(self.is_retrying_interrupted(), unsafe {
             mq_timedsend(self.mqd, mptr, msg.len(), priority as c_uint, deadline)
         });
         Ok(())
@@ -784,6 +1004,7 @@ impl PosixMq {
     /// * Priority is too high (EINVAL) => `ErrorKind::InvalidInput`
     /// * Queue is full and opened in nonblocking mode (EAGAIN) => `ErrorKind::WouldBlock`
     /// * Queue is opened in write-only mode (EBADF) => `ErrorKind::Other`
+    /// * Interrupted by a signal and retrying is disabled (EINTR) => `ErrorKind::Interrupted`
     /// * Timeout is too long / not representable => `ErrorKind::InvalidInput`
     /// * Possibly other => `ErrorKind::Other`
     pub fn send_timeout(&self,  priority: u32,  msg: &[u8],  timeout: Duration)
@@ -810,6 +1031,7 @@ impl PosixMq {
     /// * Priority is too high (EINVAL) => `ErrorKind::InvalidInput`
     /// * Queue is full and opened in nonblocking mode (EAGAIN) => `ErrorKind::WouldBlock`
     /// * Queue is opened in write-only mode (EBADF) => `ErrorKind::Other`
+    /// * Interrupted by a signal and retrying is disabled (EINTR) => `ErrorKind::Interrupted`
     /// * Possibly other => `ErrorKind::Other`
     pub fn send_deadline(&self,  priority: u32,  msg: &[u8],  deadline: SystemTime)
     -> Result<(), io::Error> {
@@ -823,7 +1045,7 @@ impl PosixMq {
     -> Result<(u32, usize), io::Error> {
         let bptr = msgbuf.as_mut_ptr() as *mut c_char;
         let mut priority: c_uint = 0;
-        let len = retry_if_interrupted!(
+        let len = retry_if_interrupted!(self.is_retrying_interrupted(),
             unsafe { mq_timedreceive(self.mqd, bptr, msgbuf.len(), &mut priority, deadline) }
         );
         Ok((priority as u32, len as usize))
@@ -841,6 +1063,7 @@ impl PosixMq {
     /// * The receive buffer is smaller than the queue's maximum message size (EMSGSIZE) => `ErrorKind::Other`
     /// * Queue is empty and opened in nonblocking mode (EAGAIN) => `ErrorKind::WouldBlock`
     /// * Queue is opened in read-only mode (EBADF) => `ErrorKind::Other`
+    /// * Interrupted by a signal and retrying is disabled (EINTR) => `ErrorKind::Interrupted`
     /// * Timeout is too long / not representable => `ErrorKind::InvalidInput`
     /// * Possibly other => `ErrorKind::Other`
     pub fn recv_timeout(&self,  msgbuf: &mut[u8],  timeout: Duration)
@@ -862,6 +1085,7 @@ impl PosixMq {
     /// * The receive buffer is smaller than the queue's maximum message size (EMSGSIZE) => `ErrorKind::Other`
     /// * Queue is empty and opened in nonblocking mode (EAGAIN) => `ErrorKind::WouldBlock`
     /// * Queue is opened in read-only mode (EBADF) => `ErrorKind::Other`
+    /// * Interrupted by a signal and retrying is disabled (EINTR) => `ErrorKind::Interrupted`
     /// * Possibly other => `ErrorKind::Other`
     pub fn recv_deadline(&self,  msgbuf: &mut[u8],  deadline: SystemTime)
     -> Result<(u32, usize), io::Error> {
@@ -988,6 +1212,226 @@ impl PosixMq {
         Ok(())
     }
 
+    /// Check whether sending, receiving and waiting is retried when
+    /// interrupted by a signal.
+    ///
+    /// This is `true` unless changed with
+    /// [`set_retry_interrupted()`](#method.set_retry_interrupted).
+    pub fn is_retrying_interrupted(&self) -> bool {
+        self.retry_interrupted.load(atomic::Ordering::Relaxed)
+    }
+
+    /// Choose whether sending, receiving and waiting should be retried or
+    /// fail with `ErrorKind::Interrupted` when interrupted by a signal.
+    ///
+    /// By default EINTR is retried, like std does for other IO.
+    /// Disabling this lets a signal handler (for example for SIGTERM or
+    /// SIGALRM) break out of a blocking [`recv()`](#method.recv) or
+    /// [`send()`](#method.send), as long as the handler is installed without
+    /// `SA_RESTART`.
+    ///
+    /// This setting only applies to this `PosixMq`, not to other descriptors
+    /// for the same queue, but is copied by
+    /// [`try_clone()`](#method.try_clone).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mq = posixmq::PosixMq::create("/not_retrying").expect("create queue");
+    /// # posixmq::remove_queue("/not_retrying").unwrap();
+    /// assert!(mq.is_retrying_interrupted());
+    /// mq.set_retry_interrupted(false);
+    /// assert!(!mq.is_retrying_interrupted());
+    /// ```
+    pub fn set_retry_interrupted(&self,  retry: bool) {
+        self.retry_interrupted.store(retry, atomic::Ordering::Relaxed);
+    }
+
+
+    /// Wait until a message can be received without blocking, or the
+    /// timeout expires.
+    ///
+    /// Returns `true` if the queue became readable, and `false` if the
+    /// timeout expired first. A timeout of `None` waits forever.
+    /// The timeout is measured with the monotonic clock.
+    ///
+    /// No message is received, so this makes it possible to do other work
+    /// before calling [`recv()`](#method.recv). Another thread or process
+    /// can receive the message in between though, so the descriptor should
+    /// be in nonblocking mode if `recv()` must not block.
+    ///
+    /// This function is not available on Illumos, Solaris or VxWorks.
+    ///
+    /// # Errors
+    ///
+    /// Waiting should only fail if the process has run out of memory, or with
+    /// `ErrorKind::Interrupted` if interrupted by a signal and retrying is
+    /// disabled.  
+    /// If the descriptor has been closed, `true` is returned and the
+    /// following `recv()` will fail.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::time::Duration;
+    /// let mq = posixmq::OpenOptions::readwrite()
+    ///     .nonblocking()
+    ///     .create_new()
+    ///     .open("/wait_readable")
+    ///     .expect("create queue");
+    /// # posixmq::remove_queue("/wait_readable").unwrap();
+    /// assert!(!mq.wait_readable(Some(Duration::from_millis(1))).unwrap());
+    /// mq.send(0, b"ready").unwrap();
+    /// assert!(mq.wait_readable(None).unwrap());
+    /// ```
+    #[cfg(any(
+        target_os="linux", target_os="freebsd",
+        target_os="netbsd", target_os="dragonfly",
+    ))]
+    pub fn wait_readable(&self,  timeout: Option<Duration>) -> Result<bool, io::Error> {
+        self.wait_for(POLLIN, timeout)
+    }
+
+    /// Wait until a message can be sent without blocking, or the timeout
+    /// expires.
+    ///
+    /// Returns `true` if the queue became writable, and `false` if the
+    /// timeout expired first. A timeout of `None` waits forever.
+    /// The timeout is measured with the monotonic clock.
+    ///
+    /// Another thread or process can fill the queue before
+    /// [`send()`](#method.send) is called, so the descriptor should be in
+    /// nonblocking mode if `send()` must not block.
+    ///
+    /// This function is not available on Illumos, Solaris or VxWorks.
+    ///
+    /// # Errors
+    ///
+    /// Waiting should only fail if the process has run out of memory, or with
+    /// `ErrorKind::Interrupted` if interrupted by a signal and retrying is
+    /// disabled.  
+    /// If the descriptor has been closed, `true` is returned and the
+    /// following `send()` will fail.
+    #[cfg(any(
+        target_os="linux", target_os="freebsd",
+        target_os="netbsd", target_os="dragonfly",
+    ))]
+    pub fn wait_writable(&self,  timeout: Option<Duration>) -> Result<bool, io::Error> {
+        self.wait_for(POLLOUT, timeout)
+    }
+
+    #[cfg(any(
+        target_os="linux", target_os="freebsd",
+        target_os="netbsd", target_os="dragonfly",
+    ))]
+    fn wait_for(&self,  events: c_short,  timeout: Option<Duration>) -> Result<bool, io::Error> {
+        let mut fds = [new_pollfd(self.as_raw_fd(), events)];
+        poll_fds(&mut fds, timeout, self.is_retrying_interrupted()).map(|ready| ready != 0 )
+    }
+
+
+    /// Add a message to the queue, or fail with `ErrorKind::Interrupted` if
+    /// the token is cancelled before there is room for it.
+    ///
+    /// This blocks until the message is sent or the token is cancelled, even
+    /// if the descriptor is in nonblocking mode.  
+    /// If the token is already cancelled when this method is called, no
+    /// message is sent.
+    ///
+    /// This function is not available on Illumos, Solaris or VxWorks.
+    ///
+    /// # Errors
+    ///
+    /// * Token is cancelled => `ErrorKind::Interrupted`
+    /// * Message is too big for the queue (EMSGSIZE) => `ErrorKind::Other`
+    /// * OS doesn't allow empty messages (EMSGSIZE) => `ErrorKind::Other`
+    /// * Priority is too high (EINVAL) => `ErrorKind::InvalidInput`
+    /// * Queue is opened in read-only mode (EBADF) => `ErrorKind::Other`
+    /// * Interrupted by a signal and retrying is disabled (EINTR) => `ErrorKind::Interrupted`
+    /// * Possibly other => `ErrorKind::Other`
+    #[cfg(any(
+        target_os="linux", target_os="freebsd",
+        target_os="netbsd", target_os="dragonfly",
+    ))]
+    pub fn send_cancellable(&self,  priority: u32,  msg: &[u8],  cancel: &CancelToken)
+    -> Result<(), io::Error> {
+        loop {
+            cancel.check()?;
+            // An expired deadline makes this nonblocking without affecting
+            // other descriptors for the same open queue.
+            match self.timedsend(priority, msg, &expired_realtime()) {
+                Err(ref e) if e.kind() == ErrorKind::TimedOut => {}
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {}
+                result => return result,
+            }
+            self.wait_or_cancel(POLLOUT, cancel)?;
+        }
+    }
+
+    /// Take the message with the highest priority from the queue, or fail
+    /// with `ErrorKind::Interrupted` if the token is cancelled before a
+    /// message arrives.
+    ///
+    /// This blocks until a message is received or the token is cancelled,
+    /// even if the descriptor is in nonblocking mode.  
+    /// If the token is already cancelled when this method is called, no
+    /// message is received.
+    ///
+    /// This function is not available on Illumos, Solaris or VxWorks.
+    ///
+    /// # Errors
+    ///
+    /// * Token is cancelled => `ErrorKind::Interrupted`
+    /// * The receive buffer is smaller than the queue's maximum message size (EMSGSIZE) => `ErrorKind::Other`
+    /// * Queue is opened in write-only mode (EBADF) => `ErrorKind::Other`
+    /// * Interrupted by a signal and retrying is disabled (EINTR) => `ErrorKind::Interrupted`
+    /// * Possibly other => `ErrorKind::Other`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::{io::ErrorKind, sync::Arc, thread};
+    /// let mq = posixmq::PosixMq::create("/cancellable").expect("create queue");
+    /// # posixmq::remove_queue("/cancellable").unwrap();
+    /// let token = posixmq::CancelToken::new().expect("create token");
+    /// let canceller = token.clone();
+    /// let receiver = thread::spawn(move|| {
+    ///     let mut buf = vec![0; mq.attributes().unwrap().max_msg_len];
+    ///     mq.recv_cancellable(&mut buf, &token)
+    /// });
+    /// canceller.cancel();
+    /// let error = receiver.join().unwrap().expect_err("cancelled");
+    /// assert_eq!(error.kind(), ErrorKind::Interrupted);
+    /// ```
+    #[cfg(any(
+        target_os="linux", target_os="freebsd",
+        target_os="netbsd", target_os="dragonfly",
+    ))]
+    pub fn recv_cancellable(&self,  msgbuf: &mut [u8],  cancel: &CancelToken)
+    -> Result<(u32, usize), io::Error> {
+        loop {
+            cancel.check()?;
+            match self.timedreceive(msgbuf, &expired_realtime()) {
+                Err(ref e) if e.kind() == ErrorKind::TimedOut => {}
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {}
+                result => return result,
+            }
+            self.wait_or_cancel(POLLIN, cancel)?;
+        }
+    }
+
+    #[cfg(any(
+        target_os="linux", target_os="freebsd",
+        target_os="netbsd", target_os="dragonfly",
+    ))]
+    fn wait_or_cancel(&self,  events: c_short,  cancel: &CancelToken) -> Result<(), io::Error> {
+        let mut fds = [
+            new_pollfd(self.as_raw_fd(), events),
+            new_pollfd(cancel.as_raw_fd(), POLLIN),
+        ];
+        poll_fds(&mut fds, None, self.is_retrying_interrupted()).map(|_| () )
+    }
+
 
     /// Create a new descriptor for the same message queue.
     ///
@@ -998,7 +1442,7 @@ impl PosixMq {
     pub fn try_clone(&self) -> Result<Self, io::Error> {
         let mq = match unsafe { fcntl(self.mqd, F_DUPFD_CLOEXEC, 0) } {
             -1 => return Err(io::Error::last_os_error()),
-            fd => PosixMq{mqd: fd},
+            fd => PosixMq{mqd: fd, retry_interrupted: AtomicBool::new(self.is_retrying_interrupted())},
         };
         // NetBSD ignores the cloexec part of F_DUPFD_CLOEXEC
         // (but DragonFly BSD respects it here)
@@ -1075,39 +1519,182 @@ impl PosixMq {
         }
     }
 
+    /// Get the owner, permissions and timestamps of the queue, with `fstat()`.
+    ///
+    /// This function is not available on Illumos, Solaris or VxWorks.
+    ///
+    /// # Errors
+    ///
+    /// This function should only fail if the underlying file descriptor has
+    /// been closed (due to incorrect usage of `from_raw_fd()` or similar),
+    /// and not reused for something else yet.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mq = posixmq::OpenOptions::readwrite()
+    ///     .mode(0o640)
+    ///     .ignore_umask()
+    ///     .create_new()
+    ///     .open("/metadata")
+    ///     .unwrap();
+    /// # posixmq::remove_queue("/metadata").unwrap();
+    /// let metadata = mq.metadata().unwrap();
+    /// assert_eq!(metadata.mode, 0o640);
+    /// assert_eq!(metadata.uid, unsafe { libc::geteuid() });
+    /// ```
+    #[cfg(any(
+        target_os="linux", target_os="freebsd",
+        target_os="netbsd", target_os="dragonfly",
+    ))]
+    #[allow(clippy::unnecessary_cast)] // the types of the stat fields differ between OSes
+    pub fn metadata(&self) -> Result<Metadata, io::Error> {
+        let mut stat: libc::stat = unsafe { mem::zeroed() };
+        if unsafe { fstat(self.as_raw_fd(), &mut stat) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        #[cfg(not(target_os="netbsd"))]
+        let (mtime_nsec, ctime_nsec) = (stat.st_mtime_nsec, stat.st_ctime_nsec);
+        #[cfg(target_os="netbsd")]
+        let (mtime_nsec, ctime_nsec) = (stat.st_mtimensec, stat.st_ctimensec);
+        Ok(Metadata {
+            uid: stat.st_uid,
+            gid: stat.st_gid,
+            mode: (stat.st_mode & 0o7777) as u32,
+            modified: stat_time(stat.st_mtime as i64, mtime_nsec as i64),
+            changed: stat_time(stat.st_ctime as i64, ctime_nsec as i64),
+            _private: ()
+        })
+    }
 
-    /// Create a `PosixMq` from an already opened message queue descriptor.
+    /// Change the permissions of the queue, with `fchmod()`.
     ///
-    /// This function should only be used for ffi or if calling `mq_open()`
-    /// directly for some reason.  
-    /// Use [`from_raw_fd()`](#method.from_raw_fd) instead if the surrounding
-    /// code requires `mqd_t` to be a file descriptor.
+    /// Unlike [`OpenOptions::mode()`](struct.OpenOptions.html#method.mode),
+    /// this is not affected by umask.
     ///
-    /// # Safety
+    /// This function is not available on Illumos, Solaris or VxWorks.
     ///
-    /// On some operating systems `mqd_t` is a pointer, which means that the
-    /// safety of most other methods depend on it being correct.
-    pub unsafe fn from_raw_mqd(mqd: mqd_t) -> Self {
-        PosixMq{mqd}
+    /// # Errors
+    ///
+    /// * Not the owner of the queue (EPERM) => `ErrorKind::PermissionDenied`
+    /// * Possibly other => `ErrorKind::Other`
+    #[cfg(any(
+        target_os="linux", target_os="freebsd",
+        target_os="netbsd", target_os="dragonfly",
+    ))]
+    pub fn set_permissions(&self,  mode: u32) -> Result<(), io::Error> {
+        if unsafe { fchmod(self.as_raw_fd(), mode as mode_t) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
     }
 
-    /// Get the raw message queue descriptor.
+    /// Change the owner and/or group of the queue, with `fchown()`.
     ///
-    /// This function should only be used for passing to ffi code or to access
-    /// portable features not exposed by this wrapper (such as calling
-    /// `mq_notify()` or not automatically retrying on EINTR /
-    /// `ErrorKind::Interrupted` when sending or receiving).
+    /// `None` leaves the owner or group unchanged.
     ///
-    /// If you need a file descriptor, use `as_raw_fd()` instead for increased
-    /// portability.
-    /// ([`as_raw_fd()`](#method.as_raw_fd) can sometimes retrieve an
-    /// underlying file descriptor even if `mqd_t` is not an `int`.)
-    pub fn as_raw_mqd(&self) -> mqd_t {
-        self.mqd
+    /// This function is not available on Illumos, Solaris or VxWorks.
+    ///
+    /// # Errors
+    ///
+    /// * Not permitted to change to this owner or group (EPERM) => `ErrorKind::PermissionDenied`
+    /// * Possibly other => `ErrorKind::Other`
+    #[cfg(any(
+        target_os="linux", target_os="freebsd",
+        target_os="netbsd", target_os="dragonfly",
+    ))]
+    pub fn set_owner(&self,  uid: Option<u32>,  gid: Option<u32>) -> Result<(), io::Error> {
+        // -1 means unchanged
+        let uid = uid.unwrap_or(!0);
+        let gid = gid.unwrap_or(!0);
+        if unsafe { fchown(self.as_raw_fd(), uid, gid) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
     }
 
-    /// Convert this wrapper into the raw message queue descriptor without
-    /// closing it.
+    /// Take ownership of a queue passed to this process by a parent that used
+    /// [`InheritQueues`](trait.InheritQueues.html).
+    ///
+    /// The descriptor is checked to be a message queue, and close-on-exec is
+    /// set again so that the queue isn't leaked further.
+    /// The queue is removed from `POSIXMQ_INHERITED`, so getting the same
+    /// name twice fails instead of creating two owners of the descriptor.
+    ///
+    /// This function is only available on Linux, NetBSD and DragonFly BSD.
+    ///
+    /// # Errors
+    ///
+    /// * `POSIXMQ_INHERITED` is not set or doesn't contain `name` => `ErrorKind::NotFound`
+    /// * The descriptor number is invalid or isn't a message queue => `ErrorKind::InvalidData`
+    /// * Setting close-on-exec failed => `ErrorKind::Other`
+    #[cfg(any(target_os="linux", target_os="netbsd", target_os="dragonfly"))]
+    pub fn from_inherited(name: &str) -> Result<Self, io::Error> {
+        let var = match env::var(INHERITED_VAR) {
+            Ok(var) => var,
+            Err(_) => return Err(io::Error::new(ErrorKind::NotFound, "no queues were inherited")),
+        };
+        let mut fd = None;
+        let mut remaining = Vec::new();
+        for pair in var.split(':').filter(|pair| !pair.is_empty() ) {
+            match pair.find('=') {
+                Some(eq) if fd.is_none()  &&  &pair[..eq] == name => fd = Some(&pair[eq+1..]),
+                _ => remaining.push(pair),
+            }
+        }
+        let fd = match fd {
+            Some(fd) => fd,
+            None => return Err(io::Error::new(
+                ErrorKind::NotFound,
+                format!("no queue named {:?} was inherited", name)
+            )),
+        };
+        let fd = match fd.parse::<RawFd>() {
+            Ok(fd) if fd >= 0 => fd,
+            _ => return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid descriptor {:?} for inherited queue {:?}", fd, name)
+            )),
+        };
+        check_queue_fd(fd)?;
+        env::set_var(INHERITED_VAR, remaining.join(":"));
+        let mq = unsafe { PosixMq::from_raw_fd(fd) };
+        mq.set_cloexec(true)?;
+        Ok(mq)
+    }
+
+
+    /// Create a `PosixMq` from an already opened message queue descriptor.
+    ///
+    /// This function should only be used for ffi or if calling `mq_open()`
+    /// directly for some reason.  
+    /// Use [`from_raw_fd()`](#method.from_raw_fd) instead if the surrounding
+    /// code requires `mqd_t` to be a file descriptor.
+    ///
+    /// # Safety
+    ///
+    /// On some operating systems `mqd_t` is a pointer, which means that the
+    /// safety of most other methods depend on it being correct.
+    pub unsafe fn from_raw_mqd(mqd: mqd_t) -> Self {
+        PosixMq{mqd, retry_interrupted: AtomicBool::new(true)}
+    }
+
+    /// Get the raw message queue descriptor.
+    ///
+    /// This function should only be used for passing to ffi code or to access
+    /// portable features not exposed by this wrapper (such as calling
+    /// `mq_notify()`).
+    ///
+    /// If you need a file descriptor, use `as_raw_fd()` instead for increased
+    /// portability.
+    /// ([`as_raw_fd()`](#method.as_raw_fd) can sometimes retrieve an
+    /// underlying file descriptor even if `mqd_t` is not an `int`.)
+    pub fn as_raw_mqd(&self) -> mqd_t {
+        self.mqd
+    }
+
+    /// Convert this wrapper into the raw message queue descriptor without
+    /// closing it.
     ///
     /// This function should only be used for ffi; If you need a file
     /// descriptor use [`into_raw_fd()`](#method.into_raw_fd) instead.
@@ -1157,7 +1744,7 @@ impl AsRawFd for PosixMq {
 #[cfg(any(target_os="linux", target_os="netbsd", target_os="dragonfly"))]
 impl FromRawFd for PosixMq {
     unsafe fn from_raw_fd(fd: RawFd) -> Self {
-        PosixMq{mqd: fd}
+        PosixMq{mqd: fd, retry_interrupted: AtomicBool::new(true)}
     }
 }
 
@@ -1176,6 +1763,49 @@ impl IntoRawFd for PosixMq {
     }
 }
 
+/// Borrow the underlying file descriptor, for use with io-safety APIs.
+///
+/// This impl requires the `io_safety` feature (and Rust 1.63), and is not
+/// available on Illumos, Solaris or VxWorks.
+#[cfg(all(feature="io_safety", any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+)))]
+impl AsFd for PosixMq {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        // the descriptor stays open as long as self is borrowed
+        unsafe { BorrowedFd::borrow_raw(self.as_raw_fd()) }
+    }
+}
+
+/// Convert the `PosixMq` into an owned file descriptor without closing the
+/// message queue.
+///
+/// This impl requires the `io_safety` feature (and Rust 1.63), and is not
+/// available on FreeBSD, Illumos or Solaris.
+#[cfg(all(feature="io_safety", any(target_os="linux", target_os="netbsd", target_os="dragonfly")))]
+impl From<PosixMq> for OwnedFd {
+    fn from(mq: PosixMq) -> OwnedFd {
+        unsafe { OwnedFd::from_raw_fd(mq.into_raw_fd()) }
+    }
+}
+
+/// Take ownership of a file descriptor, after checking that it is a message
+/// queue.
+///
+/// The descriptor is closed if it isn't a message queue, and an error of
+/// type `ErrorKind::InvalidData` is returned.
+///
+/// This impl requires the `io_safety` feature (and Rust 1.63), and is not
+/// available on FreeBSD, Illumos or Solaris.
+#[cfg(all(feature="io_safety", any(target_os="linux", target_os="netbsd", target_os="dragonfly")))]
+impl TryFrom<OwnedFd> for PosixMq {
+    type Error = io::Error;
+    fn try_from(fd: OwnedFd) -> Result<Self, io::Error> {
+        queue_from_owned_fd(fd.into_raw_fd())
+    }
+}
+
 
 impl IntoIterator for PosixMq {
     type Item = (u32, Vec<u8>);
@@ -1333,7 +1963,10 @@ impl Source for PosixMq {
 /// An `Iterator` that calls [`recv()`](struct.PosixMq.html#method.recv) on a borrowed [`PosixMq`](struct.PosixMq.html).
 ///
 /// Iteration ends when a `recv()` fails with an `ErrorKind::WouldBlock` error,
-/// but is infinite if the descriptor is in blocking mode.
+/// but is infinite if the descriptor is in blocking mode.  
+/// Iteration also ends on `ErrorKind::Interrupted`, which `recv()` only
+/// returns if [`set_retry_interrupted(false)`](struct.PosixMq.html#method.set_retry_interrupted)
+/// has been called.
 ///
 /// # Panics
 ///
@@ -1352,6 +1985,7 @@ impl<'a> Iterator for Iter<'a> {
         let mut buf = vec![0; self.max_msg_len];
         match self.mq.recv(&mut buf) {
             Err(ref e) if e.kind() == ErrorKind::WouldBlock => None,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => None,
             Err(e) => panic!("Cannot receive from posix message queue: {}", e),
             Ok((priority, len)) => {
                 buf.truncate(len);
@@ -1365,7 +1999,10 @@ impl<'a> Iterator for Iter<'a> {
 /// messages from an owned [`PosixMq`](struct.PosixMq.html).
 ///
 /// Iteration ends when a `recv()` fails with an `ErrorKind::WouldBlock` error,
-/// but is infinite if the descriptor is in blocking mode.
+/// but is infinite if the descriptor is in blocking mode.  
+/// Iteration also ends on `ErrorKind::Interrupted`, which `recv()` only
+/// returns if [`set_retry_interrupted(false)`](struct.PosixMq.html#method.set_retry_interrupted)
+/// has been called.
 ///
 /// # Panics
 ///
@@ -1384,6 +2021,4662 @@ impl Iterator for IntoIter {
 }
 
 
+/// Counters from a [`DeadLetterRouter`](struct.DeadLetterRouter.html).
+///
+/// Created by [`DeadLetterRouter::stats()`](struct.DeadLetterRouter.html#method.stats).
+#[derive(Clone,Copy, PartialEq,Eq, Default)]
+pub struct DeadLetterStats {
+    /// The number of messages that the handler succeeded with.
+    pub handled: usize,
+    /// The number of times the handler has failed, including failures for
+    /// messages that later succeeded.
+    pub failed_attempts: usize,
+    /// The number of messages that have been sent to the dead-letter queue.
+    pub dead_lettered: usize,
+    _private: ()
+}
+
+impl Debug for DeadLetterStats {
+    fn fmt(&self,  fmtr: &mut fmt::Formatter) -> fmt::Result {
+        fmtr.debug_struct("DeadLetterStats")
+            .field("handled", &self.handled)
+            .field("failed_attempts", &self.failed_attempts)
+            .field("dead_lettered", &self.dead_lettered)
+            .finish()
+    }
+}
+
+/// The attempts and reason length in front of dead-lettered messages.
+const DEAD_LETTER_HEADER_LEN: usize = 4;
+
+/// Retries handling messages, and sends messages that keep failing to a
+/// dead-letter queue.
+///
+/// Posix message queues have no acknowledgement, so a message that a
+/// handler fails on would otherwise be lost.  
+/// After [`max_attempts()`](#method.max_attempts) failures, the original
+/// payload is sent to the dead-letter queue with its original priority,
+/// prefixed by a small header containing the number of attempts and the
+/// last error. [`DeadLetter::parse()`](struct.DeadLetter.html#method.parse)
+/// splits dead-lettered messages back up.
+///
+/// The header is in this format: (integers are big-endian)
+///
+/// * attempts: `u16`
+/// * reason length in bytes: `u16`
+/// * reason: UTF-8
+/// * payload
+///
+/// The reason is truncated if the dead letter would otherwise be longer than
+/// the dead-letter queue's max message length.
+/// The dead-letter queue must therefore have a max message length at least
+/// four bytes longer than the queue the messages are received from, which
+/// [`new()`](#method.new) checks.
+///
+/// The router can be shared between threads, for example in the handler of a
+/// [`WorkerPool`](struct.WorkerPool.html).
+///
+/// # Examples
+///
+/// ```
+/// # use posixmq::{OpenOptions, DeadLetter, DeadLetterRouter};
+/// let mq = OpenOptions::readwrite()
+///     .max_msg_len(100)
+///     .capacity(10)
+///     .create_new()
+///     .open("/orders")
+///     .expect("create queue");
+/// // room for the header and a reason
+/// let dlq = OpenOptions::readwrite()
+///     .max_msg_len(200)
+///     .capacity(10)
+///     .create_new()
+///     .open("/orders_dlq")
+///     .expect("create queue");
+/// # posixmq::remove_queue("/orders").unwrap();
+/// # posixmq::remove_queue("/orders_dlq").unwrap();
+/// let max_msg_len = mq.attributes().unwrap().max_msg_len;
+/// let mut router = DeadLetterRouter::new(dlq, max_msg_len).expect("large enough queue");
+/// router.max_attempts(2);
+///
+/// mq.send(5, b"invalid").unwrap();
+/// let mut buf = vec![0; mq.attributes().unwrap().max_msg_len];
+/// let handled = router.recv_and_handle(&mq, &mut buf, |_priority, msg| {
+///     std::str::from_utf8(msg).unwrap().parse::<u32>().map(|_| () )
+/// }).unwrap();
+/// assert!(!handled);
+/// assert_eq!(router.stats().failed_attempts, 2);
+///
+/// let mut buf = vec![0; router.dead_letter_queue().attributes().unwrap().max_msg_len];
+/// let (priority, len) = router.dead_letter_queue().recv(&mut buf).unwrap();
+/// let dead = DeadLetter::parse(&buf[..len]).expect("valid header");
+/// assert_eq!((priority, dead.attempts, dead.payload), (5, 2, &b"invalid"[..]));
+/// assert_eq!(dead.reason, "invalid digit found in string");
+/// ```
+pub struct DeadLetterRouter {
+    dead_letters: PosixMq,
+    max_msg_len: usize,
+    max_attempts: u16,
+    handled: AtomicUsize,
+    failed_attempts: AtomicUsize,
+    dead_lettered: AtomicUsize,
+    last_failure: Mutex<Option<String>>,
+    failure_reasons: Mutex<BTreeMap<String, usize>>,
+}
+
+impl DeadLetterRouter {
+    /// Create a router that sends failed messages to `dead_letters` after
+    /// three failed attempts.
+    ///
+    /// `source_max_msg_len` is the max message length of the queue (or
+    /// queues) the messages are received from.
+    ///
+    /// # Errors
+    ///
+    /// * The max message length of `dead_letters` is less than four bytes
+    ///   longer than `source_max_msg_len` => `ErrorKind::InvalidInput`
+    /// * The max message length of `dead_letters` cannot be retrieved,
+    ///   see [`PosixMq::attributes()`](struct.PosixMq.html#method.attributes).
+    pub fn new(dead_letters: PosixMq,  source_max_msg_len: usize) -> Result<Self, io::Error> {
+        let max_msg_len = dead_letters.attributes()?.max_msg_len;
+        if max_msg_len < source_max_msg_len.saturating_add(DEAD_LETTER_HEADER_LEN) {
+            return Err(io::Error::new(ErrorKind::InvalidInput,
+                "dead-letter queue cannot fit the header and the longest message"
+            ));
+        }
+        Ok(DeadLetterRouter {
+            max_msg_len,
+            dead_letters,
+            max_attempts: 3,
+            handled: AtomicUsize::new(0),
+            failed_attempts: AtomicUsize::new(0),
+            dead_lettered: AtomicUsize::new(0),
+            last_failure: Mutex::new(None),
+            failure_reasons: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    /// Set how many times the handler is called for a message before the
+    /// message is sent to the dead-letter queue.
+    ///
+    /// Values below 1 are treated as 1.
+    pub fn max_attempts(&mut self,  attempts: u16) -> &mut Self {
+        self.max_attempts = cmp::max(attempts, 1);
+        return self;
+    }
+
+    /// Call the handler with the message until it succeeds or has failed
+    /// [`max_attempts()`](#method.max_attempts) times, and send the message
+    /// to the dead-letter queue if it never succeeded.
+    ///
+    /// Returns `true` if the handler succeeded and `false` if the message was
+    /// dead-lettered.
+    ///
+    /// # Errors
+    ///
+    /// Returns errors from sending to the dead-letter queue, see
+    /// [`PosixMq::send()`](struct.PosixMq.html#method.send).
+    /// The message is lost if this happens.
+    pub fn handle<F, E>(&self,  priority: u32,  msg: &[u8],  mut handler: F)
+    -> Result<bool, io::Error>
+    where F: FnMut(u32, &[u8]) -> Result<(), E>, E: fmt::Display {
+        let mut reason = String::new();
+        for _ in 0..self.max_attempts {
+            match handler(priority, msg) {
+                Ok(()) => {
+                    self.handled.fetch_add(1, atomic::Ordering::Relaxed);
+                    return Ok(true);
+                }
+                Err(e) => {
+                    self.failed_attempts.fetch_add(1, atomic::Ordering::Relaxed);
+                    reason = e.to_string();
+                    let mut reasons = self.failure_reasons.lock().unwrap_or_else(|e| e.into_inner() );
+                    *reasons.entry(reason.clone()).or_insert(0) += 1;
+                }
+            }
+        }
+
+        let header_len = DEAD_LETTER_HEADER_LEN;
+        let max_reason_len = self.max_msg_len.saturating_sub(header_len + msg.len());
+        let mut reason_len = cmp::min(reason.len(), cmp::min(max_reason_len, u16::max_value() as usize));
+        while !reason.is_char_boundary(reason_len) {
+            reason_len -= 1;
+        }
+        let mut dead_letter = Vec::with_capacity(header_len + reason_len + msg.len());
+        dead_letter.push((self.max_attempts >> 8) as u8);
+        dead_letter.push(self.max_attempts as u8);
+        dead_letter.push((reason_len >> 8) as u8);
+        dead_letter.push(reason_len as u8);
+        dead_letter.extend_from_slice(&reason.as_bytes()[..reason_len]);
+        dead_letter.extend_from_slice(msg);
+
+        self.dead_letters.send(priority, &dead_letter)?;
+        self.dead_lettered.fetch_add(1, atomic::Ordering::Relaxed);
+        *self.last_failure.lock().unwrap_or_else(|e| e.into_inner() ) = Some(reason);
+        Ok(false)
+    }
+
+    /// Receive a message from `mq` into `msgbuf` and pass it to
+    /// [`handle()`](#method.handle).
+    ///
+    /// # Errors
+    ///
+    /// Returns errors from [`PosixMq::recv()`](struct.PosixMq.html#method.recv)
+    /// and from sending to the dead-letter queue.
+    pub fn recv_and_handle<F, E>(&self,  mq: &PosixMq,  msgbuf: &mut [u8],  handler: F)
+    -> Result<bool, io::Error>
+    where F: FnMut(u32, &[u8]) -> Result<(), E>, E: fmt::Display {
+        let (priority, len) = mq.recv(msgbuf)?;
+        self.handle(priority, &msgbuf[..len], handler)
+    }
+
+    /// Get the number of handled, failed and dead-lettered messages.
+    pub fn stats(&self) -> DeadLetterStats {
+        DeadLetterStats {
+            handled: self.handled.load(atomic::Ordering::Relaxed),
+            failed_attempts: self.failed_attempts.load(atomic::Ordering::Relaxed),
+            dead_lettered: self.dead_lettered.load(atomic::Ordering::Relaxed),
+            _private: ()
+        }
+    }
+
+    /// Get the reason the most recently dead-lettered message failed with.
+    ///
+    /// This is the handler error's `Display` output, which is not truncated.
+    /// Messages that couldn't be sent to the dead-letter queue don't count.
+    pub fn last_failure(&self) -> Option<String> {
+        self.last_failure.lock().unwrap_or_else(|e| e.into_inner() ).clone()
+    }
+
+    /// Get how many times the handler has failed with each reason, sorted
+    /// by reason.
+    ///
+    /// Like [`stats().failed_attempts`](struct.DeadLetterStats.html#structfield.failed_attempts)
+    /// this counts every failed attempt, including attempts for messages
+    /// that later succeeded.  
+    /// Each distinct `Display` output of the handler's errors is stored, so
+    /// handlers should avoid including unique values such as timestamps in
+    /// their errors.
+    pub fn failure_reasons(&self) -> Vec<(String, usize)> {
+        let reasons = self.failure_reasons.lock().unwrap_or_else(|e| e.into_inner() );
+        reasons.iter().map(|(reason, &count)| (reason.clone(), count) ).collect()
+    }
+
+    /// Get the queue failed messages are sent to.
+    pub fn dead_letter_queue(&self) -> &PosixMq {
+        &self.dead_letters
+    }
+}
+
+impl Debug for DeadLetterRouter {
+    fn fmt(&self,  fmtr: &mut Formatter) -> fmt::Result {
+        fmtr.debug_struct("DeadLetterRouter")
+            .field("dead_letters", &self.dead_letters)
+            .field("max_attempts", &self.max_attempts)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+/// A message from a dead-letter queue, split into header and payload.
+///
+/// See [`DeadLetterRouter`](struct.DeadLetterRouter.html) for the format.
+#[derive(Clone,Copy, PartialEq,Eq)]
+pub struct DeadLetter<'a> {
+    /// How many times the handler failed.
+    pub attempts: u16,
+    /// The error the last attempt failed with, possibly truncated.
+    pub reason: &'a str,
+    /// The original message.
+    pub payload: &'a [u8],
+    _private: ()
+}
+
+impl<'a> DeadLetter<'a> {
+    /// Split a message received from a dead-letter queue.
+    ///
+    /// # Errors
+    ///
+    /// * The message is shorter than the header => `ErrorKind::InvalidData`
+    /// * The reason is not valid UTF-8 => `ErrorKind::InvalidData`
+    pub fn parse(msg: &'a [u8]) -> Result<Self, io::Error> {
+        let invalid = |what| io::Error::new(ErrorKind::InvalidData, what);
+        if msg.len() < 4 {
+            return Err(invalid("dead letter is shorter than its header"));
+        }
+        let attempts = (msg[0] as u16) << 8 | msg[1] as u16;
+        let reason_len = (msg[2] as usize) << 8 | msg[3] as usize;
+        if msg.len() < 4 + reason_len {
+            return Err(invalid("dead letter is shorter than its header"));
+        }
+        let reason = match str::from_utf8(&msg[4..4+reason_len]) {
+            Ok(reason) => reason,
+            Err(_) => return Err(invalid("dead letter reason is not UTF-8")),
+        };
+        Ok(DeadLetter { attempts, reason, payload: &msg[4+reason_len..], _private: () })
+    }
+}
+
+impl<'a> Debug for DeadLetter<'a> {
+    fn fmt(&self,  fmtr: &mut Formatter) -> fmt::Result {
+        fmtr.debug_struct("DeadLetter")
+            .field("attempts", &self.attempts)
+            .field("reason", &self.reason)
+            .field("payload", &self.payload)
+            .finish()
+    }
+}
+
+
+/// Packs multiple small records into each message, to make better use of the
+/// limited capacity of queues.
+///
+/// Each record is prefixed by its length as a big-endian `u32`, and a batch
+/// is sent as one message when the next record wouldn't fit in the queue's
+/// max message length, when it has [`max_records()`](#method.max_records)
+/// records, or when the oldest record has waited for
+/// [`max_delay()`](#method.max_delay).  
+/// All records in a batch have the same priority; Pushing a record with a
+/// different priority than the records already in the batch sends the batch
+/// first.
+///
+/// The delay is only checked when [`push()`](#method.push) or
+/// [`flush_if_due()`](#method.flush_if_due) is called, so a program that
+/// can stop pushing for a while must call the latter periodically, for example
+/// after waiting for [`time_until_due()`](#method.time_until_due).
+///
+/// Use [`unbatch()`](fn.unbatch.html) or [`Unbatcher`](struct.Unbatcher.html)
+/// to split received batches.
+///
+/// Records that haven't been sent when the batcher is dropped are lost.
+///
+/// # Examples
+///
+/// ```
+/// let mq = posixmq::OpenOptions::readwrite()
+///     .capacity(2)
+///     .max_msg_len(100)
+///     .create_new()
+///     .open("/batched")
+///     .expect("create queue");
+/// # posixmq::remove_queue("/batched").unwrap();
+/// let receiver = mq.try_clone().unwrap();
+/// let mut batcher = posixmq::Batcher::new(mq).unwrap();
+/// batcher.max_records(3);
+/// for record in &["one", "two", "three", "four"] {
+///     batcher.push(1, record.as_bytes()).unwrap();
+/// }
+/// batcher.flush().unwrap();
+/// assert_eq!(receiver.attributes().unwrap().current_messages, 2);
+///
+/// receiver.set_nonblocking(true).unwrap();
+/// let records = posixmq::Unbatcher::new(&receiver)
+///     .map(|record| record.unwrap() )
+///     .collect::<Vec<_>>();
+/// assert_eq!(records[2], (1, b"three".to_vec()));
+/// assert_eq!(records.len(), 4);
+/// ```
+pub struct Batcher {
+    mq: PosixMq,
+    max_msg_len: usize,
+    max_records: usize,
+    max_delay: Option<Duration>,
+    batch: Vec<u8>,
+    records: usize,
+    priority: u32,
+    started: Option<Instant>,
+}
+
+impl Batcher {
+    /// Create a batcher which sends batches of up to the queue's max message
+    /// length, and doesn't limit the number of records or the delay.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the max message length of the queue cannot be retrieved,
+    /// see [`PosixMq::attributes()`](struct.PosixMq.html#method.attributes).
+    pub fn new(mq: PosixMq) -> Result<Self, io::Error> {
+        let max_msg_len = mq.attributes()?.max_msg_len;
+        Ok(Batcher {
+            mq,
+            max_msg_len,
+            max_records: usize::max_value(),
+            max_delay: None,
+            batch: Vec::with_capacity(max_msg_len),
+            records: 0,
+            priority: 0,
+            started: None,
+        })
+    }
+
+    /// Send batches when they contain this many records.
+    ///
+    /// Values below 1 are treated as 1.
+    pub fn max_records(&mut self,  records: usize) -> &mut Self {
+        self.max_records = cmp::max(records, 1);
+        return self;
+    }
+
+    /// Send batches when their first record has waited for this long.
+    ///
+    /// `None` (the default) lets records wait until the batch is full.
+    pub fn max_delay(&mut self,  delay: Option<Duration>) -> &mut Self {
+        self.max_delay = delay;
+        return self;
+    }
+
+    /// Add a record to the batch, and send the batch if it's full or due.
+    ///
+    /// If the record doesn't fit in the current batch or has a different
+    /// priority, the current batch is sent first.
+    ///
+    /// # Errors
+    ///
+    /// * The record is too long to fit in a message with its length prefix
+    ///   (EMSGSIZE) => `ErrorKind::Other`
+    /// * Errors from [`PosixMq::send()`](struct.PosixMq.html#method.send)
+    ///
+    /// If sending fails, the record is not added and the unsent batch is kept,
+    /// so `push()` can be called again with the same record.
+    pub fn push(&mut self,  priority: u32,  record: &[u8]) -> Result<(), io::Error> {
+        let framed_len = 4 + record.len();
+        if framed_len > self.max_msg_len  ||  record.len() > u32::max_value() as usize {
+            return Err(io::Error::from_raw_os_error(EMSGSIZE));
+        }
+        if self.records != 0
+        && (self.priority != priority  ||  self.batch.len() + framed_len > self.max_msg_len) {
+            self.flush()?;
+        }
+
+        if self.records == 0 {
+            self.priority = priority;
+            self.started = Some(Instant::now());
+        }
+        self.batch.extend_from_slice(&u32_to_be(record.len() as u32));
+        self.batch.extend_from_slice(record);
+        self.records += 1;
+
+        if self.records >= self.max_records {
+            self.flush()
+        } else {
+            self.flush_if_due().map(|_| () )
+        }
+    }
+
+    /// Send the current batch if it's nonempty.
+    ///
+    /// # Errors
+    ///
+    /// Errors from [`PosixMq::send()`](struct.PosixMq.html#method.send).
+    /// The batch is kept if sending fails.
+    pub fn flush(&mut self) -> Result<(), io::Error> {
+        if self.records != 0 {
+            self.mq.send(self.priority, &self.batch)?;
+            self.batch.clear();
+            self.records = 0;
+            self.started = None;
+        }
+        Ok(())
+    }
+
+    /// Send the current batch if its first record has waited for at least
+    /// [`max_delay()`](#method.max_delay).
+    ///
+    /// Returns whether a batch was sent.
+    ///
+    /// # Errors
+    ///
+    /// The same as for [`flush()`](#method.flush).
+    pub fn flush_if_due(&mut self) -> Result<bool, io::Error> {
+        if self.time_until_due() == Some(Duration::new(0, 0)) {
+            self.flush().map(|()| true )
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Get how long it is until the current batch is due to be sent because
+    /// of [`max_delay()`](#method.max_delay).
+    ///
+    /// Returns `None` if the batch is empty or there is no max delay.
+    pub fn time_until_due(&self) -> Option<Duration> {
+        match (self.started, self.max_delay) {
+            (Some(started), Some(max_delay)) => {
+                let waited = started.elapsed();
+                Some(if waited >= max_delay {Duration::new(0, 0)} else {max_delay - waited})
+            }
+            _ => None,
+        }
+    }
+
+    /// Get the number of records in the current batch.
+    pub fn pending_records(&self) -> usize {
+        self.records
+    }
+
+    /// Get the queue batches are sent to.
+    pub fn get_ref(&self) -> &PosixMq {
+        &self.mq
+    }
+}
+
+impl Debug for Batcher {
+    fn fmt(&self,  fmtr: &mut Formatter) -> fmt::Result {
+        fmtr.debug_struct("Batcher")
+            .field("mq", &self.mq)
+            .field("max_records", &self.max_records)
+            .field("max_delay", &self.max_delay)
+            .field("pending_records", &self.records)
+            .field("pending_bytes", &self.batch.len())
+            .finish()
+    }
+}
+
+/// Split a message sent by a [`Batcher`](struct.Batcher.html) into records.
+pub fn unbatch<'a>(batch: &'a [u8]) -> Records<'a> {
+    Records { remaining: batch }
+}
+
+/// An `Iterator` over the records in a batch.
+///
+/// Created by [`unbatch()`](fn.unbatch.html).
+///
+/// Yields an error of kind `ErrorKind::InvalidData` and ends if a length
+/// prefix is truncated or longer than the rest of the batch.
+#[derive(Clone, Debug)]
+pub struct Records<'a> {
+    remaining: &'a [u8],
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Result<&'a [u8], io::Error>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining.is_empty() {
+            return None;
+        }
+        let remaining = self.remaining;
+        self.remaining = &[];
+        if remaining.len() < 4 {
+            return Some(Err(io::Error::new(ErrorKind::InvalidData, "truncated record length")));
+        }
+        let len = u32_from_be(remaining) as usize;
+        if remaining.len() - 4 < len {
+            return Some(Err(io::Error::new(ErrorKind::InvalidData, "truncated record")));
+        }
+        self.remaining = &remaining[4+len..];
+        Some(Ok(&remaining[4..4+len]))
+    }
+}
+
+/// An `Iterator` that receives batches sent by a
+/// [`Batcher`](struct.Batcher.html) and yields the records in them together
+/// with the priority of the batch.
+///
+/// Like [`Iter`](struct.Iter.html), iteration ends when receiving fails with
+/// `ErrorKind::WouldBlock` (or `ErrorKind::Interrupted`), and is infinite if
+/// the descriptor is in blocking mode.  
+/// Other errors from receiving are yielded once, and then iteration ends, as
+/// they are unlikely to go away by trying again.
+/// Malformed batches are yielded as errors without ending iteration, and the
+/// rest of the batch is skipped.
+pub struct Unbatcher<'a> {
+    mq: &'a PosixMq,
+    buf: Vec<u8>,
+    priority: u32,
+    /// The unread part of `buf`.
+    start: usize,
+    end: usize,
+    /// Set after yielding an error from receiving.
+    failed: bool,
+}
+
+impl<'a> Unbatcher<'a> {
+    /// Create an iterator which receives from `mq`.
+    ///
+    /// The receive buffer is allocated based on the queue's max message
+    /// length. If this cannot be retrieved, the first `recv()` fails and the
+    /// error is yielded.
+    pub fn new(mq: &'a PosixMq) -> Self {
+        let max_msg_len = mq.attributes().map(|attrs| attrs.max_msg_len ).unwrap_or(0);
+        Unbatcher { mq, buf: vec![0; max_msg_len], priority: 0, start: 0, end: 0, failed: false }
+    }
+}
+
+impl<'a> Iterator for Unbatcher<'a> {
+    type Item = Result<(u32, Vec<u8>), io::Error>;
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.start == self.end {
+            if self.failed {
+                return None;
+            }
+            match self.mq.recv(&mut self.buf) {
+                Ok((priority, len)) => {
+                    self.priority = priority;
+                    self.start = 0;
+                    self.end = len;
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return None,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => return None,
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        let mut records = unbatch(&self.buf[self.start..self.end]);
+        match records.next() {
+            Some(Ok(record)) => {
+                self.start = self.end - records.remaining.len();
+                Some(Ok((self.priority, record.to_vec())))
+            }
+            Some(Err(e)) => {
+                self.start = self.end;
+                Some(Err(e))
+            }
+            None => unreachable!("batch is not empty"),
+        }
+    }
+}
+
+impl<'a> Debug for Unbatcher<'a> {
+    fn fmt(&self,  fmtr: &mut Formatter) -> fmt::Result {
+        fmtr.debug_struct("Unbatcher")
+            .field("mq", &self.mq)
+            .field("priority", &self.priority)
+            .field("unread", &(self.end - self.start))
+            .finish()
+    }
+}
+
+
+const DUMP_MAGIC: &[u8; 8] = b"PMQDUMP1";
+/// No OS allows messages longer than this (Linux has the highest limit), so
+/// longer lengths in a dump mean it's corrupt.
+const MAX_DUMPED_MSG_LEN: usize = 16*1024*1024;
+
+/// Receive all messages currently in a queue and write them to `to`.
+///
+/// Returns the number of messages written.
+///
+/// The format starts with the eight bytes `PMQDUMP1`, followed by one record
+/// per message in the order they were received: (integers are big-endian)
+///
+/// * priority: `u32`
+/// * when the message was received by this function, as seconds and
+///   nanoseconds since the Unix epoch: `u64` and `u32`
+///   (The queue doesn't record when messages were sent.)
+/// * message length: `u32`
+/// * the message
+///
+/// Messages are received without blocking, even if the descriptor is in
+/// blocking mode, so messages that other processes send while dumping might
+/// or might not be included. `to` is written to once per message and should
+/// therefore be buffered.
+///
+/// Use [`restore()`](fn.restore.html) to send the messages to a queue again,
+/// or [`DumpReader`](struct.DumpReader.html) to inspect them.
+///
+/// # Errors
+///
+/// * The queue is opened in write-only mode (EBADF) => `ErrorKind::Other`
+/// * Errors from writing
+///
+/// Messages that have been received before an error occurs are lost unless
+/// they were written.
+///
+/// # Examples
+///
+/// ```
+/// let mq = posixmq::PosixMq::create("/dumped").expect("create queue");
+/// # posixmq::remove_queue("/dumped").unwrap();
+/// mq.send(1, b"low").unwrap();
+/// mq.send(5, b"high").unwrap();
+/// let mut dump = Vec::new();
+/// assert_eq!(posixmq::dump(&mq, &mut dump).unwrap(), 2);
+/// assert_eq!(mq.attributes().unwrap().current_messages, 0);
+///
+/// assert_eq!(posixmq::restore(&dump[..], &mq).unwrap(), 2);
+/// let mut buf = vec![0; mq.attributes().unwrap().max_msg_len];
+/// assert_eq!(mq.recv(&mut buf).unwrap(), (5, 4));
+/// ```
+pub fn dump<W: Write>(mq: &PosixMq,  to: W) -> Result<usize, io::Error> {
+    dump_with(mq, to, |_, _| Ok(()) )
+}
+
+/// Write all messages currently in a queue to `to`, and then send them back
+/// to the queue.
+///
+/// The messages are sent back in the order they were received, so the queue
+/// ends up with the messages it started with in the same order, unless other
+/// processes send or receive in the meantime. Sending uses the descriptor's
+/// blocking mode.
+///
+/// See [`dump()`](fn.dump.html) for the format.
+///
+/// # Errors
+///
+/// In addition to the errors from `dump()`, sending can fail. Messages that
+/// haven't been sent back when an error occurs are lost from the queue, but
+/// the messages that were written can be restored from the dump.
+pub fn dump_preserving<W: Write>(mq: &PosixMq,  to: W) -> Result<usize, io::Error> {
+    let mut received = Vec::new();
+    let dumped = dump_with(mq, to, |priority, msg| {
+        received.push((priority, msg.to_vec()));
+        Ok(())
+    });
+    for (priority, msg) in received {
+        mq.send(priority, &msg)?;
+    }
+    dumped
+}
+
+fn dump_with<W, F>(mq: &PosixMq,  mut to: W,  mut also: F) -> Result<usize, io::Error>
+where W: Write, F: FnMut(u32, &[u8]) -> Result<(), io::Error> {
+    let mut buf = vec![0; mq.attributes()?.max_msg_len];
+    to.write_all(DUMP_MAGIC)?;
+    let mut count = 0;
+    loop {
+        let (priority, len) = match mq.timedreceive(&mut buf, &expired_realtime()) {
+            Ok(received) => received,
+            Err(ref e) if e.kind() == ErrorKind::TimedOut => break,
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) => return Err(e),
+        };
+        let msg = &buf[..len];
+        also(priority, msg)?;
+        let received = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(Duration::new(0, 0));
+        let secs = received.as_secs();
+        let mut header = [0; 20];
+        header[..4].copy_from_slice(&u32_to_be(priority));
+        header[4..8].copy_from_slice(&u32_to_be((secs >> 32) as u32));
+        header[8..12].copy_from_slice(&u32_to_be(secs as u32));
+        header[12..16].copy_from_slice(&u32_to_be(received.subsec_nanos()));
+        header[16..].copy_from_slice(&u32_to_be(len as u32));
+        to.write_all(&header)?;
+        to.write_all(msg)?;
+        count += 1;
+    }
+    to.flush()?;
+    Ok(count)
+}
+
+/// Send all messages in a dump created by [`dump()`](fn.dump.html) or
+/// [`dump_preserving()`](fn.dump_preserving.html) to a queue.
+///
+/// Returns the number of messages sent.
+///
+/// The messages are sent in the order they were dumped, which was highest
+/// priority first, so messages with equal priority stay in the same order.
+/// Sending uses the descriptor's blocking mode, so if the queue has a lower
+/// capacity than the number of messages, this function will block until
+/// something receives from the queue, or fail with `ErrorKind::WouldBlock`.
+///
+/// # Errors
+///
+/// * The dump doesn't start with the expected header => `ErrorKind::InvalidData`
+/// * The dump ends in the middle of a message => `ErrorKind::UnexpectedEof`
+/// * Errors from reading
+/// * Errors from [`PosixMq::send()`](struct.PosixMq.html#method.send)
+///
+/// Messages before the one that failed have been sent.
+pub fn restore<R: Read>(from: R,  mq: &PosixMq) -> Result<usize, io::Error> {
+    let mut count = 0;
+    for message in DumpReader::new(from)? {
+        let message = message?;
+        mq.send(message.priority, &message.msg)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// A message read from a dump.
+#[derive(Clone, PartialEq,Eq, Debug)]
+pub struct DumpedMessage {
+    /// The priority the message was sent with.
+    pub priority: u32,
+    /// When the message was dumped.
+    pub received: SystemTime,
+    /// The content of the message.
+    pub msg: Vec<u8>,
+}
+
+/// An `Iterator` over the messages in a dump created by
+/// [`dump()`](fn.dump.html) or [`dump_preserving()`](fn.dump_preserving.html).
+///
+/// Iteration ends after the last message, or after yielding an error.
+pub struct DumpReader<R: Read> {
+    from: R,
+    failed: bool,
+}
+
+impl<R: Read> DumpReader<R> {
+    /// Check the header of the dump and create a reader for the messages.
+    ///
+    /// # Errors
+    ///
+    /// * The dump doesn't start with the expected header => `ErrorKind::InvalidData`
+    /// * Errors from reading
+    pub fn new(mut from: R) -> Result<Self, io::Error> {
+        let mut magic = [0; 8];
+        let invalid = io::Error::new(ErrorKind::InvalidData, "not a posixmq dump");
+        match from.read_exact(&mut magic) {
+            Ok(()) if &magic == DUMP_MAGIC => Ok(DumpReader { from, failed: false }),
+            Ok(()) => Err(invalid),
+            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => Err(invalid),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn read_message(&mut self) -> Result<Option<DumpedMessage>, io::Error> {
+        let mut header = [0; 20];
+        // distinguish between the end of the dump and a truncated header
+        let mut read = 0;
+        while read < header.len() {
+            match self.from.read(&mut header[read..]) {
+                Ok(0) if read == 0 => return Ok(None),
+                Ok(0) => return Err(io::Error::new(ErrorKind::UnexpectedEof, "truncated dump")),
+                Ok(n) => read += n,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let invalid = |what| io::Error::new(ErrorKind::InvalidData, what);
+        let secs = (u32_from_be(&header[4..]) as u64) << 32 | u32_from_be(&header[8..]) as u64;
+        let nanos = u32_from_be(&header[12..]);
+        // SystemTime has the same range as time_t (see deadline_to_realtime()),
+        // and SystemTime::checked_add() requires Rust 1.34.
+        if secs > time_t::max_value() as u64  ||  nanos >= 1_000_000_000 {
+            return Err(invalid("invalid timestamp in dump"));
+        }
+        let received = UNIX_EPOCH + Duration::new(secs, nanos);
+        let len = u32_from_be(&header[16..]) as usize;
+        if len > MAX_DUMPED_MSG_LEN {
+            return Err(invalid("message in dump is too long"));
+        }
+        // don't trust the length for allocating
+        let mut msg = Vec::new();
+        (&mut self.from).take(len as u64).read_to_end(&mut msg)?;
+        if msg.len() != len {
+            return Err(io::Error::new(ErrorKind::UnexpectedEof, "truncated dump"));
+        }
+        Ok(Some(DumpedMessage { priority: u32_from_be(&header), received, msg }))
+    }
+
+    /// Get the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.from
+    }
+}
+
+impl<R: Read> Iterator for DumpReader<R> {
+    type Item = Result<DumpedMessage, io::Error>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        match self.read_message() {
+            Ok(message) => message.map(Ok),
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl<R: Read> Debug for DumpReader<R> {
+    fn fmt(&self,  fmtr: &mut Formatter) -> fmt::Result {
+        fmtr.debug_struct("DumpReader")
+            .field("failed", &self.failed)
+            .finish()
+    }
+}
+
+
+/// The result of [`resize_queue()`](fn.resize_queue.html).
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+pub struct ResizedQueue {
+    /// A read-write descriptor for the new queue.
+    pub queue: PosixMq,
+    /// The number of messages that were moved to the new queue.
+    pub moved: usize,
+    /// Messages that are longer than the new max message length, with their
+    /// priority.
+    pub oversized: Vec<(u32, Vec<u8>)>,
+    /// Messages that didn't fit because the new capacity is lower than the
+    /// number of messages, with their priority.
+    pub overflowed: Vec<(u32, Vec<u8>)>,
+    _private: ()
+}
+
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+impl Debug for ResizedQueue {
+    fn fmt(&self,  fmtr: &mut Formatter) -> fmt::Result {
+        fmtr.debug_struct("ResizedQueue")
+            .field("queue", &self.queue)
+            .field("moved", &self.moved)
+            .field("oversized", &self.oversized.len())
+            .field("overflowed", &self.overflowed.len())
+            .finish()
+    }
+}
+
+/// The error returned by [`resize_queue()`](fn.resize_queue.html), with the
+/// messages that couldn't be put back.
+///
+/// Converts into the `io::Error` it contains, so `?` can be used in functions
+/// returning `io::Error`.
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+pub struct ResizeError {
+    /// What went wrong.
+    pub error: io::Error,
+    /// A read-write descriptor for the queue recreated with the old
+    /// capacities, or `None` if the queue was never removed or couldn't be
+    /// recreated.
+    pub restored: Option<PosixMq>,
+    /// Messages that were received from the old queue but couldn't be sent
+    /// to the restored queue, with their priority.
+    pub unmoved: Vec<(u32, Vec<u8>)>,
+    _private: ()
+}
+
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+impl ResizeError {
+    fn new(error: io::Error) -> Self {
+        ResizeError { error, restored: None, unmoved: Vec::new(), _private: () }
+    }
+
+    /// Get the kind of the underlying `io::Error`.
+    pub fn kind(&self) -> ErrorKind {
+        self.error.kind()
+    }
+}
+
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+impl Debug for ResizeError {
+    fn fmt(&self,  fmtr: &mut Formatter) -> fmt::Result {
+        fmtr.debug_struct("ResizeError")
+            .field("error", &self.error)
+            .field("restored", &self.restored)
+            .field("unmoved", &self.unmoved.len())
+            .finish()
+    }
+}
+
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+impl fmt::Display for ResizeError {
+    fn fmt(&self,  fmtr: &mut Formatter) -> fmt::Result {
+        if self.unmoved.is_empty() {
+            write!(fmtr, "resizing queue failed: {}", self.error)
+        } else {
+            write!(fmtr, "resizing queue failed: {} ({} messages not restored)",
+                self.error, self.unmoved.len()
+            )
+        }
+    }
+}
+
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+impl std::error::Error for ResizeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+impl From<ResizeError> for io::Error {
+    fn from(error: ResizeError) -> io::Error {
+        error.error
+    }
+}
+
+/// Replace a queue with one that has a different capacity and max message
+/// length, and move the messages in it to the new queue.
+///
+/// The capacities cannot be changed after a queue is created, so this
+/// function opens the existing queue, removes it, creates a new queue with
+/// the same name and permissions, and then moves the messages over in order.
+/// Messages that are too long for the new queue or that don't fit are
+/// returned in the [`ResizedQueue`](struct.ResizedQueue.html) instead of
+/// being lost.
+///
+/// A capacity or max message length of zero uses the OS default.  
+/// Processes that have the old queue open will keep using it, and messages
+/// they send after the messages have been moved are lost. Only the
+/// permission bits are copied, not the owner.
+///
+/// This function is not available on Illumos, Solaris or VxWorks.
+///
+/// # Errors
+///
+/// * Errors from [`PosixMq::open()`](struct.PosixMq.html#method.open) and
+///   [`remove_queue()`](fn.remove_queue.html)
+/// * The new capacities are invalid (EINVAL) => `ErrorKind::InvalidInput`
+/// * Getting or setting the permissions fails
+/// * Receiving from the old queue or sending to the new queue fails
+///
+/// If anything fails after the old queue has been removed, the new queue is
+/// removed if it was created, a queue with the old capacities is created
+/// again, and the messages are moved back to it.
+/// Messages that cannot be put back are returned in the
+/// [`ResizeError`](struct.ResizeError.html) instead of being lost.
+///
+/// # Examples
+///
+/// ```
+/// let mq = posixmq::OpenOptions::readwrite()
+///     .capacity(2)
+///     .max_msg_len(20)
+///     .create_new()
+///     .open("/resizable")
+///     .expect("create queue");
+/// mq.send(0, b"short").unwrap();
+/// mq.send(1, b"much longer message").unwrap();
+///
+/// let resized = posixmq::resize_queue("/resizable", 4, 10).expect("resize");
+/// # posixmq::remove_queue("/resizable").unwrap();
+/// assert_eq!(resized.moved, 1);
+/// assert_eq!(resized.oversized, vec![(1, b"much longer message".to_vec())]);
+/// assert_eq!(resized.queue.attributes().unwrap().capacity, 4);
+/// ```
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+pub fn resize_queue<N: AsRef<[u8]> + ?Sized>(name: &N,  capacity: usize,  max_msg_len: usize)
+-> Result<ResizedQueue, ResizeError> {
+    let old = PosixMq::open(name).map_err(ResizeError::new)?;
+    let old_attrs = old.attributes().map_err(ResizeError::new)?;
+    let mode = old.metadata().map_err(ResizeError::new)?.mode;
+    remove_queue(name).map_err(ResizeError::new)?;
+
+    let create = |capacity, max_msg_len| {
+        OpenOptions::readwrite()
+            .capacity(capacity)
+            .max_msg_len(max_msg_len)
+            .mode(mode)
+            .ignore_umask()
+            .create_new()
+            .open(name)
+    };
+    let new = match create(capacity, max_msg_len) {
+        Ok(new) => new,
+        Err(e) => {
+            let restored = create(old_attrs.capacity, old_attrs.max_msg_len);
+            return Err(restore_resized(e, restored, &[&old], Vec::new()));
+        }
+    };
+    let mut resized = ResizedQueue {
+        queue: new,
+        moved: 0,
+        oversized: Vec::new(),
+        overflowed: Vec::new(),
+        _private: ()
+    };
+    match move_messages(&old, &resized.queue, &mut resized.oversized, &mut resized.overflowed) {
+        Ok(moved) => {
+            resized.moved = moved;
+            Ok(resized)
+        }
+        Err(e) => {
+            // The new queue has the name, so it must be removed before the
+            // old capacities can be recreated.
+            let _ = remove_queue(name);
+            let restored = create(old_attrs.capacity, old_attrs.max_msg_len);
+            let mut unmoved = resized.oversized;
+            unmoved.append(&mut resized.overflowed);
+            Err(restore_resized(e, restored, &[&old, &resized.queue], unmoved))
+        }
+    }
+}
+
+/// Move the messages in `sources` and `pending` to the recreated queue after
+/// resizing failed, and collect the messages that don't fit.
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+fn restore_resized(error: io::Error,  restored: Result<PosixMq, io::Error>,
+        sources: &[&PosixMq],  pending: Vec<(u32, Vec<u8>)>,
+) -> ResizeError {
+    let restored = restored.ok();
+    let mut unmoved = Vec::new();
+    if let Some(ref restored) = restored {
+        for (priority, msg) in pending {
+            if restored.timedsend(priority, &msg, &expired_realtime()).is_err() {
+                unmoved.push((priority, msg));
+            }
+        }
+    } else {
+        unmoved = pending;
+    }
+    for source in sources {
+        let moved = match restored {
+            Some(ref restored) => {
+                let (mut oversized, mut overflowed) = (Vec::new(), Vec::new());
+                let moved = move_messages(source, restored, &mut oversized, &mut overflowed);
+                unmoved.append(&mut oversized);
+                unmoved.append(&mut overflowed);
+                moved.is_ok()
+            }
+            None => false,
+        };
+        if !moved {
+            // receive the rest so that they're not lost when the descriptor is closed
+            drain_messages(source, &mut unmoved);
+        }
+    }
+    ResizeError { error, restored, unmoved, _private: () }
+}
+
+/// Receive all messages in a queue without blocking, stopping at the first
+/// error.
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+fn drain_messages(from: &PosixMq,  into: &mut Vec<(u32, Vec<u8>)>) {
+    let max_msg_len = match from.attributes() {
+        Ok(attrs) => attrs.max_msg_len,
+        Err(_) => return,
+    };
+    let mut buf = vec![0; max_msg_len];
+    while let Ok((priority, len)) = from.timedreceive(&mut buf, &expired_realtime()) {
+        into.push((priority, buf[..len].to_vec()));
+    }
+}
+
+/// Move all messages from one queue to another without blocking, and return
+/// the number of messages moved.
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+fn move_messages(from: &PosixMq,  to: &PosixMq,
+        oversized: &mut Vec<(u32, Vec<u8>)>,  overflowed: &mut Vec<(u32, Vec<u8>)>,
+) -> Result<usize, io::Error> {
+    let max_msg_len = to.attributes()?.max_msg_len;
+    let mut buf = vec![0; from.attributes()?.max_msg_len];
+    let mut moved = 0;
+    loop {
+        let (priority, len) = match from.timedreceive(&mut buf, &expired_realtime()) {
+            Ok(received) => received,
+            Err(ref e) if e.kind() == ErrorKind::TimedOut => return Ok(moved),
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(moved),
+            Err(e) => return Err(e),
+        };
+        let msg = &buf[..len];
+        if len > max_msg_len {
+            oversized.push((priority, msg.to_vec()));
+            continue;
+        }
+        match to.timedsend(priority, msg, &expired_realtime()) {
+            Ok(()) => moved += 1,
+            Err(ref e) if e.kind() == ErrorKind::TimedOut
+                      ||  e.kind() == ErrorKind::WouldBlock => overflowed.push((priority, msg.to_vec())),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+
+/// A descriptor which becomes readable when `notify()` is called.
+///
+/// Used to wake up a thread that is waiting in `poll()`.
+/// This is an eventfd on Linux and a pipe on other OSes.
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+#[derive(Debug)]
+struct Notifier {
+    read: RawFd,
+    /// Same as read for eventfds.
+    write: RawFd,
+}
+
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+impl Notifier {
+    #[cfg(target_os="linux")]
+    fn new() -> Result<Self, io::Error> {
+        match unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) } {
+            -1 => Err(io::Error::last_os_error()),
+            fd => Ok(Notifier{read: fd, write: fd}),
+        }
+    }
+
+    #[cfg(not(target_os="linux"))]
+    fn new() -> Result<Self, io::Error> {
+        let mut fds: [RawFd; 2] = [-1, -1];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // create the struct first so that both ends are closed on error
+        let notifier = Notifier{read: fds[0], write: fds[1]};
+        for &fd in &fds {
+            // nonblocking so that notify() cannot block when the pipe is full
+            if unsafe { ioctl(fd, FIOCLEX) } == -1
+            ||  unsafe { fcntl(fd, F_SETFL, O_NONBLOCK) } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(notifier)
+    }
+
+    fn notify(&self) {
+        // If the write fails with EAGAIN the pipe or counter is full and
+        // therefore already readable, and no other errors should be possible.
+        // eventfds require eight bytes.
+        let one: u64 = 1;
+        let _ = unsafe { libc::write(self.write, &one as *const u64 as *const c_void, 8) };
+    }
+}
+
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+impl Drop for Notifier {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.read) };
+        if self.write != self.read {
+            unsafe { libc::close(self.write) };
+        }
+    }
+}
+
+#[derive(Debug)]
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+struct Cancellation {
+    cancelled: AtomicBool,
+    notifier: Notifier,
+}
+
+/// A cloneable handle for cancelling blocking operations from another
+/// thread.
+///
+/// Cancelling is permanent, and affects all clones of the token.
+///
+/// Pass it to [`PosixMq::recv_cancellable()`](struct.PosixMq.html#method.recv_cancellable)
+/// or [`send_cancellable()`](struct.PosixMq.html#method.send_cancellable),
+/// or register it with a [`Selector`](struct.Selector.html) through
+/// `AsRawFd`: It becomes readable once cancelled.
+///
+/// On Linux the token is backed by an eventfd, and on other OSes by a pipe.
+///
+/// This type is not available on Illumos, Solaris or VxWorks.
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+#[derive(Clone, Debug)]
+pub struct CancelToken {
+    inner: Arc<Cancellation>,
+}
+
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+impl CancelToken {
+    /// Create a token that is not cancelled.
+    ///
+    /// # Errors
+    ///
+    /// Creating the underlying descriptor can fail if the process or system
+    /// has too many open file descriptors (EMFILE or ENFILE)
+    /// => `ErrorKind::Other`
+    pub fn new() -> Result<Self, io::Error> {
+        Ok(CancelToken {
+            inner: Arc::new(Cancellation {
+                cancelled: AtomicBool::new(false),
+                notifier: Notifier::new()?,
+            })
+        })
+    }
+
+    /// Cancel ongoing and future operations that use this token or any of
+    /// its clones.
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, atomic::Ordering::SeqCst);
+        self.inner.notifier.notify();
+    }
+
+    /// Check whether [`cancel()`](#method.cancel) has been called on this
+    /// token or any of its clones.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(atomic::Ordering::SeqCst)
+    }
+
+    fn check(&self) -> Result<(), io::Error> {
+        if self.is_cancelled() {
+            Err(io::Error::new(ErrorKind::Interrupted, "operation was cancelled"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Get a descriptor which becomes readable when the token is cancelled.
+///
+/// Reading from the descriptor is not necessary and will not reset the token.
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+impl AsRawFd for CancelToken {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.notifier.read
+    }
+}
+
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+fn new_pollfd(fd: RawFd,  events: c_short) -> pollfd {
+    pollfd { fd, events, revents: 0 }
+}
+
+/// Convert an optional timeout to a monotonic deadline.
+///
+/// A timeout too long to be represented is treated as no timeout.
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+fn timeout_to_monotonic(timeout: Option<Duration>) -> Option<Instant> {
+    timeout.and_then(|timeout| Instant::now().checked_add(timeout) )
+}
+
+/// Get the number of milliseconds until a deadline in the format `poll()`
+/// and `epoll_wait()` expects.
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+fn remaining_ms(deadline: Option<Instant>) -> c_int {
+    match deadline {
+        None => -1,
+        Some(deadline) => {
+            let now = Instant::now();
+            let remaining = if deadline > now {deadline - now} else {Duration::new(0, 0)};
+            // round up so that the call doesn't return before the timeout has expired
+            let mut ms = remaining.as_secs().saturating_mul(1000);
+            ms = ms.saturating_add(remaining.subsec_nanos() as u64 / 1_000_000);
+            if remaining.subsec_nanos() % 1_000_000 != 0 {
+                ms = ms.saturating_add(1);
+            }
+            cmp::min(ms, c_int::max_value() as u64) as c_int
+        }
+    }
+}
+
+/// Wait until at least one of the descriptors is ready or the timeout
+/// expires, and return the number of descriptors with events.
+///
+/// If `retry_interrupted` is set, EINTR is retried with the remaining time,
+/// which is measured with the monotonic clock.
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+fn poll_fds(fds: &mut [pollfd],  timeout: Option<Duration>,  retry_interrupted: bool)
+-> Result<usize, io::Error> {
+    let deadline = timeout_to_monotonic(timeout);
+    loop {
+        let timeout_ms = remaining_ms(deadline);
+        match unsafe { poll(fds.as_mut_ptr(), fds.len() as nfds_t, timeout_ms) } {
+            -1 => {
+                let err = io::Error::last_os_error();
+                if err.kind() != ErrorKind::Interrupted  ||  !retry_interrupted {
+                    return Err(err);
+                }
+            }
+            ready => return Ok(ready as usize),
+        }
+    }
+}
+
+
+/// A received message waiting to be sent to a destination queue.
+struct Pending {
+    priority: u32,
+    /// Keeps messages with equal priority in the order they were received.
+    sequence: u64,
+    msg: Arc<[u8]>,
+}
+
+impl PartialEq for Pending {
+    fn eq(&self,  other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for Pending {}
+impl PartialOrd for Pending {
+    fn partial_cmp(&self,  other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Pending {
+    // BinaryHeap pops the greatest element first, so the highest priority
+    // must be greatest, and among equal priorities the oldest message.
+    fn cmp(&self,  other: &Self) -> Ordering {
+        self.priority.cmp(&other.priority).then(other.sequence.cmp(&self.sequence))
+    }
+}
+
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+struct Destination {
+    mq: PosixMq,
+    pending: BinaryHeap<Pending>,
+}
+
+/// A handle for stopping a [`Forwarder`](struct.Forwarder.html),
+/// possibly from another thread.
+///
+/// Created by [`Forwarder::shutdown_handle()`](struct.Forwarder.html#method.shutdown_handle).
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+#[derive(Clone, Debug)]
+pub struct ShutdownHandle {
+    token: CancelToken,
+}
+
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+impl ShutdownHandle {
+    /// Make [`Forwarder::run()`](struct.Forwarder.html#method.run) return.
+    ///
+    /// If `run()` isn't currently running, it will return as soon as it's
+    /// called. Shutting down is permanent.
+    pub fn shutdown(&self) {
+        self.token.cancel();
+    }
+
+    /// Check whether [`shutdown()`](#method.shutdown) has been called.
+    pub fn is_shutdown(&self) -> bool {
+        self.token.is_cancelled()
+    }
+}
+
+/// Moves messages from one or more source queues to one or more destination
+/// queues.
+///
+/// Every message received from any of the sources is sent to all
+/// destinations, with its original priority.  
+/// Messages that cannot be sent immediately because a destination is full
+/// are kept in a buffer, and are sent highest priority first when the
+/// destination has room for them. When the buffer for any destination
+/// reaches [`max_pending()`](#method.max_pending) messages, the forwarder
+/// stops receiving from the sources until the destination catches up, so
+/// that sources fill up instead.
+///
+/// All queues are put in nonblocking mode when [`run()`](#method.run) starts.
+/// This also affects descriptors created with
+/// [`try_clone()`](struct.PosixMq.html#method.try_clone) from them, so
+/// queues the program uses for other purposes should be opened separately.
+///
+/// This type is not available on Illumos, Solaris or VxWorks.
+///
+/// # Examples
+///
+/// ```
+/// # use std::thread;
+/// let mut opts = posixmq::OpenOptions::readwrite();
+/// opts.capacity(2).max_msg_len(32).create();
+/// let mut forwarder = posixmq::Forwarder::new().expect("create forwarder");
+/// forwarder
+///     .add_source(opts.open("/forward_from").expect("create source"))
+///     .add_destination(opts.open("/forward_to").expect("create destination"));
+/// let shutdown = forwarder.shutdown_handle();
+/// let forwarding = thread::spawn(move|| forwarder.run() );
+///
+/// let src = opts.open("/forward_from").unwrap();
+/// let dst = opts.open("/forward_to").unwrap();
+/// # posixmq::remove_queue("/forward_from").unwrap();
+/// # posixmq::remove_queue("/forward_to").unwrap();
+/// src.send(3, b"hello").unwrap();
+/// let mut buf = [0; 32];
+/// assert_eq!(dst.recv(&mut buf).unwrap(), (3, 5));
+///
+/// shutdown.shutdown();
+/// forwarding.join().unwrap().expect("forwarding failed");
+/// ```
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+pub struct Forwarder {
+    sources: Vec<PosixMq>,
+    destinations: Vec<Destination>,
+    max_pending: usize,
+    received: u64,
+    shutdown: CancelToken,
+}
+
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+impl Forwarder {
+    /// Create a forwarder without any queues.
+    ///
+    /// # Errors
+    ///
+    /// Creating the pipe used for shutting down can fail if the process or
+    /// system has too many open file descriptors (EMFILE or ENFILE)
+    /// => `ErrorKind::Other`
+    pub fn new() -> Result<Self, io::Error> {
+        Ok(Forwarder {
+            sources: Vec::new(),
+            destinations: Vec::new(),
+            max_pending: 64,
+            received: 0,
+            shutdown: CancelToken::new()?,
+        })
+    }
+
+    /// Receive messages from this queue.
+    ///
+    /// The queue must be opened for reading.
+    pub fn add_source(&mut self,  mq: PosixMq) -> &mut Self {
+        self.sources.push(mq);
+        return self;
+    }
+
+    /// Send all received messages to this queue.
+    ///
+    /// The queue must be opened for writing, and should have a
+    /// `max_msg_len` at least as big as the biggest source.
+    pub fn add_destination(&mut self,  mq: PosixMq) -> &mut Self {
+        self.destinations.push(Destination{mq, pending: BinaryHeap::new()});
+        return self;
+    }
+
+    /// Set the maximum number of messages buffered for any destination
+    /// before the forwarder stops receiving.
+    ///
+    /// The default is 64. Zero is treated as one.
+    pub fn max_pending(&mut self,  max_pending: usize) -> &mut Self {
+        self.max_pending = cmp::max(max_pending, 1);
+        return self;
+    }
+
+    /// Get a handle which can be used to make [`run()`](#method.run) return.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle { token: self.shutdown.clone() }
+    }
+
+    /// Get the highest number of messages waiting to be sent to any
+    /// destination.
+    pub fn pending(&self) -> usize {
+        self.destinations.iter().map(|dst| dst.pending.len() ).max().unwrap_or(0)
+    }
+
+    /// Get the number of messages received from the sources so far.
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    /// Forward messages until shut down through a
+    /// [`ShutdownHandle`](struct.ShutdownHandle.html) or an error occurs.
+    ///
+    /// Before returning after a shutdown, pending messages are sent to
+    /// destinations that have room for them. Messages that are still
+    /// pending after that remain in the forwarder, and are dropped with it.
+    ///
+    /// # Errors
+    ///
+    /// Errors are returned instead of being retried, with a description of
+    /// which queue failed added, but the `ErrorKind` is preserved.
+    ///
+    /// * No sources or no destinations => `ErrorKind::InvalidInput`
+    /// * A source is opened write-only (EBADF) => `ErrorKind::Other`
+    /// * A destination is opened read-only (EBADF) => `ErrorKind::Other`
+    /// * A message is too big for a destination (EMSGSIZE) => `ErrorKind::Other`
+    /// * A message has a priority too high for a destination (EINVAL) => `ErrorKind::InvalidInput`
+    /// * Possibly other => `ErrorKind::Other`
+    pub fn run(&mut self) -> Result<(), io::Error> {
+        if self.sources.is_empty()  ||  self.destinations.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "forwarder requires at least one source and one destination"
+            ));
+        }
+
+        let mut max_msg_len = 0;
+        for (i, src) in self.sources.iter().enumerate() {
+            let attrs = src.attributes()
+                .and_then(|attrs| src.set_nonblocking(true).map(|()| attrs ) )
+                .map_err(|e| forwarding_error(e, "configure source", i) )?;
+            max_msg_len = cmp::max(max_msg_len, attrs.max_msg_len);
+        }
+        for (i, dst) in self.destinations.iter().enumerate() {
+            dst.mq.set_nonblocking(true)
+                .map_err(|e| forwarding_error(e, "configure destination", i) )?;
+        }
+
+        let mut buf = vec![0; max_msg_len];
+        let mut fds = Vec::with_capacity(1 + self.sources.len() + self.destinations.len());
+        loop {
+            // send before checking for shutdown so that messages received
+            // in the last round aren't dropped unnecessarily.
+            self.send_pending()?;
+            if self.shutdown.is_cancelled() {
+                return Ok(());
+            }
+
+            let receiving = self.pending() < self.max_pending;
+            fds.clear();
+            fds.push(new_pollfd(self.shutdown.as_raw_fd(), POLLIN));
+            if receiving {
+                fds.extend(self.sources.iter().map(|src| new_pollfd(src.as_raw_fd(), POLLIN) ));
+            }
+            for dst in &self.destinations {
+                if !dst.pending.is_empty() {
+                    fds.push(new_pollfd(dst.mq.as_raw_fd(), POLLOUT));
+                }
+            }
+            poll_fds(&mut fds, None, true)?;
+
+            if receiving {
+                for i in 0..self.sources.len() {
+                    if fds[1+i].revents != 0 {
+                        self.receive_from(i, &mut buf)?;
+                    }
+                }
+            }
+        }
+    }
+
+    fn receive_from(&mut self,  src: usize,  buf: &mut [u8]) -> Result<(), io::Error> {
+        while self.pending() < self.max_pending {
+            match self.sources[src].recv(buf) {
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(forwarding_error(e, "receive from source", src)),
+                Ok((priority, len)) => {
+                    let msg = Arc::<[u8]>::from(&buf[..len]);
+                    self.received += 1;
+                    for dst in &mut self.destinations {
+                        let sequence = self.received;
+                        dst.pending.push(Pending{priority, sequence, msg: msg.clone()});
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn send_pending(&mut self) -> Result<(), io::Error> {
+        for (i, dst) in self.destinations.iter_mut().enumerate() {
+            while let Some(next) = dst.pending.peek() {
+                match dst.mq.send(next.priority, &next.msg) {
+                    Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(e) => return Err(forwarding_error(e, "send to destination", i)),
+                    Ok(()) => {}
+                }
+                let _ = dst.pending.pop();
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+fn forwarding_error(error: io::Error,  action: &str,  queue: usize) -> io::Error {
+    io::Error::new(error.kind(), format!("cannot {} {}: {}", action, queue, error))
+}
+
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+impl Debug for Forwarder {
+    fn fmt(&self,  fmtr: &mut Formatter) -> fmt::Result {
+        fmtr.debug_struct("Forwarder")
+            .field("sources", &self.sources)
+            .field("destinations", &self.destinations.iter().map(|dst| &dst.mq ).collect::<Vec<_>>())
+            .field("max_pending", &self.max_pending)
+            .field("pending", &self.pending())
+            .field("received", &self.received)
+            .finish()
+    }
+}
+
+
+/// Storage for messages that don't fit in the memory buffer of a
+/// `BufferedSender`.
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+#[derive(Debug)]
+struct Spill {
+    file: File,
+    read_pos: u64,
+    write_pos: u64,
+    messages: usize,
+}
+
+/// Sends messages to a queue, and keeps the messages that don't fit in the
+/// queue until there is room for them.
+///
+/// Messages are never blocked on; When the queue is full they are stored in
+/// a buffer, and are sent highest priority first (and in order among equal
+/// priorities) by later calls to [`send()`](#method.send),
+/// [`send_buffered()`](#method.send_buffered) or [`flush()`](#method.flush).
+///
+/// The in-memory buffer is limited to a number of bytes. Sending fails with
+/// `ErrorKind::WouldBlock` when it is full, unless a
+/// [spill file](#method.spill_to) has been set, in which case messages are
+/// appended to the file instead. Spilled messages are moved back into memory
+/// in the order they were spilled as the buffer empties, so priorities are
+/// only respected between messages that are in memory at the same time.
+///
+/// To send buffered messages as soon as the queue has room for them, register
+/// the sender with a [`Selector`](struct.Selector.html) (or mio) for
+/// writability while [`pending()`](#method.pending) is nonzero, and call
+/// `send_buffered()` when it's ready.
+///
+/// The queue can be in either blocking or nonblocking mode.  
+/// Messages that are still buffered when the sender is dropped are lost.
+///
+/// This type is not available on Illumos, Solaris or VxWorks.
+///
+/// # Examples
+///
+/// ```
+/// # use std::time::Duration;
+/// let mq = posixmq::OpenOptions::readwrite()
+///     .capacity(1)
+///     .max_msg_len(10)
+///     .create_new()
+///     .open("/buffered_sender")
+///     .expect("create queue");
+/// # posixmq::remove_queue("/buffered_sender").unwrap();
+/// let receiver = mq.try_clone().unwrap();
+/// let mut sender = posixmq::BufferedSender::new(mq, 1000).unwrap();
+/// sender.send(1, b"first").unwrap();
+/// sender.send(2, b"second").unwrap(); // queue is full
+/// assert_eq!(sender.pending(), 1);
+///
+/// let mut buf = [0; 10];
+/// assert_eq!(receiver.recv(&mut buf).unwrap(), (1, 5));
+/// assert!(sender.flush(Some(Duration::from_secs(1))).unwrap());
+/// assert_eq!(receiver.recv(&mut buf).unwrap(), (2, 6));
+/// ```
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+pub struct BufferedSender {
+    mq: PosixMq,
+    max_msg_len: usize,
+    buffer: BinaryHeap<Pending>,
+    buffered_bytes: usize,
+    max_buffered_bytes: usize,
+    sequence: u64,
+    spill: Option<Spill>,
+}
+
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+impl BufferedSender {
+    /// Create a sender that buffers up to `max_buffered_bytes` of messages
+    /// in memory.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the max message length of the queue cannot be retrieved,
+    /// see [`PosixMq::attributes()`](struct.PosixMq.html#method.attributes).
+    pub fn new(mq: PosixMq,  max_buffered_bytes: usize) -> Result<Self, io::Error> {
+        Ok(BufferedSender {
+            max_msg_len: mq.attributes()?.max_msg_len,
+            mq,
+            buffer: BinaryHeap::new(),
+            buffered_bytes: 0,
+            max_buffered_bytes,
+            sequence: 0,
+            spill: None,
+        })
+    }
+
+    /// Store messages that don't fit in the memory buffer in this file.
+    ///
+    /// The file must be opened for both reading and writing, and is truncated.
+    /// It's also truncated whenever all spilled messages have been moved back
+    /// into memory.
+    ///
+    /// # Errors
+    ///
+    /// * Messages have already been spilled to another file => `ErrorKind::InvalidInput`
+    /// * Truncating the file fails
+    pub fn spill_to(&mut self,  file: File) -> Result<(), io::Error> {
+        if self.spilled() != 0 {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "messages are spilled to another file"));
+        }
+        file.set_len(0)?;
+        self.spill = Some(Spill { file, read_pos: 0, write_pos: 0, messages: 0 });
+        Ok(())
+    }
+
+    /// Send a message, or buffer it if the queue is full.
+    ///
+    /// Buffered messages are sent first, and the message is buffered if any
+    /// of them remain.
+    ///
+    /// # Errors
+    ///
+    /// * Message is too big for the queue (EMSGSIZE) => `ErrorKind::Other`
+    /// * The memory buffer is full and there is no spill file => `ErrorKind::WouldBlock`
+    /// * Errors from [`send_buffered()`](#method.send_buffered)
+    /// * Errors from writing to the spill file
+    /// * Other errors from [`PosixMq::send()`](struct.PosixMq.html#method.send)
+    pub fn send(&mut self,  priority: u32,  msg: &[u8]) -> Result<(), io::Error> {
+        if msg.len() > self.max_msg_len {
+            return Err(io::Error::from_raw_os_error(EMSGSIZE));
+        }
+        self.send_buffered()?;
+        if self.pending() == 0 {
+            match self.mq.timedsend(priority, msg, &expired_realtime()) {
+                Err(ref e) if e.kind() == ErrorKind::TimedOut => {}
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {}
+                result => return result,
+            }
+        }
+
+        if self.spilled() == 0  &&  self.buffered_bytes + msg.len() <= self.max_buffered_bytes {
+            self.buffer.push(Pending { priority, sequence: self.sequence, msg: Arc::from(msg) });
+            self.sequence += 1;
+            self.buffered_bytes += msg.len();
+            Ok(())
+        } else if let Some(ref mut spill) = self.spill {
+            let mut header = [0; 8];
+            header[..4].copy_from_slice(&u32_to_be(priority));
+            header[4..].copy_from_slice(&u32_to_be(msg.len() as u32));
+            spill.file.seek(SeekFrom::Start(spill.write_pos))?;
+            spill.file.write_all(&header)?;
+            spill.file.write_all(msg)?;
+            spill.write_pos += (header.len() + msg.len()) as u64;
+            spill.messages += 1;
+            Ok(())
+        } else {
+            Err(io::Error::new(ErrorKind::WouldBlock, "send buffer is full"))
+        }
+    }
+
+    /// Send as many buffered messages as the queue has room for, without
+    /// blocking.
+    ///
+    /// # Errors
+    ///
+    /// If sending fails with something other than that the queue is full,
+    /// the message is discarded and the error is returned.  
+    /// Reading from the spill file can also fail.
+    pub fn send_buffered(&mut self) -> Result<(), io::Error> {
+        loop {
+            self.unspill()?;
+            let pending = match self.buffer.pop() {
+                Some(pending) => pending,
+                None => return Ok(()),
+            };
+            match self.mq.timedsend(pending.priority, &pending.msg, &expired_realtime()) {
+                Err(ref e) if e.kind() == ErrorKind::TimedOut
+                          ||  e.kind() == ErrorKind::WouldBlock => {
+                    self.buffer.push(pending);
+                    return Ok(());
+                }
+                result => {
+                    self.buffered_bytes -= pending.msg.len();
+                    result?;
+                }
+            }
+        }
+    }
+
+    /// Move spilled messages into memory while there is room for them.
+    fn unspill(&mut self) -> Result<(), io::Error> {
+        if let Some(ref mut spill) = self.spill {
+            while spill.messages != 0 {
+                let mut header = [0; 8];
+                spill.file.seek(SeekFrom::Start(spill.read_pos))?;
+                spill.file.read_exact(&mut header)?;
+                let len = u32_from_be(&header[4..]) as usize;
+                // a message bigger than the buffer must be sent at some point
+                if self.buffered_bytes + len > self.max_buffered_bytes  &&  !self.buffer.is_empty() {
+                    break;
+                }
+                let mut msg = vec![0; len];
+                spill.file.read_exact(&mut msg)?;
+                spill.read_pos += (header.len() + len) as u64;
+                spill.messages -= 1;
+                let priority = u32_from_be(&header[..4]);
+                self.buffer.push(Pending { priority, sequence: self.sequence, msg: Arc::from(msg) });
+                self.sequence += 1;
+                self.buffered_bytes += len;
+            }
+            if spill.messages == 0  &&  spill.write_pos != 0 {
+                spill.file.set_len(0)?;
+                spill.read_pos = 0;
+                spill.write_pos = 0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Send buffered messages until there are none left or the timeout
+    /// expires, waiting for the queue to have room for them.
+    ///
+    /// Returns `true` if all messages were sent. A timeout of `None` waits
+    /// forever. The timeout is measured with the monotonic clock.
+    ///
+    /// # Errors
+    ///
+    /// The same as for [`send_buffered()`](#method.send_buffered), and
+    /// [`PosixMq::wait_writable()`](struct.PosixMq.html#method.wait_writable).
+    pub fn flush(&mut self,  timeout: Option<Duration>) -> Result<bool, io::Error> {
+        let deadline = timeout_to_monotonic(timeout);
+        loop {
+            self.send_buffered()?;
+            if self.pending() == 0 {
+                return Ok(true);
+            }
+            let remaining = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Ok(false);
+                    }
+                    Some(deadline - now)
+                }
+                None => None,
+            };
+            self.mq.wait_writable(remaining)?;
+        }
+    }
+
+    /// Get the number of messages that have not been sent yet, including
+    /// spilled messages.
+    pub fn pending(&self) -> usize {
+        self.buffer.len() + self.spilled()
+    }
+
+    fn spilled(&self) -> usize {
+        self.spill.as_ref().map_or(0, |spill| spill.messages )
+    }
+
+    /// Get the total length of the messages that are buffered in memory.
+    pub fn buffered_bytes(&self) -> usize {
+        self.buffered_bytes
+    }
+
+    /// Get the queue messages are sent to.
+    pub fn get_ref(&self) -> &PosixMq {
+        &self.mq
+    }
+}
+
+/// Get the descriptor of the queue, for waiting until it is writable.
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+impl AsRawFd for BufferedSender {
+    fn as_raw_fd(&self) -> RawFd {
+        self.mq.as_raw_fd()
+    }
+}
+
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+impl Debug for BufferedSender {
+    fn fmt(&self,  fmtr: &mut Formatter) -> fmt::Result {
+        fmtr.debug_struct("BufferedSender")
+            .field("mq", &self.mq)
+            .field("pending", &self.pending())
+            .field("buffered_bytes", &self.buffered_bytes)
+            .field("max_buffered_bytes", &self.max_buffered_bytes)
+            .field("spill", &self.spill.as_ref().map(|spill| &spill.file ))
+            .finish()
+    }
+}
+
+
+/// Whether a queue is readable and / or writable, or which of those to wait
+/// for.
+///
+/// Combine with `|`: `Readiness::READABLE | Readiness::WRITABLE`.
+#[derive(Clone,Copy, PartialEq,Eq, Default)]
+pub struct Readiness {
+    readable: bool,
+    writable: bool,
+}
+
+impl Readiness {
+    /// Messages can be received.
+    pub const READABLE: Readiness = Readiness { readable: true, writable: false };
+    /// Messages can be sent.
+    pub const WRITABLE: Readiness = Readiness { readable: false, writable: true };
+
+    /// Check whether receiving would not block.
+    pub fn is_readable(self) -> bool {
+        self.readable
+    }
+
+    /// Check whether sending would not block.
+    pub fn is_writable(self) -> bool {
+        self.writable
+    }
+}
+
+impl BitOr for Readiness {
+    type Output = Readiness;
+    fn bitor(self,  other: Readiness) -> Readiness {
+        Readiness {
+            readable: self.readable || other.readable,
+            writable: self.writable || other.writable,
+        }
+    }
+}
+
+impl Debug for Readiness {
+    fn fmt(&self,  fmtr: &mut Formatter) -> fmt::Result {
+        match (self.readable, self.writable) {
+            (true, true) => fmtr.write_str("READABLE | WRITABLE"),
+            (true, false) => fmtr.write_str("READABLE"),
+            (false, true) => fmtr.write_str("WRITABLE"),
+            (false, false) => fmtr.write_str("(empty)"),
+        }
+    }
+}
+
+/// Waits for any of multiple message queues to become readable or writable,
+/// without requiring mio.
+///
+/// Queues are registered through their `AsRawFd` impl together with a token
+/// that identifies them in the results of [`select()`](#method.select).
+/// The selector doesn't take ownership of the queues, so they must be
+/// deregistered before they are closed.  
+/// Readiness is level-triggered: A queue that still has messages will be
+/// reported as readable again by the next `select()`.
+///
+/// On Linux this uses epoll, and on other operating systems `poll()`.
+///
+/// This type is not available on Illumos, Solaris or VxWorks.
+///
+/// # Examples
+///
+/// ```
+/// use posixmq::{Readiness, Selector};
+/// use std::time::Duration;
+///
+/// let mut opts = posixmq::OpenOptions::readwrite();
+/// opts.capacity(1).max_msg_len(10).create_new();
+/// let a = opts.open("/select_a").expect("create queue");
+/// let b = opts.open("/select_b").expect("create queue");
+/// # posixmq::remove_queue("/select_a").unwrap();
+/// # posixmq::remove_queue("/select_b").unwrap();
+///
+/// let mut selector = Selector::new().expect("create selector");
+/// selector.register(&a, 0, Readiness::READABLE).unwrap();
+/// selector.register(&b, 1, Readiness::READABLE).unwrap();
+///
+/// b.send(0, b"b").unwrap();
+/// let mut events = Vec::new();
+/// selector.select(&mut events, Some(Duration::from_secs(1))).unwrap();
+/// assert_eq!(events, vec![(1, Readiness::READABLE)]);
+/// ```
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+pub struct Selector {
+    #[cfg(target_os="linux")]
+    epoll: RawFd,
+    #[cfg(target_os="linux")]
+    registered: usize,
+    #[cfg(target_os="linux")]
+    events: Vec<epoll_event>,
+    #[cfg(not(target_os="linux"))]
+    fds: Vec<pollfd>,
+    #[cfg(not(target_os="linux"))]
+    tokens: Vec<usize>,
+}
+
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+impl Selector {
+    /// Create a selector without any registered queues.
+    ///
+    /// # Errors
+    ///
+    /// On Linux, creating the epoll descriptor can fail if the process or
+    /// system has too many open file descriptors (EMFILE or ENFILE)
+    /// => `ErrorKind::Other`
+    #[cfg(target_os="linux")]
+    pub fn new() -> Result<Self, io::Error> {
+        match unsafe { epoll_create1(EPOLL_CLOEXEC) } {
+            -1 => Err(io::Error::last_os_error()),
+            epoll => Ok(Selector { epoll, registered: 0, events: Vec::new() }),
+        }
+    }
+
+    /// Create a selector without any registered queues.
+    #[cfg(not(target_os="linux"))]
+    pub fn new() -> Result<Self, io::Error> {
+        Ok(Selector { fds: Vec::new(), tokens: Vec::new() })
+    }
+
+    /// Start waiting for readiness of a queue.
+    ///
+    /// Other types of file descriptors can also be registered.
+    ///
+    /// # Errors
+    ///
+    /// * The descriptor is already registered (EEXIST) => `ErrorKind::AlreadyExists`
+    /// * On Linux, possibly other => `ErrorKind::Other`
+    pub fn register<S: AsRawFd + ?Sized>(&mut self,  source: &S,  token: usize,  interest: Readiness)
+    -> Result<(), io::Error> {
+        self.control(source.as_raw_fd(), token, interest, true)
+    }
+
+    /// Change which readiness to wait for, or the token, of an already
+    /// registered queue.
+    ///
+    /// # Errors
+    ///
+    /// * The descriptor is not registered (ENOENT) => `ErrorKind::NotFound`
+    /// * On Linux, possibly other => `ErrorKind::Other`
+    pub fn reregister<S: AsRawFd + ?Sized>(&mut self,  source: &S,  token: usize,  interest: Readiness)
+    -> Result<(), io::Error> {
+        self.control(source.as_raw_fd(), token, interest, false)
+    }
+
+    #[cfg(target_os="linux")]
+    fn control(&mut self,  fd: RawFd,  token: usize,  interest: Readiness,  add: bool)
+    -> Result<(), io::Error> {
+        let mut event = epoll_event { events: 0, u64: token as u64 };
+        if interest.readable {
+            event.events |= EPOLLIN as u32;
+        }
+        if interest.writable {
+            event.events |= EPOLLOUT as u32;
+        }
+        let op = if add {EPOLL_CTL_ADD} else {EPOLL_CTL_MOD};
+        if unsafe { epoll_ctl(self.epoll, op, fd, &mut event) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        if add {
+            self.registered += 1;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os="linux"))]
+    fn control(&mut self,  fd: RawFd,  token: usize,  interest: Readiness,  add: bool)
+    -> Result<(), io::Error> {
+        let mut events = 0;
+        if interest.readable {
+            events |= POLLIN;
+        }
+        if interest.writable {
+            events |= POLLOUT;
+        }
+        match (self.fds.iter().position(|pollfd| pollfd.fd == fd ), add) {
+            (Some(_), true) => Err(io::Error::from_raw_os_error(libc::EEXIST)),
+            (None, false) => Err(io::Error::from_raw_os_error(libc::ENOENT)),
+            (None, true) => {
+                self.fds.push(new_pollfd(fd, events));
+                self.tokens.push(token);
+                Ok(())
+            }
+            (Some(i), false) => {
+                self.fds[i].events = events;
+                self.tokens[i] = token;
+                Ok(())
+            }
+        }
+    }
+
+    /// Stop waiting for readiness of a queue.
+    ///
+    /// # Errors
+    ///
+    /// * The descriptor is not registered (ENOENT) => `ErrorKind::NotFound`
+    /// * On Linux, possibly other => `ErrorKind::Other`
+    pub fn deregister<S: AsRawFd + ?Sized>(&mut self,  source: &S) -> Result<(), io::Error> {
+        let fd = source.as_raw_fd();
+        #[cfg(target_os="linux")]
+        {
+            // Linux versions before 2.6.9 requires a non-null event
+            let mut unused = epoll_event { events: 0, u64: 0 };
+            if unsafe { epoll_ctl(self.epoll, EPOLL_CTL_DEL, fd, &mut unused) } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            self.registered -= 1;
+        }
+        #[cfg(not(target_os="linux"))]
+        {
+            let i = match self.fds.iter().position(|pollfd| pollfd.fd == fd ) {
+                Some(i) => i,
+                None => return Err(io::Error::from_raw_os_error(libc::ENOENT)),
+            };
+            let _ = self.fds.remove(i);
+            let _ = self.tokens.remove(i);
+        }
+        Ok(())
+    }
+
+    /// Wait until at least one registered queue is ready or the timeout
+    /// expires, and replace the content of `events` with the tokens and
+    /// readiness of the ready queues.
+    ///
+    /// `events` is left empty if the timeout expires. `None` waits forever.
+    /// The timeout is measured with the monotonic clock, and EINTR is
+    /// retried with the remaining time.
+    ///
+    /// Errors and hangups are reported as both readable and writable, so
+    /// that the following `recv()` or `send()` returns the error.
+    ///
+    /// # Errors
+    ///
+    /// Waiting should only fail if the process has run out of memory.
+    pub fn select(&mut self,  events: &mut Vec<(usize, Readiness)>,  timeout: Option<Duration>)
+    -> Result<(), io::Error> {
+        events.clear();
+        #[cfg(target_os="linux")]
+        {
+            let capacity = cmp::max(self.registered, 1);
+            self.events.clear();
+            self.events.reserve(capacity);
+            let deadline = timeout_to_monotonic(timeout);
+            let ready = loop {
+                let ready = unsafe { epoll_wait(
+                        self.epoll,
+                        self.events.as_mut_ptr(),
+                        capacity as c_int,
+                        remaining_ms(deadline)
+                ) };
+                if ready != -1 {
+                    break ready as usize;
+                }
+                let err = io::Error::last_os_error();
+                if err.kind() != ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            };
+            unsafe { self.events.set_len(ready) };
+            for event in &self.events {
+                let error = event.events & (EPOLLERR | EPOLLHUP) as u32 != 0;
+                events.push((event.u64 as usize, Readiness {
+                    readable: error  ||  event.events & EPOLLIN as u32 != 0,
+                    writable: error  ||  event.events & EPOLLOUT as u32 != 0,
+                }));
+            }
+        }
+        #[cfg(not(target_os="linux"))]
+        {
+            for pollfd in &mut self.fds {
+                pollfd.revents = 0;
+            }
+            poll_fds(&mut self.fds, timeout, true)?;
+            for (pollfd, &token) in self.fds.iter().zip(&self.tokens) {
+                if pollfd.revents != 0 {
+                    let error = pollfd.revents & (POLLERR | POLLHUP | POLLNVAL) != 0;
+                    events.push((token, Readiness {
+                        readable: error  ||  pollfd.revents & POLLIN != 0,
+                        writable: error  ||  pollfd.revents & POLLOUT != 0,
+                    }));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+impl Debug for Selector {
+    fn fmt(&self,  fmtr: &mut Formatter) -> fmt::Result {
+        let mut representation = fmtr.debug_struct("Selector");
+        #[cfg(target_os="linux")]
+        representation.field("epoll", &self.epoll).field("registered", &self.registered);
+        #[cfg(not(target_os="linux"))]
+        representation.field("registered", &self.fds.len());
+        return representation.finish();
+    }
+}
+
+#[cfg(target_os="linux")]
+impl Drop for Selector {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.epoll) };
+    }
+}
+
+
+/// Something that happened in a [`WorkerPool`](struct.WorkerPool.html)
+/// worker thread, passed to the supervisor callback.
+///
+/// `worker` is the index of the thread that the event happened in.
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+#[derive(Debug)]
+pub enum WorkerEvent<E> {
+    /// The handler returned an error for a message.
+    HandlerFailed {worker: usize,  priority: u32,  error: E},
+    /// The handler panicked while handling a message.
+    ///
+    /// The payload is what [`std::thread::JoinHandle::join()`](https://doc.rust-lang.org/std/thread/struct.JoinHandle.html#method.join)
+    /// would have returned, and can be passed to `std::panic::resume_unwind()`.
+    HandlerPanicked {worker: usize,  priority: u32,  payload: Box<dyn Any + Send>},
+    /// Receiving failed, and the worker has stopped.
+    RecvFailed {worker: usize,  error: io::Error},
+}
+
+/// Threads that receive from a shared queue and pass the messages to a
+/// handler.
+///
+/// Each worker has its own buffer which is reused for every message.
+/// Errors returned by the handler, panics in the handler and errors from
+/// receiving are passed to a supervisor callback, which is called from the
+/// worker thread. A worker continues with the next message after a handler
+/// error or panic, but stops after a receive error, as those are likely to
+/// repeat.
+///
+/// [`shutdown()`](#method.shutdown) lets handlers that are running finish
+/// before the workers stop. Dropping the pool also shuts it down.
+///
+/// The workers receive with
+/// [`recv_cancellable()`](struct.PosixMq.html#method.recv_cancellable),
+/// so the queue can be in either blocking or nonblocking mode.
+///
+/// This type is not available on Illumos, Solaris or VxWorks.
+///
+/// # Examples
+///
+/// ```
+/// # use std::sync::mpsc;
+/// let mq = posixmq::PosixMq::create("/worker_pool").expect("create queue");
+/// # posixmq::remove_queue("/worker_pool").unwrap();
+/// mq.send(0, b"work").unwrap();
+/// let (sender, receiver) = mpsc::channel();
+/// let sender = std::sync::Mutex::new(sender);
+/// let pool = posixmq::WorkerPool::spawn(
+///     mq,
+///     2,
+///     move |priority, msg| sender.lock().unwrap().send((priority, msg.to_vec())),
+///     |event| panic!("{:?}", event)
+/// ).expect("start workers");
+/// assert_eq!(receiver.recv().unwrap(), (0, b"work".to_vec()));
+/// pool.shutdown();
+/// ```
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+pub struct WorkerPool {
+    workers: Vec<JoinHandle<()>>,
+    shutdown: CancelToken,
+}
+
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+impl WorkerPool {
+    /// Start `threads` worker threads that receive from `mq`.
+    ///
+    /// # Errors
+    ///
+    /// * `threads` is zero => `ErrorKind::InvalidInput`
+    /// * Getting the maximum message length fails => `ErrorKind::Other`
+    /// * Creating the shutdown pipe or a thread fails => `ErrorKind::Other`
+    ///
+    /// Threads that were started before an error are stopped again.
+    pub fn spawn<H, E, S>(mq: PosixMq,  threads: usize,  handler: H,  supervisor: S)
+    -> Result<Self, io::Error>
+    where H: Fn(u32, &[u8]) -> Result<(), E> + Send + Sync + 'static,
+          E: Send + 'static,
+          S: Fn(WorkerEvent<E>) + Send + Sync + 'static {
+        if threads == 0 {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "a worker pool needs threads"));
+        }
+        let max_msg_len = mq.attributes()?.max_msg_len;
+        let mut pool = WorkerPool {
+            workers: Vec::with_capacity(threads),
+            shutdown: CancelToken::new()?,
+        };
+        let mq = Arc::new(mq);
+        let handler = Arc::new(handler);
+        let supervisor = Arc::new(supervisor);
+        for worker in 0..threads {
+            let mq = mq.clone();
+            let handler = handler.clone();
+            let supervisor = supervisor.clone();
+            let shutdown = pool.shutdown.clone();
+            let thread = thread::Builder::new()
+                .name(format!("posixmq worker {}", worker))
+                .spawn(move|| {
+                    let mut buf = vec![0; max_msg_len];
+                    work(worker, &mq, &mut buf, &*handler, &*supervisor, &shutdown)
+                })?;
+            pool.workers.push(thread);
+        }
+        Ok(pool)
+    }
+
+    /// Stop the workers after they have finished handling the messages they
+    /// have already received, and wait for them to exit.
+    ///
+    /// # Panics
+    ///
+    /// If the supervisor callback panicked, the panic is resumed here.
+    pub fn shutdown(mut self) {
+        if let Err(payload) = self.stop() {
+            panic::resume_unwind(payload);
+        }
+    }
+
+    /// Returns the payload of the first panic from a worker thread.
+    fn stop(&mut self) -> thread::Result<()> {
+        self.shutdown.cancel();
+        let mut result = Ok(());
+        for worker in self.workers.drain(..) {
+            if let Err(payload) = worker.join() {
+                if result.is_ok() {
+                    result = Err(payload);
+                }
+            }
+        }
+        return result;
+    }
+}
+
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+fn work<H, E, S>(worker: usize,  mq: &PosixMq,  buf: &mut [u8],  handler: &H,  supervisor: &S,
+        shutdown: &CancelToken)
+where H: Fn(u32, &[u8]) -> Result<(), E>, S: Fn(WorkerEvent<E>) {
+    loop {
+        let (priority, len) = match mq.recv_cancellable(buf, shutdown) {
+            Ok(received) => received,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted  &&  shutdown.is_cancelled() => return,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(error) => return supervisor(WorkerEvent::RecvFailed {worker, error}),
+        };
+        let msg = &buf[..len];
+        match panic::catch_unwind(AssertUnwindSafe(|| handler(priority, msg) )) {
+            Ok(Ok(())) => {}
+            Ok(Err(error)) => supervisor(WorkerEvent::HandlerFailed {worker, priority, error}),
+            Err(payload) => supervisor(WorkerEvent::HandlerPanicked {worker, priority, payload}),
+        }
+    }
+}
+
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+impl Debug for WorkerPool {
+    fn fmt(&self,  fmtr: &mut Formatter) -> fmt::Result {
+        fmtr.debug_struct("WorkerPool")
+            .field("threads", &self.workers.len())
+            .field("shutdown", &self.shutdown.is_cancelled())
+            .finish()
+    }
+}
+
+#[cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        // don't panic while possibly already panicking
+        let _ = self.stop();
+    }
+}
+
+
+/// The operations shared by [`PosixMq`](struct.PosixMq.html) and the other
+/// message queue types in this crate, for writing code that works with any
+/// of them.
+///
+/// The methods behave like the inherent methods on `PosixMq` with the same
+/// name; See those for details and errors.
+/// The inherent methods take precedence, so importing this trait doesn't
+/// change what existing code calls.
+///
+/// # Examples
+///
+/// Testing code with [`MemoryMq`](struct.MemoryMq.html):
+///
+/// ```
+/// use posixmq::{MemoryMq, MessageQueue};
+///
+/// fn forward_urgent<Q: MessageQueue>(from: &Q,  to: &Q) -> std::io::Result<()> {
+///     let mut buf = vec![0; from.attributes()?.max_msg_len];
+///     let (priority, len) = from.recv(&mut buf)?;
+///     if priority >= 10 {
+///         to.send(priority, &buf[..len])?;
+///     }
+///     Ok(())
+/// }
+///
+/// let (from, to) = (MemoryMq::new(2, 10).unwrap(), MemoryMq::new(1, 10).unwrap());
+/// from.send(10, b"urgent").unwrap();
+/// forward_urgent(&from, &to).unwrap();
+/// assert_eq!(to.attributes().unwrap().current_messages, 1);
+/// ```
+pub trait MessageQueue {
+    /// Add a message to the queue.
+    fn send(&self,  priority: u32,  msg: &[u8]) -> Result<(), io::Error>;
+    /// Take the message with the highest priority from the queue.
+    fn recv(&self,  msgbuf: &mut [u8]) -> Result<(u32, usize), io::Error>;
+    /// Add a message to the queue or cancel if it's still full after a given
+    /// duration.
+    fn send_timeout(&self,  priority: u32,  msg: &[u8],  timeout: Duration)
+    -> Result<(), io::Error>;
+    /// Add a message to the queue or cancel if the queue is still full at a
+    /// certain point in time.
+    fn send_deadline(&self,  priority: u32,  msg: &[u8],  deadline: SystemTime)
+    -> Result<(), io::Error>;
+    /// Take the message with the highest priority from the queue or cancel if
+    /// the queue still empty after a given duration.
+    fn recv_timeout(&self,  msgbuf: &mut [u8],  timeout: Duration)
+    -> Result<(u32, usize), io::Error>;
+    /// Take the message with the highest priority from the queue or cancel if
+    /// the queue is still empty at a point in time.
+    fn recv_deadline(&self,  msgbuf: &mut [u8],  deadline: SystemTime)
+    -> Result<(u32, usize), io::Error>;
+    /// Get information about the capacities and state of the queue.
+    fn attributes(&self) -> Result<Attributes, io::Error>;
+}
+
+impl MessageQueue for PosixMq {
+    fn send(&self,  priority: u32,  msg: &[u8]) -> Result<(), io::Error> {
+        PosixMq::send(self, priority, msg)
+    }
+    fn recv(&self,  msgbuf: &mut [u8]) -> Result<(u32, usize), io::Error> {
+        PosixMq::recv(self, msgbuf)
+    }
+    fn send_timeout(&self,  priority: u32,  msg: &[u8],  timeout: Duration)
+    -> Result<(), io::Error> {
+        PosixMq::send_timeout(self, priority, msg, timeout)
+    }
+    fn send_deadline(&self,  priority: u32,  msg: &[u8],  deadline: SystemTime)
+    -> Result<(), io::Error> {
+        PosixMq::send_deadline(self, priority, msg, deadline)
+    }
+    fn recv_timeout(&self,  msgbuf: &mut [u8],  timeout: Duration)
+    -> Result<(u32, usize), io::Error> {
+        PosixMq::recv_timeout(self, msgbuf, timeout)
+    }
+    fn recv_deadline(&self,  msgbuf: &mut [u8],  deadline: SystemTime)
+    -> Result<(u32, usize), io::Error> {
+        PosixMq::recv_deadline(self, msgbuf, deadline)
+    }
+    fn attributes(&self) -> Result<Attributes, io::Error> {
+        PosixMq::attributes(self)
+    }
+}
+
+impl<'a, Q: MessageQueue + ?Sized> MessageQueue for &'a Q {
+    fn send(&self,  priority: u32,  msg: &[u8]) -> Result<(), io::Error> {
+        (**self).send(priority, msg)
+    }
+    fn recv(&self,  msgbuf: &mut [u8]) -> Result<(u32, usize), io::Error> {
+        (**self).recv(msgbuf)
+    }
+    fn send_timeout(&self,  priority: u32,  msg: &[u8],  timeout: Duration)
+    -> Result<(), io::Error> {
+        (**self).send_timeout(priority, msg, timeout)
+    }
+    fn send_deadline(&self,  priority: u32,  msg: &[u8],  deadline: SystemTime)
+    -> Result<(), io::Error> {
+        (**self).send_deadline(priority, msg, deadline)
+    }
+    fn recv_timeout(&self,  msgbuf: &mut [u8],  timeout: Duration)
+    -> Result<(u32, usize), io::Error> {
+        (**self).recv_timeout(msgbuf, timeout)
+    }
+    fn recv_deadline(&self,  msgbuf: &mut [u8],  deadline: SystemTime)
+    -> Result<(u32, usize), io::Error> {
+        (**self).recv_deadline(msgbuf, deadline)
+    }
+    fn attributes(&self) -> Result<Attributes, io::Error> {
+        (**self).attributes()
+    }
+}
+
+
+/// The highest priority the queues implemented by this crate accept,
+/// which is the same as on Linux.
+const EMULATED_MAX_PRIORITY: u32 = 32767;
+
+struct MemoryQueue {
+    messages: BinaryHeap<Pending>,
+    sequence: u64,
+}
+
+struct MemoryShared {
+    queue: Mutex<MemoryQueue>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    capacity: usize,
+    max_msg_len: usize,
+}
+
+/// A message queue that only exists in memory, for testing code that uses
+/// [`MessageQueue`](trait.MessageQueue.html) without creating kernel queues.
+///
+/// Unlike posix message queues, these don't collide with queues created by
+/// other tests or processes, and their capacity is not limited by the OS.
+///
+/// Messages are received in the same order as from a posix message queue:
+/// Highest priority first, and in the order they were sent among messages
+/// with equal priority.  
+/// Errors mirror what Linux returns:
+///
+/// * Queue is full or empty and the handle is in nonblocking mode (EAGAIN) => `ErrorKind::WouldBlock`
+/// * Message is longer than `max_msg_len` (EMSGSIZE) => `ErrorKind::Other`
+/// * The receive buffer is shorter than `max_msg_len` (EMSGSIZE) => `ErrorKind::Other`
+/// * Priority is above 32767 (EINVAL) => `ErrorKind::InvalidInput`
+/// * Timeout expired or deadline reached (ETIMEDOUT) => `ErrorKind::TimedOut`
+/// * Timeout is too long / not representable => `ErrorKind::InvalidInput`
+///
+/// Cloning creates another handle to the same queue, which like
+/// [`try_clone()`](struct.PosixMq.html#method.try_clone) starts out in the
+/// same mode, but can then be set to nonblocking mode independently.
+///
+/// # Examples
+///
+/// ```
+/// use std::thread;
+/// use posixmq::{MemoryMq, MessageQueue};
+///
+/// let mq = MemoryMq::new(20, 100).unwrap();
+/// let sender = mq.clone();
+/// thread::spawn(move|| {
+///     for n in 0..20 {
+///         sender.send(n % 3, &[n as u8]).unwrap();
+///     }
+/// }).join().unwrap();
+///
+/// let mut buf = [0; 100];
+/// assert_eq!(mq.recv(&mut buf).unwrap(), (2, 1));
+/// assert_eq!(buf[0], 2);
+/// ```
+pub struct MemoryMq {
+    shared: Arc<MemoryShared>,
+    nonblocking: AtomicBool,
+}
+
+impl MemoryMq {
+    /// Create a new empty queue in blocking mode.
+    ///
+    /// # Errors
+    ///
+    /// * `capacity` or `max_msg_len` is zero (EINVAL) => `ErrorKind::InvalidInput`
+    pub fn new(capacity: usize,  max_msg_len: usize) -> Result<Self, io::Error> {
+        if capacity == 0  ||  max_msg_len == 0 {
+            return Err(io::Error::from_raw_os_error(EINVAL));
+        }
+        let queue = MemoryQueue {
+            messages: BinaryHeap::new(),
+            sequence: 0,
+        };
+        Ok(MemoryMq {
+            shared: Arc::new(MemoryShared {
+                queue: Mutex::new(queue),
+                not_empty: Condvar::new(),
+                not_full: Condvar::new(),
+                capacity,
+                max_msg_len,
+            }),
+            nonblocking: AtomicBool::new(false),
+        })
+    }
+
+    /// Check whether this handle is in nonblocking mode.
+    pub fn is_nonblocking(&self) -> bool {
+        self.nonblocking.load(atomic::Ordering::Relaxed)
+    }
+
+    /// Enable or disable nonblocking mode for this handle.
+    ///
+    /// Other handles to the same queue are not affected.
+    pub fn set_nonblocking(&self,  nonblocking: bool) {
+        self.nonblocking.store(nonblocking, atomic::Ordering::Relaxed);
+    }
+
+    fn lock<'a>(&'a self) -> MutexGuard<'a, MemoryQueue> {
+        // the queue is never left in an inconsistent state
+        self.shared.queue.lock().unwrap_or_else(|e| e.into_inner() )
+    }
+
+    /// Wait on `condvar` until `ready` returns true.
+    fn wait<'a, F>(&self,  mut queue: MutexGuard<'a, MemoryQueue>,  condvar: &Condvar,
+            deadline: Option<SystemTime>,  ready: F,
+    ) -> Result<MutexGuard<'a, MemoryQueue>, io::Error>
+    where F: Fn(&MemoryQueue)->bool {
+        while !ready(&queue) {
+            if self.is_nonblocking() {
+                return Err(io::Error::from_raw_os_error(EAGAIN));
+            }
+            queue = match deadline {
+                None => condvar.wait(queue).unwrap_or_else(|e| e.into_inner() ),
+                Some(deadline) => match deadline.duration_since(SystemTime::now()) {
+                    Ok(remaining) if remaining > Duration::new(0, 0) => {
+                        condvar.wait_timeout(queue, remaining)
+                            .unwrap_or_else(|e| e.into_inner() )
+                            .0
+                    }
+                    _ => return Err(io::Error::from_raw_os_error(ETIMEDOUT)),
+                }
+            };
+        }
+        Ok(queue)
+    }
+
+    fn send_until(&self,  priority: u32,  msg: &[u8],  deadline: Option<SystemTime>)
+    -> Result<(), io::Error> {
+        if msg.len() > self.shared.max_msg_len {
+            return Err(io::Error::from_raw_os_error(EMSGSIZE));
+        } else if priority > EMULATED_MAX_PRIORITY {
+            return Err(io::Error::from_raw_os_error(EINVAL));
+        }
+        let capacity = self.shared.capacity;
+        let queue = self.lock();
+        let mut queue = self.wait(queue, &self.shared.not_full, deadline,
+            |queue| queue.messages.len() < capacity
+        )?;
+        let sequence = queue.sequence;
+        queue.sequence += 1;
+        queue.messages.push(Pending{priority, sequence, msg: Arc::from(msg)});
+        self.shared.not_empty.notify_one();
+        Ok(())
+    }
+
+    fn recv_until(&self,  msgbuf: &mut [u8],  deadline: Option<SystemTime>)
+    -> Result<(u32, usize), io::Error> {
+        if msgbuf.len() < self.shared.max_msg_len {
+            return Err(io::Error::from_raw_os_error(EMSGSIZE));
+        }
+        let queue = self.lock();
+        let mut queue = self.wait(queue, &self.shared.not_empty, deadline,
+            |queue| !queue.messages.is_empty()
+        )?;
+        let message = queue.messages.pop().unwrap();
+        self.shared.not_full.notify_one();
+        msgbuf[..message.msg.len()].copy_from_slice(&message.msg);
+        Ok((message.priority, message.msg.len()))
+    }
+
+    /// Returns the same errors as sending with a timeout to a `PosixMq`.
+    fn timeout_to_deadline(timeout: Duration) -> Result<SystemTime, io::Error> {
+        timeout_to_realtime(timeout).map(|_| SystemTime::now() + timeout )
+    }
+}
+
+impl MessageQueue for MemoryMq {
+    fn send(&self,  priority: u32,  msg: &[u8]) -> Result<(), io::Error> {
+        self.send_until(priority, msg, None)
+    }
+    fn recv(&self,  msgbuf: &mut [u8]) -> Result<(u32, usize), io::Error> {
+        self.recv_until(msgbuf, None)
+    }
+    fn send_timeout(&self,  priority: u32,  msg: &[u8],  timeout: Duration)
+    -> Result<(), io::Error> {
+        let deadline = MemoryMq::timeout_to_deadline(timeout)?;
+        self.send_until(priority, msg, Some(deadline))
+    }
+    fn send_deadline(&self,  priority: u32,  msg: &[u8],  deadline: SystemTime)
+    -> Result<(), io::Error> {
+        self.send_until(priority, msg, Some(deadline))
+    }
+    fn recv_timeout(&self,  msgbuf: &mut [u8],  timeout: Duration)
+    -> Result<(u32, usize), io::Error> {
+        let deadline = MemoryMq::timeout_to_deadline(timeout)?;
+        self.recv_until(msgbuf, Some(deadline))
+    }
+    fn recv_deadline(&self,  msgbuf: &mut [u8],  deadline: SystemTime)
+    -> Result<(u32, usize), io::Error> {
+        self.recv_until(msgbuf, Some(deadline))
+    }
+    fn attributes(&self) -> Result<Attributes, io::Error> {
+        Ok(Attributes {
+            max_msg_len: self.shared.max_msg_len,
+            capacity: self.shared.capacity,
+            current_messages: self.lock().messages.len(),
+            nonblocking: self.is_nonblocking(),
+            _private: ()
+        })
+    }
+}
+
+impl Clone for MemoryMq {
+    fn clone(&self) -> Self {
+        MemoryMq {
+            shared: self.shared.clone(),
+            nonblocking: AtomicBool::new(self.is_nonblocking()),
+        }
+    }
+}
+
+impl Debug for MemoryMq {
+    fn fmt(&self,  fmtr: &mut Formatter) -> fmt::Result {
+        fmtr.debug_struct("MemoryMq")
+            .field("capacity", &self.shared.capacity)
+            .field("max_msg_len", &self.shared.max_msg_len)
+            .field("current_messages", &self.lock().messages.len())
+            .field("nonblocking", &self.is_nonblocking())
+            .finish()
+    }
+}
+
+
+/// A failure or delay that [`FaultyMq`](struct.FaultyMq.html) injects.
+#[derive(Clone,Copy, PartialEq,Eq, Debug)]
+pub enum Fault {
+    /// EAGAIN => `ErrorKind::WouldBlock`, as if the queue was full or empty
+    /// in nonblocking mode.
+    WouldBlock,
+    /// ETIMEDOUT => `ErrorKind::TimedOut`, as if a timeout expired.
+    TimedOut,
+    /// EINTR => `ErrorKind::Interrupted`, as if interrupted by a signal with
+    /// retrying disabled.
+    Interrupted,
+    /// EMSGSIZE => `ErrorKind::Other`, as if the message or receive buffer
+    /// had the wrong size.
+    MessageSize,
+    /// EBADF => `ErrorKind::Other`, as if the descriptor was closed or opened
+    /// in the wrong mode.
+    BadDescriptor,
+    /// Sleep for a duration before performing the operation normally.
+    Delay(Duration),
+}
+
+impl Fault {
+    fn inject(self) -> Result<(), io::Error> {
+        let errno = match self {
+            Fault::WouldBlock => EAGAIN,
+            Fault::TimedOut => ETIMEDOUT,
+            Fault::Interrupted => EINTR,
+            Fault::MessageSize => EMSGSIZE,
+            Fault::BadDescriptor => EBADF,
+            Fault::Delay(delay) => {
+                thread::sleep(delay);
+                return Ok(());
+            }
+        };
+        Err(io::Error::from_raw_os_error(errno))
+    }
+}
+
+struct Faults {
+    sends: VecDeque<Fault>,
+    recvs: VecDeque<Fault>,
+    random: Vec<Fault>,
+    /// probability multiplied by 2^32
+    random_threshold: u64,
+    /// xorshift64 state, never zero
+    rng: u64,
+    injected: usize,
+}
+
+impl Faults {
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    fn next(&mut self,  sending: bool) -> Option<Fault> {
+        let scripted = if sending {self.sends.pop_front()} else {self.recvs.pop_front()};
+        let fault = match scripted {
+            Some(fault) => Some(fault),
+            None if self.random.is_empty() => None,
+            None => {
+                let random = self.next_random();
+                if random >> 32 < self.random_threshold {
+                    Some(self.random[(random & 0xffff_ffff) as usize % self.random.len()])
+                } else {
+                    None
+                }
+            }
+        };
+        if fault.is_some() {
+            self.injected += 1;
+        }
+        fault
+    }
+}
+
+/// A wrapper around any [`MessageQueue`](trait.MessageQueue.html) that
+/// injects errors and delays, for testing error handling.
+///
+/// Faults can be scripted to happen for the next sends or receives, and/or
+/// be picked randomly with a given probability from a seeded pseudo-random
+/// sequence, which makes failing runs reproducible.
+/// Scripted faults are injected before random ones.
+///
+/// Injected errors are returned before the wrapped queue is touched, so the
+/// failed operation has no effect.
+/// Only sending and receiving (including the timed variants) are affected;
+/// `attributes()` is always passed through.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::ErrorKind;
+/// use posixmq::{Fault, FaultyMq, MemoryMq, MessageQueue};
+///
+/// let mq = FaultyMq::new(MemoryMq::new(2, 10).unwrap());
+/// mq.script_sends(vec![Fault::Interrupted, Fault::WouldBlock]);
+/// assert_eq!(mq.send(0, b"a").unwrap_err().kind(), ErrorKind::Interrupted);
+/// assert_eq!(mq.send(0, b"a").unwrap_err().kind(), ErrorKind::WouldBlock);
+/// mq.send(0, b"a").unwrap();
+/// assert_eq!(mq.get_ref().attributes().unwrap().current_messages, 1);
+/// ```
+pub struct FaultyMq<Q: MessageQueue> {
+    inner: Q,
+    faults: Mutex<Faults>,
+}
+
+impl<Q: MessageQueue> FaultyMq<Q> {
+    /// Wrap a queue, without injecting any faults until told to.
+    pub fn new(inner: Q) -> Self {
+        FaultyMq {
+            inner,
+            faults: Mutex::new(Faults {
+                sends: VecDeque::new(),
+                recvs: VecDeque::new(),
+                random: Vec::new(),
+                random_threshold: 0,
+                rng: 1,
+                injected: 0,
+            }),
+        }
+    }
+
+    fn faults<'a>(&'a self) -> MutexGuard<'a, Faults> {
+        self.faults.lock().unwrap_or_else(|e| e.into_inner() )
+    }
+
+    /// Inject these faults, one per call, into the next sends.
+    ///
+    /// The faults are added after any previously scripted send faults.
+    pub fn script_sends<I: IntoIterator<Item=Fault>>(&self,  faults: I) {
+        self.faults().sends.extend(faults);
+    }
+
+    /// Inject these faults, one per call, into the next receives.
+    ///
+    /// The faults are added after any previously scripted receive faults.
+    pub fn script_recvs<I: IntoIterator<Item=Fault>>(&self,  faults: I) {
+        self.faults().recvs.extend(faults);
+    }
+
+    /// Make sends and receives without a scripted fault fail with
+    /// `probability` (between 0.0 and 1.0), with a fault picked from `faults`.
+    ///
+    /// The same seed produces the same sequence of faults for the same
+    /// sequence of calls.
+    /// An empty slice or a probability of zero disables random faults.
+    pub fn random_faults(&mut self,  probability: f64,  faults: &[Fault],  seed: u64)
+    -> &mut Self {
+        let state = self.faults.get_mut().unwrap_or_else(|e| e.into_inner() );
+        state.random = faults.to_vec();
+        // also turns NaN into zero
+        let probability = if probability > 1.0 {1.0} else if probability > 0.0 {probability} else {0.0};
+        state.random_threshold = (probability * 4294967296.0) as u64;
+        // xorshift gets stuck on zero
+        state.rng = if seed == 0 {0x9e37_79b9_7f4a_7c15} else {seed};
+        return self;
+    }
+
+    /// Get the number of faults injected so far, including delays.
+    pub fn injected(&self) -> usize {
+        self.faults().injected
+    }
+
+    /// Get the wrapped queue.
+    pub fn get_ref(&self) -> &Q {
+        &self.inner
+    }
+
+    /// Unwrap the wrapped queue.
+    pub fn into_inner(self) -> Q {
+        self.inner
+    }
+
+    fn before(&self,  sending: bool) -> Result<(), io::Error> {
+        // don't hold the lock while sleeping
+        let fault = self.faults().next(sending);
+        match fault {
+            Some(fault) => fault.inject(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<Q: MessageQueue> MessageQueue for FaultyMq<Q> {
+    fn send(&self,  priority: u32,  msg: &[u8]) -> Result<(), io::Error> {
+        self.before(true)?;
+        self.inner.send(priority, msg)
+    }
+    fn recv(&self,  msgbuf: &mut [u8]) -> Result<(u32, usize), io::Error> {
+        self.before(false)?;
+        self.inner.recv(msgbuf)
+    }
+    fn send_timeout(&self,  priority: u32,  msg: &[u8],  timeout: Duration)
+    -> Result<(), io::Error> {
+        self.before(true)?;
+        self.inner.send_timeout(priority, msg, timeout)
+    }
+    fn send_deadline(&self,  priority: u32,  msg: &[u8],  deadline: SystemTime)
+    -> Result<(), io::Error> {
+        self.before(true)?;
+        self.inner.send_deadline(priority, msg, deadline)
+    }
+    fn recv_timeout(&self,  msgbuf: &mut [u8],  timeout: Duration)
+    -> Result<(u32, usize), io::Error> {
+        self.before(false)?;
+        self.inner.recv_timeout(msgbuf, timeout)
+    }
+    fn recv_deadline(&self,  msgbuf: &mut [u8],  deadline: SystemTime)
+    -> Result<(u32, usize), io::Error> {
+        self.before(false)?;
+        self.inner.recv_deadline(msgbuf, deadline)
+    }
+    fn attributes(&self) -> Result<Attributes, io::Error> {
+        self.inner.attributes()
+    }
+}
+
+impl<Q: MessageQueue + Debug> Debug for FaultyMq<Q> {
+    fn fmt(&self,  fmtr: &mut Formatter) -> fmt::Result {
+        let faults = self.faults();
+        fmtr.debug_struct("FaultyMq")
+            .field("inner", &self.inner)
+            .field("scripted_sends", &faults.sends)
+            .field("scripted_recvs", &faults.recvs)
+            .field("random", &faults.random)
+            .field("injected", &faults.injected)
+            .finish()
+    }
+}
+
+
+/// Shared memory queues are named `/posixmq.` followed by the queue name,
+/// to not clobber unrelated shared memory objects.
+#[cfg(all(feature="shm", target_os="linux"))]
+fn with_shm_name<F: FnOnce(&CStr)->Result<R,io::Error>, R>(name: &[u8],  f: F)
+-> Result<R,io::Error> {
+    let name = if name.first() == Some(&b'/') {&name[1..]} else {name};
+    let mut prefixed = b"posixmq.".to_vec();
+    prefixed.extend_from_slice(name);
+    with_name_as_cstr(&prefixed, f)
+}
+
+/// `b"PMQSHM01"` as a little-endian integer, to detect unrelated or
+/// incompatible shared memory objects.
+#[cfg(all(feature="shm", target_os="linux"))]
+const SHM_MAGIC: u64 = 0x3130_4d48_534d_5150;
+
+/// The start of the shared memory.
+///
+/// Other processes can write to any part of the shared memory, so every
+/// field is atomic and nothing read from it is trusted.
+/// `magic`, `capacity`, `max_msg_len` and `mode` are stored before
+/// `initialized` is set and never changed afterwards.
+///
+/// The header is followed by `capacity` slot indexes, padded to a multiple of
+/// eight bytes, and then by the slots.
+/// The first `current_messages` indexes form a binary heap ordered by
+/// priority and then by sequence number, and the rest are the free slots.
+/// The indexes and slots are only accessed while `lock` is held.
+#[cfg(all(feature="shm", target_os="linux"))]
+#[repr(C)]
+struct ShmHeader {
+    magic: AtomicU64,
+    capacity: AtomicU32,
+    max_msg_len: AtomicU32,
+    initialized: AtomicU32,
+    /// futex-based mutex: 0 = unlocked, 1 = locked, 2 = locked with waiters
+    lock: AtomicU32,
+    /// incremented by every send, for waiting on with futex
+    sent: AtomicU32,
+    /// incremented by every receive, for waiting on with futex
+    received: AtomicU32,
+    current_messages: AtomicU32,
+    /// the permissions the queue was created with, see `shm_check_access()`
+    mode: AtomicU32,
+    sequence: AtomicU64,
+}
+
+/// Each slot contains this followed by `max_msg_len` rounded up to a
+/// multiple of eight bytes.
+#[cfg(all(feature="shm", target_os="linux"))]
+#[repr(C)]
+#[derive(Clone,Copy)]
+struct ShmSlot {
+    priority: u32,
+    len: u32,
+    sequence: u64,
+}
+
+#[cfg(all(feature="shm", target_os="linux"))]
+fn shm_slot_size(max_msg_len: usize) -> usize {
+    mem::size_of::<ShmSlot>() + max_msg_len + (8 - max_msg_len % 8) % 8
+}
+
+#[cfg(all(feature="shm", target_os="linux"))]
+fn shm_indexes_size(capacity: usize) -> usize {
+    (capacity + capacity % 2) * mem::size_of::<u32>()
+}
+
+/// Returns `None` on overflow.
+#[cfg(all(feature="shm", target_os="linux"))]
+fn shm_size(capacity: usize,  max_msg_len: usize) -> Option<usize> {
+    max_msg_len.checked_add(mem::size_of::<ShmSlot>() + 8)
+        .and_then(|_| capacity.checked_add(capacity % 2) )
+        .and_then(|indexes| indexes.checked_mul(mem::size_of::<u32>()) )
+        .and_then(|indexes| capacity.checked_mul(shm_slot_size(max_msg_len))
+            .and_then(|slots| slots.checked_add(indexes) )
+        )
+        .and_then(|size| size.checked_add(mem::size_of::<ShmHeader>()) )
+}
+
+#[cfg(all(feature="shm", target_os="linux"))]
+fn shm_corrupted() -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, "shared memory queue is corrupted")
+}
+
+/// Give both read and write permission to every class of users that has
+/// either in `mode`, as both sending and receiving modifies the shared memory.
+#[cfg(all(feature="shm", target_os="linux"))]
+fn shm_object_mode(mode: u32) -> u32 {
+    [0o700, 0o070, 0o007].iter()
+        .filter(|&&class| mode & class & 0o666 != 0 )
+        .fold(0, |object_mode, &class| object_mode | (class & 0o666) )
+}
+
+/// Check that the process is permitted to open the queue for `access`
+/// (`O_RDONLY`, `O_WRONLY` or `O_RDWR`) by the permissions it was created
+/// with, the same way the kernel checks it for posix message queues.
+#[cfg(all(feature="shm", target_os="linux"))]
+fn shm_check_access(fd: c_int,  mode: u32,  access: c_int) -> Result<(), io::Error> {
+    let mut stat: libc::stat = unsafe { mem::zeroed() };
+    if unsafe { fstat(fd, &mut stat) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let euid = unsafe { libc::geteuid() };
+    let in_group = || {
+        if unsafe { libc::getegid() } == stat.st_gid {
+            return true;
+        }
+        let count = unsafe { libc::getgroups(0, ptr::null_mut()) };
+        let mut groups = vec![0; cmp::max(count, 0) as usize];
+        let count = unsafe { libc::getgroups(groups.len() as c_int, groups.as_mut_ptr()) };
+        groups.truncate(cmp::max(count, 0) as usize);
+        groups.contains(&stat.st_gid)
+    };
+    let permitted = if euid == stat.st_uid {
+        mode >> 6
+    } else if in_group() {
+        mode >> 3
+    } else {
+        mode
+    };
+    let needed = match access {
+        O_RDONLY => 0o4,
+        O_WRONLY => 0o2,
+        _ => 0o6,
+    };
+    if euid == 0  ||  permitted & needed == needed {
+        Ok(())
+    } else {
+        Err(io::Error::from_raw_os_error(libc::EACCES))
+    }
+}
+
+/// Sleep until `word` no longer contains `expected`, is woken, or the timeout
+/// expires, whichever comes first.
+///
+/// Spurious wakeups and interruptions are not errors.
+#[cfg(all(feature="shm", target_os="linux"))]
+fn futex_wait(word: &AtomicU32,  expected: u32,  timeout: Option<Duration>)
+-> Result<(), io::Error> {
+    let mut ts: timespec = unsafe { mem::zeroed() };
+    let ts_ptr = match timeout {
+        Some(timeout) => {
+            ts.tv_sec = cmp::min(timeout.as_secs(), time_t::max_value() as u64) as time_t;
+            ts.tv_nsec = timeout.subsec_nanos() as KernelLong;
+            &ts as *const timespec
+        }
+        None => ptr::null(),
+    };
+    // not FUTEX_PRIVATE_FLAG because the memory is shared between processes
+    let ret = unsafe { libc::syscall(
+        libc::SYS_futex,
+        word as *const AtomicU32,
+        libc::FUTEX_WAIT,
+        expected,
+        ts_ptr,
+        ptr::null::<u32>(),
+        0,
+    ) };
+    if ret == -1 {
+        let error = io::Error::last_os_error();
+        match error.raw_os_error() {
+            Some(EAGAIN) | Some(EINTR) | Some(ETIMEDOUT) => {}
+            _ => return Err(error),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(feature="shm", target_os="linux"))]
+fn futex_wake(word: &AtomicU32,  waiters: c_int) {
+    unsafe { libc::syscall(libc::SYS_futex, word as *const AtomicU32, libc::FUTEX_WAKE, waiters) };
+}
+
+/// A message queue in shared memory, for when posix message queues are not
+/// available, or their capacity limits are too low.
+///
+/// Created by [`OpenOptions::open_shm()`](struct.OpenOptions.html#method.open_shm),
+/// and removed with [`remove_shm_queue()`](fn.remove_shm_queue.html).
+///
+/// The queue is a `shm_open()`ed object with a header and a fixed number of
+/// slots, synchronized with futexes so that multiple processes can send and
+/// receive concurrently.
+/// Messages are received in the same order as from a posix message queue,
+/// and errors mirror what Linux returns for posix message queues.
+/// Sending and receiving uses the [`MessageQueue`](trait.MessageQueue.html)
+/// trait.
+///
+/// Differences from posix message queues:
+///
+/// * The queue is named `/posixmq.` followed by the name, and is visible in
+///   `/dev/shm/`.
+/// * There is no capacity limit apart from available memory.
+/// * Both sending and receiving modifies the shared memory, so the shared
+///   memory object is made readable and writable for every class of users
+///   that the mode permits either for.
+///   The mode is stored in the queue and checked when opening it, but is not
+///   enforced against processes that map the object directly.
+/// * A process that dies while sending or receiving can leave the queue
+///   locked forever.
+/// * Cannot be used with `poll()` or mio.
+///
+/// This type is only available on Linux with the `shm` feature enabled,
+/// which requires Rust 1.34.
+///
+/// # Examples
+///
+/// ```
+/// use posixmq::{MessageQueue, OpenOptions, remove_shm_queue};
+///
+/// let mq = OpenOptions::readwrite()
+///     .capacity(100)
+///     .max_msg_len(10)
+///     .create_new()
+///     .open_shm("/shm_example")
+///     .unwrap();
+/// remove_shm_queue("/shm_example").unwrap();
+/// for n in 0..100 {
+///     mq.send(n % 10, b"above 10").unwrap();
+/// }
+/// assert_eq!(mq.attributes().unwrap().current_messages, 100);
+/// ```
+#[cfg(all(feature="shm", target_os="linux"))]
+pub struct ShmMq {
+    map: *mut u8,
+    map_len: usize,
+    capacity: usize,
+    max_msg_len: usize,
+    readable: bool,
+    writable: bool,
+    nonblocking: AtomicBool,
+}
+
+// the shared memory is only accessed through atomics or while holding the lock
+#[cfg(all(feature="shm", target_os="linux"))]
+unsafe impl Send for ShmMq {}
+#[cfg(all(feature="shm", target_os="linux"))]
+unsafe impl Sync for ShmMq {}
+
+#[cfg(all(feature="shm", target_os="linux"))]
+impl OpenOptions {
+    /// Open or create a shared memory queue with the specified options.
+    ///
+    /// If capacity and max_msg_len are both zero, a new queue is created
+    /// with the same defaults as on Linux: 10 messages of up to 8192 bytes.
+    ///
+    /// This function is only available on Linux with the `shm` feature.
+    ///
+    /// # Errors
+    ///
+    /// * Queue doesn't exist (ENOENT) => `ErrorKind::NotFound`
+    /// * Queue already exists (EEXISTS) => `ErrorKind::AlreadyExists`
+    /// * Not permitted to open in this mode (EACCESS) => `ErrorKind::PermissionDenied`
+    /// * Only one of capacity and max_msg_len is zero (EINVAL) => `ErrorKind::InvalidInput`
+    /// * The shared memory object is not a queue created by this library => `ErrorKind::InvalidData`
+    /// * Name contains '\0' => `ErrorKind::InvalidInput`
+    /// * Possibly other
+    pub fn open_shm<N: AsRef<[u8]> + ?Sized>(&self,  name: &N) -> Result<ShmMq, io::Error> {
+        with_shm_name(name.as_ref(), |name| self.open_shm_c(name) )
+    }
+
+    fn open_shm_c(&self,  name: &CStr) -> Result<ShmMq, io::Error> {
+        let (capacity, max_msg_len) = match (self.capacity, self.max_msg_len) {
+            (0, 0) => (10, 8192),
+            (0, _) | (_, 0) => return Err(io::Error::from_raw_os_error(EINVAL)),
+            (capacity, max_msg_len) if capacity <= u32::max_value() as usize
+                &&  max_msg_len <= u32::max_value() as usize => (capacity, max_msg_len),
+            _ => return Err(io::Error::from_raw_os_error(EINVAL)),
+        };
+        let size = match shm_size(capacity, max_msg_len) {
+            Some(size) if size as u64 <= libc::off_t::max_value() as u64 => size,
+            _ => return Err(io::Error::from_raw_os_error(EINVAL)),
+        };
+        let access = self.flags & O_ACCMODE;
+        // the shared memory object must be opened for both reading and
+        // writing to be able to map it writable; the requested access is
+        // checked against the mode stored in the queue instead.
+        let open = |flags, mode| {
+            let fd = unsafe { libc::shm_open(name.as_ptr(), flags | O_RDWR | libc::O_CLOEXEC, mode) };
+            if fd == -1 {Err(io::Error::last_os_error())} else {Ok(fd)}
+        };
+
+        let created = if self.flags & O_CREAT != 0 {
+            match open(O_CREAT | O_EXCL, self.mode & 0o777) {
+                Ok(fd) => Some(fd),
+                Err(ref e) if e.kind() == ErrorKind::AlreadyExists  &&  self.flags & O_EXCL == 0 => None,
+                Err(e) => return Err(e),
+            }
+        } else {
+            None
+        };
+        let mut mq = match created {
+            Some(fd) => {
+                let mq = ShmMq::create(fd, size, capacity, max_msg_len);
+                unsafe { libc::close(fd) };
+                if mq.is_err() {
+                    unsafe { libc::shm_unlink(name.as_ptr()) };
+                }
+                mq
+            }
+            None => {
+                let fd = open(0, 0)?;
+                let mq = ShmMq::map_existing(fd).and_then(|mq| {
+                    let mode = mq.header().mode.load(atomic::Ordering::Relaxed);
+                    shm_check_access(fd, mode, access).map(|_| mq )
+                });
+                unsafe { libc::close(fd) };
+                mq
+            }
+        }?;
+        mq.readable = access == O_RDONLY  ||  access == O_RDWR;
+        mq.writable = access == O_WRONLY  ||  access == O_RDWR;
+        mq.nonblocking = AtomicBool::new(self.flags & O_NONBLOCK != 0);
+        Ok(mq)
+    }
+
+    /// Open a posix message queue, or a shared memory queue if posix message
+    /// queues are not supported by the kernel.
+    ///
+    /// The fallback happens if opening the posix message queue fails with
+    /// ENOSYS, and the shared memory queue is opened with the same options.
+    ///
+    /// This function is only available on Linux with the `shm` feature.
+    pub fn open_any<N: AsRef<[u8]> + ?Sized>(&self,  name: &N) -> Result<AnyMq, io::Error> {
+        match self.open(name) {
+            Err(ref e) if e.raw_os_error() == Some(libc::ENOSYS) => self.open_shm(name).map(AnyMq::Shm),
+            result => result.map(AnyMq::Posix),
+        }
+    }
+}
+
+/// Delete a shared memory queue.
+///
+/// Processes that have it open will still be able to use it.
+///
+/// This function is only available on Linux with the `shm` feature.
+///
+/// # Errors
+///
+/// * Queue doesn't exist (ENOENT) => `ErrorKind::NotFound`
+/// * Not permitted to delete the queue (EACCES) => `ErrorKind::PermissionDenied`
+/// * Name contains '\0' bytes => `ErrorKind::InvalidInput`
+/// * Possibly other
+#[cfg(all(feature="shm", target_os="linux"))]
+pub fn remove_shm_queue<N: AsRef<[u8]> + ?Sized>(name: &N) -> Result<(), io::Error> {
+    with_shm_name(name.as_ref(), |name| {
+        if unsafe { libc::shm_unlink(name.as_ptr()) } == -1 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    })
+}
+
+#[cfg(all(feature="shm", target_os="linux"))]
+impl ShmMq {
+    fn map(fd: c_int,  len: usize) -> Result<*mut u8, io::Error> {
+        let map = unsafe { libc::mmap(
+            ptr::null_mut(),
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED,
+            fd,
+            0,
+        ) };
+        if map == libc::MAP_FAILED {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(map as *mut u8)
+        }
+    }
+
+    /// Initialize a newly created shared memory object.
+    fn create(fd: c_int,  size: usize,  capacity: usize,  max_msg_len: usize)
+    -> Result<Self, io::Error> {
+        // store the mode with umask applied, and then let everybody who can
+        // open the queue in some way read and write the shared memory
+        let mut stat: libc::stat = unsafe { mem::zeroed() };
+        if unsafe { fstat(fd, &mut stat) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let mode = stat.st_mode & 0o777;
+        if unsafe { libc::fchmod(fd, shm_object_mode(mode)) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        if unsafe { libc::ftruncate(fd, size as libc::off_t) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let map = ShmMq::map(fd, size)?;
+        let mq = ShmMq {
+            map,
+            map_len: size,
+            capacity,
+            max_msg_len,
+            readable: false,
+            writable: false,
+            nonblocking: AtomicBool::new(false),
+        };
+        // the memory is zeroed by ftruncate(), which is a valid state for
+        // the atomics, and nobody else uses the queue before it's initialized
+        let header = mq.header();
+        header.magic.store(SHM_MAGIC, atomic::Ordering::Relaxed);
+        header.capacity.store(capacity as u32, atomic::Ordering::Relaxed);
+        header.max_msg_len.store(max_msg_len as u32, atomic::Ordering::Relaxed);
+        header.mode.store(mode, atomic::Ordering::Relaxed);
+        for slot in 0..capacity {
+            mq.set_index(slot, slot);
+        }
+        header.initialized.store(1, atomic::Ordering::Release);
+        Ok(mq)
+    }
+
+    /// Map a queue created by another process, waiting up to a second for it
+    /// to be initialized.
+    fn map_existing(fd: c_int) -> Result<Self, io::Error> {
+        let not_a_queue = || io::Error::new(ErrorKind::InvalidData, "not a posixmq shared memory queue");
+        for _ in 0..1000 {
+            let mut stat: libc::stat = unsafe { mem::zeroed() };
+            if unsafe { fstat(fd, &mut stat) } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            let size = stat.st_size as usize;
+            if size < mem::size_of::<ShmHeader>() {
+                thread::sleep(Duration::from_millis(1));
+                continue;
+            }
+            let map = ShmMq::map(fd, size)?;
+            let header = unsafe { &*(map as *const ShmHeader) };
+            if header.initialized.load(atomic::Ordering::Acquire) == 0 {
+                unsafe { libc::munmap(map as *mut c_void, size) };
+                thread::sleep(Duration::from_millis(1));
+                continue;
+            }
+            let magic = header.magic.load(atomic::Ordering::Relaxed);
+            let capacity = header.capacity.load(atomic::Ordering::Relaxed) as usize;
+            let max_msg_len = header.max_msg_len.load(atomic::Ordering::Relaxed) as usize;
+            // unmaps on error
+            let mq = ShmMq {
+                map,
+                map_len: size,
+                capacity,
+                max_msg_len,
+                readable: false,
+                writable: false,
+                nonblocking: AtomicBool::new(false),
+            };
+            if magic != SHM_MAGIC  ||  capacity == 0  ||  max_msg_len == 0
+            ||  shm_size(capacity, max_msg_len) != Some(size) {
+                return Err(not_a_queue());
+            }
+            return Ok(mq);
+        }
+        Err(not_a_queue())
+    }
+
+    fn header(&self) -> &ShmHeader {
+        unsafe { &*(self.map as *const ShmHeader) }
+    }
+
+    /// Get the slot index at a position in the heap or the free part,
+    /// checking that it's a valid slot.
+    /// Must hold the lock.
+    fn index(&self,  position: usize) -> Result<usize, io::Error> {
+        debug_assert!(position < self.capacity);
+        let offset = mem::size_of::<ShmHeader>() + position * mem::size_of::<u32>();
+        let slot = unsafe { ptr::read_volatile(self.map.add(offset) as *const u32) } as usize;
+        if slot < self.capacity {Ok(slot)} else {Err(shm_corrupted())}
+    }
+
+    fn set_index(&self,  position: usize,  slot: usize) {
+        debug_assert!(position < self.capacity);
+        let offset = mem::size_of::<ShmHeader>() + position * mem::size_of::<u32>();
+        unsafe { ptr::write_volatile(self.map.add(offset) as *mut u32, slot as u32) };
+    }
+
+    /// The slot header, followed by the message.
+    fn slot(&self,  slot: usize) -> *mut ShmSlot {
+        let offset = mem::size_of::<ShmHeader>()
+            + shm_indexes_size(self.capacity)
+            + slot * shm_slot_size(self.max_msg_len);
+        unsafe { self.map.add(offset) as *mut ShmSlot }
+    }
+
+    /// Check whether the message at heap position `a` should be received
+    /// before the one at `b`.
+    /// Must hold the lock.
+    fn before(&self,  a: usize,  b: usize) -> Result<bool, io::Error> {
+        let a = unsafe { ptr::read_volatile(self.slot(self.index(a)?)) };
+        let b = unsafe { ptr::read_volatile(self.slot(self.index(b)?)) };
+        Ok(a.priority > b.priority  ||  (a.priority == b.priority  &&  a.sequence < b.sequence))
+    }
+
+    fn swap_indexes(&self,  a: usize,  b: usize) -> Result<(), io::Error> {
+        let (slot_a, slot_b) = (self.index(a)?, self.index(b)?);
+        self.set_index(a, slot_b);
+        self.set_index(b, slot_a);
+        Ok(())
+    }
+
+    /// Get the number of queued messages, checking that it's not above
+    /// capacity.
+    /// Must hold the lock.
+    fn current_messages(&self) -> Result<usize, io::Error> {
+        let current = self.header().current_messages.load(atomic::Ordering::Relaxed) as usize;
+        if current <= self.capacity {Ok(current)} else {Err(shm_corrupted())}
+    }
+
+    fn lock(&self) {
+        let lock = &self.header().lock;
+        let mut state = match lock.compare_exchange(0, 1, atomic::Ordering::Acquire, atomic::Ordering::Relaxed) {
+            Ok(unlocked) => unlocked,
+            Err(locked) => locked,
+        };
+        if state != 0 {
+            if state != 2 {
+                state = lock.swap(2, atomic::Ordering::Acquire);
+            }
+            while state != 0 {
+                // lock errors would be bugs in this library
+                let _ = futex_wait(lock, 2, None);
+                state = lock.swap(2, atomic::Ordering::Acquire);
+            }
+        }
+    }
+
+    fn unlock(&self) {
+        let lock = &self.header().lock;
+        if lock.fetch_sub(1, atomic::Ordering::Release) != 1 {
+            lock.store(0, atomic::Ordering::Release);
+            futex_wake(lock, 1);
+        }
+    }
+
+    /// Lock, and wait until `ready` returns true. Returns with the lock held.
+    fn wait<F: Fn(&ShmHeader)->bool>(&self,  wait_on: &AtomicU32,  deadline: Option<SystemTime>,
+            ready: F,
+    ) -> Result<(), io::Error> {
+        loop {
+            self.lock();
+            if ready(self.header()) {
+                return Ok(());
+            }
+            let seen = wait_on.load(atomic::Ordering::Relaxed);
+            self.unlock();
+            if self.is_nonblocking() {
+                return Err(io::Error::from_raw_os_error(EAGAIN));
+            }
+            let timeout = match deadline {
+                None => None,
+                Some(deadline) => match deadline.duration_since(SystemTime::now()) {
+                    Ok(remaining) if remaining > Duration::new(0, 0) => Some(remaining),
+                    _ => return Err(io::Error::from_raw_os_error(ETIMEDOUT)),
+                }
+            };
+            futex_wait(wait_on, seen, timeout)?;
+        }
+    }
+
+    fn send_until(&self,  priority: u32,  msg: &[u8],  deadline: Option<SystemTime>)
+    -> Result<(), io::Error> {
+        if !self.writable {
+            return Err(io::Error::from_raw_os_error(EBADF));
+        } else if msg.len() > self.max_msg_len {
+            return Err(io::Error::from_raw_os_error(EMSGSIZE));
+        } else if priority > EMULATED_MAX_PRIORITY {
+            return Err(io::Error::from_raw_os_error(EINVAL));
+        }
+        let header = self.header();
+        let capacity = self.capacity;
+        self.wait(&header.received, deadline,
+            |header| (header.current_messages.load(atomic::Ordering::Relaxed) as usize) < capacity
+        )?;
+        let result = self.push(priority, msg);
+        self.unlock();
+        if result.is_ok() {
+            futex_wake(&header.sent, c_int::max_value());
+        }
+        result
+    }
+
+    /// Add a message to the heap, with the lock held and space available.
+    fn push(&self,  priority: u32,  msg: &[u8]) -> Result<(), io::Error> {
+        let header = self.header();
+        let mut position = self.current_messages()?;
+        if position == self.capacity {
+            return Err(shm_corrupted());
+        }
+        unsafe {
+            let slot = self.slot(self.index(position)?);
+            let msg_ptr = (slot as *mut u8).add(mem::size_of::<ShmSlot>());
+            ptr::copy_nonoverlapping(msg.as_ptr(), msg_ptr, msg.len());
+            ptr::write_volatile(slot, ShmSlot {
+                priority,
+                len: msg.len() as u32,
+                sequence: header.sequence.fetch_add(1, atomic::Ordering::Relaxed),
+            });
+        }
+        header.current_messages.store(position as u32 + 1, atomic::Ordering::Relaxed);
+        header.sent.fetch_add(1, atomic::Ordering::Relaxed);
+        while position > 0  &&  self.before(position, (position-1)/2)? {
+            self.swap_indexes(position, (position-1)/2)?;
+            position = (position-1)/2;
+        }
+        Ok(())
+    }
+
+    fn recv_until(&self,  msgbuf: &mut [u8],  deadline: Option<SystemTime>)
+    -> Result<(u32, usize), io::Error> {
+        if !self.readable {
+            return Err(io::Error::from_raw_os_error(EBADF));
+        } else if msgbuf.len() < self.max_msg_len {
+            return Err(io::Error::from_raw_os_error(EMSGSIZE));
+        }
+        let header = self.header();
+        self.wait(&header.sent, deadline,
+            |header| header.current_messages.load(atomic::Ordering::Relaxed) != 0
+        )?;
+        let result = self.pop(msgbuf);
+        self.unlock();
+        if result.is_ok() {
+            futex_wake(&header.received, c_int::max_value());
+        }
+        result
+    }
+
+    /// Remove the highest priority and then oldest message from the heap,
+    /// with the lock held and at least one message queued.
+    fn pop(&self,  msgbuf: &mut [u8]) -> Result<(u32, usize), io::Error> {
+        let header = self.header();
+        let current = self.current_messages()?;
+        if current == 0 {
+            return Err(shm_corrupted());
+        }
+        let slot = self.slot(self.index(0)?);
+        let message = unsafe { ptr::read_volatile(slot) };
+        let len = message.len as usize;
+        if len > self.max_msg_len {
+            return Err(shm_corrupted());
+        }
+        unsafe {
+            let msg_ptr = (slot as *const u8).add(mem::size_of::<ShmSlot>());
+            ptr::copy_nonoverlapping(msg_ptr, msgbuf.as_mut_ptr(), len);
+        }
+        // the received slot becomes the first free one
+        let last = current - 1;
+        self.swap_indexes(0, last)?;
+        header.current_messages.store(last as u32, atomic::Ordering::Relaxed);
+        header.received.fetch_add(1, atomic::Ordering::Relaxed);
+        let mut position = 0;
+        loop {
+            let left = 2*position + 1;
+            let right = left + 1;
+            if left >= last {
+                break;
+            }
+            let child = if right < last  &&  self.before(right, left)? {right} else {left};
+            if !self.before(child, position)? {
+                break;
+            }
+            self.swap_indexes(position, child)?;
+            position = child;
+        }
+        Ok((message.priority, len))
+    }
+
+    /// Check whether this handle is in nonblocking mode.
+    pub fn is_nonblocking(&self) -> bool {
+        self.nonblocking.load(atomic::Ordering::Relaxed)
+    }
+
+    /// Enable or disable nonblocking mode for this handle.
+    pub fn set_nonblocking(&self,  nonblocking: bool) {
+        self.nonblocking.store(nonblocking, atomic::Ordering::Relaxed);
+    }
+}
+
+#[cfg(all(feature="shm", target_os="linux"))]
+impl MessageQueue for ShmMq {
+    fn send(&self,  priority: u32,  msg: &[u8]) -> Result<(), io::Error> {
+        self.send_until(priority, msg, None)
+    }
+    fn recv(&self,  msgbuf: &mut [u8]) -> Result<(u32, usize), io::Error> {
+        self.recv_until(msgbuf, None)
+    }
+    fn send_timeout(&self,  priority: u32,  msg: &[u8],  timeout: Duration)
+    -> Result<(), io::Error> {
+        let deadline = MemoryMq::timeout_to_deadline(timeout)?;
+        self.send_until(priority, msg, Some(deadline))
+    }
+    fn send_deadline(&self,  priority: u32,  msg: &[u8],  deadline: SystemTime)
+    -> Result<(), io::Error> {
+        self.send_until(priority, msg, Some(deadline))
+    }
+    fn recv_timeout(&self,  msgbuf: &mut [u8],  timeout: Duration)
+    -> Result<(u32, usize), io::Error> {
+        let deadline = MemoryMq::timeout_to_deadline(timeout)?;
+        self.recv_until(msgbuf, Some(deadline))
+    }
+    fn recv_deadline(&self,  msgbuf: &mut [u8],  deadline: SystemTime)
+    -> Result<(u32, usize), io::Error> {
+        self.recv_until(msgbuf, Some(deadline))
+    }
+    fn attributes(&self) -> Result<Attributes, io::Error> {
+        Ok(Attributes {
+            max_msg_len: self.max_msg_len,
+            capacity: self.capacity,
+            current_messages: self.header().current_messages.load(atomic::Ordering::Relaxed) as usize,
+            nonblocking: self.is_nonblocking(),
+            _private: ()
+        })
+    }
+}
+
+#[cfg(all(feature="shm", target_os="linux"))]
+impl Debug for ShmMq {
+    fn fmt(&self,  fmtr: &mut Formatter) -> fmt::Result {
+        fmtr.debug_struct("ShmMq")
+            .field("capacity", &self.capacity)
+            .field("max_msg_len", &self.max_msg_len)
+            .field("readable", &self.readable)
+            .field("writable", &self.writable)
+            .field("nonblocking", &self.is_nonblocking())
+            .finish()
+    }
+}
+
+#[cfg(all(feature="shm", target_os="linux"))]
+impl Drop for ShmMq {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.map as *mut c_void, self.map_len) };
+    }
+}
+
+/// Either a posix message queue or a shared memory queue.
+///
+/// Created by [`OpenOptions::open_any()`](struct.OpenOptions.html#method.open_any).
+///
+/// This type is only available on Linux with the `shm` feature.
+#[cfg(all(feature="shm", target_os="linux"))]
+#[derive(Debug)]
+pub enum AnyMq {
+    /// The kernel supports posix message queues.
+    Posix(PosixMq),
+    /// The fallback.
+    Shm(ShmMq),
+}
+
+#[cfg(all(feature="shm", target_os="linux"))]
+impl AnyMq {
+    fn get(&self) -> &dyn MessageQueue {
+        match *self {
+            AnyMq::Posix(ref mq) => mq,
+            AnyMq::Shm(ref mq) => mq,
+        }
+    }
+}
+
+#[cfg(all(feature="shm", target_os="linux"))]
+impl MessageQueue for AnyMq {
+    fn send(&self,  priority: u32,  msg: &[u8]) -> Result<(), io::Error> {
+        self.get().send(priority, msg)
+    }
+    fn recv(&self,  msgbuf: &mut [u8]) -> Result<(u32, usize), io::Error> {
+        self.get().recv(msgbuf)
+    }
+    fn send_timeout(&self,  priority: u32,  msg: &[u8],  timeout: Duration)
+    -> Result<(), io::Error> {
+        self.get().send_timeout(priority, msg, timeout)
+    }
+    fn send_deadline(&self,  priority: u32,  msg: &[u8],  deadline: SystemTime)
+    -> Result<(), io::Error> {
+        self.get().send_deadline(priority, msg, deadline)
+    }
+    fn recv_timeout(&self,  msgbuf: &mut [u8],  timeout: Duration)
+    -> Result<(u32, usize), io::Error> {
+        self.get().recv_timeout(msgbuf, timeout)
+    }
+    fn recv_deadline(&self,  msgbuf: &mut [u8],  deadline: SystemTime)
+    -> Result<(u32, usize), io::Error> {
+        self.get().recv_deadline(msgbuf, deadline)
+    }
+    fn attributes(&self) -> Result<Attributes, io::Error> {
+        self.get().attributes()
+    }
+}
+
+
+/// A System V message queue, with the same interface as posix message queues.
+///
+/// Created by [`OpenOptions::open_sysv()`](struct.OpenOptions.html#method.open_sysv)
+/// or [`SysvMq::from_id()`](#method.from_id), and used through the
+/// [`MessageQueue`](trait.MessageQueue.html) trait.
+///
+/// By default the priority is used directly as the message type, so that
+/// processes which use `msgsnd()` and `msgrcv()` directly see the same
+/// numbers. Messages are then received lowest type first (like `msgrcv()`
+/// with a negative type argument), which is the opposite of posix message
+/// queues, and priority 0 is invalid because message types must be positive.
+///
+/// With [`set_direct_types(false)`](#method.set_direct_types), priorities are
+/// instead mapped to message types as `32768 - priority`, so that the highest
+/// priority is received first and priorities 0 to 32767 are supported like
+/// on Linux. Processes that use `msgsnd()` and `msgrcv()` directly must then
+/// use the same mapping.
+///
+/// Differences from posix message queues:
+///
+/// * Queues are identified by a `key_t` instead of a name, and are not
+///   closed; Remove them with [`remove()`](#method.remove).
+/// * `max_msg_len` is not a property of the queue, but of each `SysvMq`
+///   value. It defaults to the system limit `/proc/sys/kernel/msgmax`.
+/// * The capacity of the queue is in bytes. When creating a queue with
+///   `OpenOptions`, the byte limit is set to `capacity * max_msg_len`, and
+///   [`attributes()`](trait.MessageQueue.html#tymethod.attributes) divides the
+///   limit by `max_msg_len`.
+/// * Receiving from an empty queue in nonblocking mode (ENOMSG) is reported
+///   as EAGAIN / `ErrorKind::WouldBlock`.
+/// * System V message queues have no timed operations, so the timed variants
+///   retry in nonblocking mode every few milliseconds until the deadline.
+/// * Read-only and write-only modes are enforced by this type.
+///
+/// This type is only available on Linux.
+///
+/// # Examples
+///
+/// Bridging to a posix message queue:
+///
+/// ```
+/// use posixmq::{MessageQueue, OpenOptions, SysvMq};
+///
+/// let legacy = OpenOptions::readwrite().capacity(2).max_msg_len(10).open_sysv(0).unwrap();
+/// legacy.send(3, b"old").unwrap();
+/// let posix = posixmq::MemoryMq::new(2, 10).unwrap(); // or a PosixMq
+/// let mut buf = [0; 10];
+/// let (priority, len) = legacy.recv(&mut buf).unwrap();
+/// posix.send(priority, &buf[..len]).unwrap();
+/// assert_eq!(posix.recv(&mut buf).unwrap(), (3, 3));
+/// legacy.remove().unwrap();
+/// ```
+#[cfg(target_os="linux")]
+pub struct SysvMq {
+    id: c_int,
+    max_msg_len: usize,
+    readable: bool,
+    writable: bool,
+    nonblocking: AtomicBool,
+    direct_types: AtomicBool,
+}
+
+/// Message types are `SYSV_PRIORITY_BASE - priority` when not using direct
+/// types.
+#[cfg(target_os="linux")]
+const SYSV_PRIORITY_BASE: libc::c_long = EMULATED_MAX_PRIORITY as libc::c_long + 1;
+
+#[cfg(target_os="linux")]
+impl OpenOptions {
+    /// Open or create a System V message queue with the specified options.
+    ///
+    /// Use key 0 (`IPC_PRIVATE`) to always create a new queue.
+    /// If capacity is not zero and the queue is created, its byte limit is
+    /// set to `capacity * max_msg_len`.
+    ///
+    /// This function is only available on Linux.
+    ///
+    /// # Errors
+    ///
+    /// * Queue doesn't exist (ENOENT) => `ErrorKind::NotFound`
+    /// * Queue already exists (EEXISTS) => `ErrorKind::AlreadyExists`
+    /// * Not permitted to open the queue (EACCESS) => `ErrorKind::PermissionDenied`
+    /// * Not permitted to raise the byte limit (EPERM) => `ErrorKind::PermissionDenied`
+    /// * Too many queues (ENOSPC) => `ErrorKind::Other`
+    /// * Possibly other
+    pub fn open_sysv(&self,  key: libc::key_t) -> Result<SysvMq, io::Error> {
+        let max_msg_len = match self.max_msg_len {
+            0 => sysv_max_msg_len(),
+            max_msg_len => max_msg_len,
+        };
+        let get = |flags| {
+            let id = unsafe { libc::msgget(key, flags | (self.mode & 0o777) as c_int) };
+            if id == -1 {Err(io::Error::last_os_error())} else {Ok(id)}
+        };
+        let (id, created) = if self.flags & O_CREAT == 0 {
+            (get(0)?, false)
+        } else {
+            match get(libc::IPC_CREAT | libc::IPC_EXCL) {
+                Ok(id) => (id, true),
+                Err(ref e) if e.kind() == ErrorKind::AlreadyExists  &&  self.flags & O_EXCL == 0
+                    => (get(0)?, false),
+                Err(e) => return Err(e),
+            }
+        };
+        let access = self.flags & O_ACCMODE;
+        let mq = SysvMq {
+            id,
+            max_msg_len,
+            readable: access == O_RDONLY  ||  access == O_RDWR,
+            writable: access == O_WRONLY  ||  access == O_RDWR,
+            nonblocking: AtomicBool::new(self.flags & O_NONBLOCK != 0),
+            direct_types: AtomicBool::new(true),
+        };
+        if created  &&  self.capacity != 0 {
+            if let Err(e) = mq.set_byte_limit(self.capacity.saturating_mul(max_msg_len)) {
+                let _ = mq.remove();
+                return Err(e);
+            }
+        }
+        Ok(mq)
+    }
+}
+
+/// Read the system limit, or use Linux's default if that fails.
+#[cfg(target_os="linux")]
+fn sysv_max_msg_len() -> usize {
+    let mut limit = String::new();
+    let read = File::open("/proc/sys/kernel/msgmax").and_then(|mut f| f.read_to_string(&mut limit) );
+    read.ok().and_then(|_| limit.trim().parse().ok() ).unwrap_or(8192)
+}
+
+#[cfg(target_os="linux")]
+impl SysvMq {
+    /// Use an existing queue identifier, for example from `msgget()` or `ipcs`.
+    ///
+    /// The queue is used in read-write and blocking mode.
+    pub fn from_id(id: c_int,  max_msg_len: usize) -> Self {
+        SysvMq {
+            id,
+            max_msg_len,
+            readable: true,
+            writable: true,
+            nonblocking: AtomicBool::new(false),
+            direct_types: AtomicBool::new(true),
+        }
+    }
+
+    /// Get the queue identifier.
+    pub fn id(&self) -> c_int {
+        self.id
+    }
+
+    fn stat(&self) -> Result<libc::msqid_ds, io::Error> {
+        let mut stat: libc::msqid_ds = unsafe { mem::zeroed() };
+        if unsafe { libc::msgctl(self.id, libc::IPC_STAT, &mut stat) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(stat)
+    }
+
+    fn set_byte_limit(&self,  bytes: usize) -> Result<(), io::Error> {
+        let mut stat = self.stat()?;
+        stat.msg_qbytes = bytes as libc::msglen_t;
+        if unsafe { libc::msgctl(self.id, libc::IPC_SET, &mut stat) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Delete the queue.
+    ///
+    /// Unlike posix message queues, this immediately makes all further
+    /// operations on the queue fail, also for other processes.
+    pub fn remove(&self) -> Result<(), io::Error> {
+        if unsafe { libc::msgctl(self.id, libc::IPC_RMID, ptr::null_mut()) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Check whether this handle is in nonblocking mode.
+    pub fn is_nonblocking(&self) -> bool {
+        self.nonblocking.load(atomic::Ordering::Relaxed)
+    }
+
+    /// Enable or disable nonblocking mode for this handle.
+    pub fn set_nonblocking(&self,  nonblocking: bool) {
+        self.nonblocking.store(nonblocking, atomic::Ordering::Relaxed);
+    }
+
+    /// Check whether priorities are used directly as message types.
+    pub fn is_using_direct_types(&self) -> bool {
+        self.direct_types.load(atomic::Ordering::Relaxed)
+    }
+
+    /// Use priorities directly as message types and receive the lowest type
+    /// first (the default), or map them so that the highest priority is
+    /// received first.
+    ///
+    /// All processes using the queue should use the same mapping.
+    /// See the [type documentation](struct.SysvMq.html) for details.
+    pub fn set_direct_types(&self,  direct: bool) {
+        self.direct_types.store(direct, atomic::Ordering::Relaxed);
+    }
+
+    /// The largest type received in direct mode, as larger ones cannot be
+    /// returned as priorities.
+    fn max_direct_type() -> libc::c_long {
+        cmp::min(u32::max_value() as u64, libc::c_long::max_value() as u64) as libc::c_long
+    }
+
+    /// Call `op` with IPC_NOWAIT until it doesn't fail with EAGAIN, or the
+    /// deadline is reached.
+    fn poll_until<R, F>(&self,  deadline: SystemTime,  mut op: F) -> Result<R, io::Error>
+    where F: FnMut(c_int)->Result<R, io::Error> {
+        let mut delay = Duration::from_millis(1);
+        loop {
+            match op(libc::IPC_NOWAIT) {
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock  &&  !self.is_nonblocking() => {}
+                result => return result,
+            }
+            match deadline.duration_since(SystemTime::now()) {
+                Ok(remaining) if remaining > Duration::new(0, 0) => {
+                    thread::sleep(cmp::min(delay, remaining));
+                    delay = cmp::min(delay * 2, Duration::from_millis(10));
+                }
+                _ => return Err(io::Error::from_raw_os_error(ETIMEDOUT)),
+            }
+        }
+    }
+
+    fn send_flags(&self,  priority: u32,  msg: &[u8],  flags: c_int) -> Result<(), io::Error> {
+        if !self.writable {
+            return Err(io::Error::from_raw_os_error(EBADF));
+        } else if msg.len() > self.max_msg_len {
+            return Err(io::Error::from_raw_os_error(EMSGSIZE));
+        }
+        let mtype = if self.is_using_direct_types() {
+            if priority == 0  ||  priority as u64 > SysvMq::max_direct_type() as u64 {
+                return Err(io::Error::from_raw_os_error(EINVAL));
+            }
+            priority as libc::c_long
+        } else if priority > EMULATED_MAX_PRIORITY {
+            return Err(io::Error::from_raw_os_error(EINVAL));
+        } else {
+            SYSV_PRIORITY_BASE - priority as libc::c_long
+        };
+        // struct msgbuf {long mtype; char mtext[];}
+        let mut buf = vec![0 as libc::c_long; 2 + msg.len() / mem::size_of::<libc::c_long>()];
+        buf[0] = mtype;
+        unsafe {
+            let text = buf.as_mut_ptr().add(1) as *mut u8;
+            ptr::copy_nonoverlapping(msg.as_ptr(), text, msg.len());
+        }
+        let flags = if self.is_nonblocking() {flags | libc::IPC_NOWAIT} else {flags};
+        let buf_ptr = buf.as_ptr() as *const c_void;
+        retry_if_interrupted!(true, unsafe { libc::msgsnd(self.id, buf_ptr, msg.len(), flags) });
+        Ok(())
+    }
+
+    fn recv_flags(&self,  msgbuf: &mut [u8],  flags: c_int) -> Result<(u32, usize), io::Error> {
+        if !self.readable {
+            return Err(io::Error::from_raw_os_error(EBADF));
+        } else if msgbuf.len() < self.max_msg_len {
+            return Err(io::Error::from_raw_os_error(EMSGSIZE));
+        }
+        let mut buf = vec![0 as libc::c_long; 2 + msgbuf.len() / mem::size_of::<libc::c_long>()];
+        let flags = if self.is_nonblocking() {flags | libc::IPC_NOWAIT} else {flags};
+        let buf_ptr = buf.as_mut_ptr() as *mut c_void;
+        let direct = self.is_using_direct_types();
+        // receive the lowest type up to this
+        let max_type = if direct {SysvMq::max_direct_type()} else {SYSV_PRIORITY_BASE};
+        let len = loop {
+            let len = unsafe {
+                libc::msgrcv(self.id, buf_ptr, msgbuf.len(), -max_type, flags)
+            };
+            if len != -1 {
+                break len as usize;
+            }
+            let error = io::Error::last_os_error();
+            match error.raw_os_error() {
+                Some(EINTR) => continue,
+                Some(libc::ENOMSG) => return Err(io::Error::from_raw_os_error(EAGAIN)),
+                _ => return Err(error),
+            }
+        };
+        unsafe {
+            let text = buf.as_ptr().add(1) as *const u8;
+            ptr::copy_nonoverlapping(text, msgbuf.as_mut_ptr(), len);
+        }
+        if direct {
+            Ok((buf[0] as u32, len))
+        } else {
+            Ok(((SYSV_PRIORITY_BASE - buf[0]) as u32, len))
+        }
+    }
+}
+
+#[cfg(target_os="linux")]
+impl MessageQueue for SysvMq {
+    fn send(&self,  priority: u32,  msg: &[u8]) -> Result<(), io::Error> {
+        self.send_flags(priority, msg, 0)
+    }
+    fn recv(&self,  msgbuf: &mut [u8]) -> Result<(u32, usize), io::Error> {
+        self.recv_flags(msgbuf, 0)
+    }
+    fn send_timeout(&self,  priority: u32,  msg: &[u8],  timeout: Duration)
+    -> Result<(), io::Error> {
+        let deadline = MemoryMq::timeout_to_deadline(timeout)?;
+        self.send_deadline(priority, msg, deadline)
+    }
+    fn send_deadline(&self,  priority: u32,  msg: &[u8],  deadline: SystemTime)
+    -> Result<(), io::Error> {
+        self.poll_until(deadline, |flags| self.send_flags(priority, msg, flags) )
+    }
+    fn recv_timeout(&self,  msgbuf: &mut [u8],  timeout: Duration)
+    -> Result<(u32, usize), io::Error> {
+        let deadline = MemoryMq::timeout_to_deadline(timeout)?;
+        self.recv_deadline(msgbuf, deadline)
+    }
+    fn recv_deadline(&self,  msgbuf: &mut [u8],  deadline: SystemTime)
+    -> Result<(u32, usize), io::Error> {
+        self.poll_until(deadline, |flags| self.recv_flags(msgbuf, flags) )
+    }
+    fn attributes(&self) -> Result<Attributes, io::Error> {
+        let stat = self.stat()?;
+        Ok(Attributes {
+            max_msg_len: self.max_msg_len,
+            capacity: stat.msg_qbytes as usize / cmp::max(self.max_msg_len, 1),
+            current_messages: stat.msg_qnum as usize,
+            nonblocking: self.is_nonblocking(),
+            _private: ()
+        })
+    }
+}
+
+#[cfg(target_os="linux")]
+impl Debug for SysvMq {
+    fn fmt(&self,  fmtr: &mut Formatter) -> fmt::Result {
+        fmtr.debug_struct("SysvMq")
+            .field("id", &self.id)
+            .field("max_msg_len", &self.max_msg_len)
+            .field("readable", &self.readable)
+            .field("writable", &self.writable)
+            .field("nonblocking", &self.is_nonblocking())
+            .field("direct_types", &self.is_using_direct_types())
+            .finish()
+    }
+}
+
+
+/// Check that a descriptor received from elsewhere is a message queue,
+/// with `mq_getattr()`.
+#[cfg(any(target_os="linux", target_os="netbsd", target_os="dragonfly"))]
+fn check_queue_fd(fd: RawFd) -> Result<(), io::Error> {
+    let mut attrs = unsafe { mem::zeroed::<mq_attr>() };
+    if unsafe { mq_getattr(fd, &mut attrs) } == -1 {
+        let error = io::Error::last_os_error();
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("descriptor {} is not a message queue ({})", fd, error)
+        ));
+    }
+    Ok(())
+}
+
+/// Take ownership of a descriptor received from elsewhere, checking that it
+/// is a message queue before accepting it.
+///
+/// The descriptor is closed if it isn't.
+#[cfg(any(target_os="linux", target_os="netbsd", target_os="dragonfly"))]
+fn queue_from_owned_fd(fd: RawFd) -> Result<PosixMq, io::Error> {
+    if let Err(e) = check_queue_fd(fd) {
+        unsafe { libc::close(fd) };
+        return Err(e);
+    }
+    Ok(unsafe { PosixMq::from_raw_fd(fd) })
+}
+
+/// Send a message queue descriptor to another process over a Unix domain
+/// socket, with `SCM_RIGHTS`.
+///
+/// The receiving process gets its own descriptor for the queue, which shares
+/// nonblocking mode with this one like [`try_clone()`](struct.PosixMq.html#method.try_clone)d
+/// descriptors do. The queue can be sent even if it has been removed, and
+/// this process can close its descriptor as soon as this function returns.
+///
+/// One byte of regular data is sent along with the descriptor, so don't mix
+/// this with other data on the same stream unless the protocol accounts
+/// for it.
+///
+/// This function is only available on Linux, NetBSD and DragonFly BSD.
+///
+/// # Errors
+///
+/// * The socket is closed or not connected (EPIPE or ENOTCONN) => `ErrorKind::BrokenPipe` or `ErrorKind::NotConnected`
+/// * The socket is nonblocking and its buffer is full (EAGAIN) => `ErrorKind::WouldBlock`
+/// * Possibly other => `ErrorKind::Other`
+///
+/// # Examples
+///
+/// ```
+/// use std::os::unix::net::UnixStream;
+/// use std::thread;
+///
+/// let (broker, worker) = UnixStream::pair().unwrap();
+/// thread::spawn(move|| {
+///     let mq = posixmq::OpenOptions::readwrite()
+///         .capacity(1)
+///         .max_msg_len(10)
+///         .create_new()
+///         .open("/brokered")
+///         .unwrap();
+///     posixmq::remove_queue("/brokered").unwrap();
+///     mq.send(0, b"welcome").unwrap();
+///     posixmq::send_queue_over(&broker, &mq).unwrap();
+/// });
+///
+/// let mq = posixmq::recv_queue_from(&worker).unwrap();
+/// let mut buf = [0; 10];
+/// assert_eq!(mq.recv(&mut buf).unwrap(), (0, 7));
+/// ```
+#[cfg(any(target_os="linux", target_os="netbsd", target_os="dragonfly"))]
+pub fn send_queue_over(socket: &UnixStream,  mq: &PosixMq) -> Result<(), io::Error> {
+    let fd_len = mem::size_of::<RawFd>() as c_uint;
+    // u64 for alignment
+    let mut control = [0u64; 4];
+    let control_len = unsafe { libc::CMSG_SPACE(fd_len) } as usize;
+    debug_assert!(control_len <= mem::size_of_val(&control));
+    let mut data = [0u8];
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr() as *mut c_void,
+        iov_len: data.len(),
+    };
+    let mut msg = unsafe { mem::zeroed::<libc::msghdr>() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut c_void;
+    msg.msg_controllen = control_len as _;
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(fd_len) as _;
+        ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut RawFd, mq.as_raw_fd());
+    }
+    retry_if_interrupted!(true, unsafe { libc::sendmsg(socket.as_raw_fd(), &msg, 0) });
+    Ok(())
+}
+
+/// Receive a message queue descriptor sent with
+/// [`send_queue_over()`](fn.send_queue_over.html).
+///
+/// The received descriptor is checked to be a message queue with
+/// `mq_getattr()` before it's accepted, and has close-on-exec set.
+///
+/// This function is only available on Linux, NetBSD and DragonFly BSD.
+///
+/// # Errors
+///
+/// * The other end closed the socket => `ErrorKind::UnexpectedEof`
+/// * No descriptor was received => `ErrorKind::InvalidData`
+/// * The received descriptor is not a message queue => `ErrorKind::InvalidData`
+/// * The socket is nonblocking and nothing has been sent (EAGAIN) => `ErrorKind::WouldBlock`
+/// * Too many open files (EMFILE) => `ErrorKind::Other`
+/// * Possibly other => `ErrorKind::Other`
+#[cfg(any(target_os="linux", target_os="netbsd", target_os="dragonfly"))]
+pub fn recv_queue_from(socket: &UnixStream) -> Result<PosixMq, io::Error> {
+    let fd_len = mem::size_of::<RawFd>() as c_uint;
+    let mut control = [0u64; 4];
+    let mut data = [0u8];
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr() as *mut c_void,
+        iov_len: data.len(),
+    };
+    let mut msg = unsafe { mem::zeroed::<libc::msghdr>() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut c_void;
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+    // NetBSD has the same race as when opening, see set_cloexec()
+    #[cfg(any(target_os="linux", target_os="dragonfly"))]
+    let flags = libc::MSG_CMSG_CLOEXEC;
+    #[cfg(target_os="netbsd")]
+    let flags = 0;
+    let received = retry_if_interrupted!(true, unsafe {
+        libc::recvmsg(socket.as_raw_fd(), &mut msg, flags)
+    });
+
+    // collect all descriptors, so that extra ones can be closed
+    let mut fds = Vec::new();
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET  &&  (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let data = libc::CMSG_DATA(cmsg);
+                let data_len = (*cmsg).cmsg_len as usize - (data as usize - cmsg as usize);
+                for i in 0..data_len / fd_len as usize {
+                    fds.push(ptr::read_unaligned((data as *const RawFd).add(i)));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+    for &extra in fds.iter().skip(1) {
+        unsafe { libc::close(extra) };
+    }
+    match fds.first() {
+        Some(&fd) => {
+            let mq = queue_from_owned_fd(fd)?;
+            #[cfg(target_os="netbsd")]
+            mq.set_cloexec(true)?;
+            Ok(mq)
+        }
+        None if received == 0 => Err(io::Error::new(ErrorKind::UnexpectedEof, "socket is closed")),
+        None if msg.msg_flags & libc::MSG_CTRUNC != 0
+            => Err(io::Error::new(ErrorKind::InvalidData, "control message was truncated")),
+        None => Err(io::Error::new(ErrorKind::InvalidData, "no descriptor received")),
+    }
+}
+
+/// The environment variable [`InheritQueues`](trait.InheritQueues.html) uses
+/// to tell child processes which descriptors are which queues.
+#[cfg(any(target_os="linux", target_os="netbsd", target_os="dragonfly"))]
+const INHERITED_VAR: &str = "POSIXMQ_INHERITED";
+
+/// Extension trait for passing message queues to child processes.
+///
+/// Clearing close-on-exec with [`set_cloexec(false)`](struct.PosixMq.html#method.set_cloexec)
+/// makes the descriptor leak into every process spawned while it is cleared,
+/// including ones spawned by other threads.
+/// [`inherit_queues()`](#tymethod.inherit_queues) instead clears it in the
+/// child after forking, and tells the child which descriptor is which queue
+/// through the `POSIXMQ_INHERITED` environment variable.
+/// The child can then get the queues with
+/// [`PosixMq::from_inherited()`](struct.PosixMq.html#method.from_inherited).
+///
+/// The variable contains `name=fd` pairs separated by `:`, for example
+/// `jobs=5:results=6`.
+///
+/// This trait is only available on Linux, NetBSD and DragonFly BSD.
+///
+/// # Examples
+///
+/// ```no_run
+/// use posixmq::InheritQueues;
+/// use std::process::Command;
+///
+/// let jobs = posixmq::OpenOptions::writeonly().create().open("/jobs").unwrap();
+/// let mut child = Command::new("worker")
+///     .inherit_queues(&[("jobs", &jobs)])
+///     .spawn()
+///     .unwrap();
+/// jobs.send(0, b"first").unwrap();
+/// child.wait().unwrap();
+/// ```
+#[cfg(any(target_os="linux", target_os="netbsd", target_os="dragonfly"))]
+pub trait InheritQueues {
+    /// Keep the queues open in the child process, and set `POSIXMQ_INHERITED`
+    /// to describe them.
+    ///
+    /// The queues are duplicated with [`try_clone()`](struct.PosixMq.html#method.try_clone),
+    /// and the duplicates are kept open until the command is dropped,
+    /// so the originals can be closed before spawning.
+    /// All queues must be passed in one call: Calling this method again
+    /// replaces the variable, but the queues from earlier calls still have
+    /// close-on-exec cleared in the child, so they are leaked into it without
+    /// being listed.
+    ///
+    /// Names must be non-empty and cannot contain `=` or `:`; if one does,
+    /// spawning the command fails with EINVAL (`ErrorKind::InvalidInput`).
+    /// If duplicating a queue fails, spawning fails with that error.
+    fn inherit_queues(&mut self,  queues: &[(&str, &PosixMq)]) -> &mut Self;
+}
+
+#[cfg(any(target_os="linux", target_os="netbsd", target_os="dragonfly"))]
+impl InheritQueues for Command {
+    fn inherit_queues(&mut self,  queues: &[(&str, &PosixMq)]) -> &mut Self {
+        let mut var = String::new();
+        let mut clones = Vec::with_capacity(queues.len());
+        for &(name, mq) in queues {
+            // Report errors when spawning, like other Command errors.
+            // Use only the error code because creating an error with a
+            // message would allocate in the child.
+            let error = if name.is_empty()  ||  name.contains('=')  ||  name.contains(':') {
+                Some(EINVAL)
+            } else {
+                match mq.try_clone() {
+                    Ok(clone) => {
+                        if !var.is_empty() {
+                            var.push(':');
+                        }
+                        var.push_str(&format!("{}={}", name, clone.as_raw_fd()));
+                        clones.push(clone);
+                        None
+                    }
+                    Err(e) => Some(e.raw_os_error().unwrap_or(EINVAL)),
+                }
+            };
+            if let Some(errno) = error {
+                #[allow(deprecated)] // pre_exec() requires 1.34
+                unsafe { self.before_exec(move|| Err(io::Error::from_raw_os_error(errno)) ) };
+                return self;
+            }
+        }
+        self.env(INHERITED_VAR, var);
+        // Owns the clones so that the descriptors stay open until the command
+        // is dropped.
+        // Runs in the child between fork() and exec(), so it must not
+        // allocate; ioctl() is async-signal-safe.
+        #[allow(deprecated)] // pre_exec() requires 1.34
+        unsafe { self.before_exec(move|| {
+            for mq in &clones {
+                if ioctl(mq.as_raw_fd(), FIONCLEX) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        }) };
+        return self;
+    }
+}
+
+
+/// Receiving message queues created by systemd.
+///
+/// Units can have systemd create message queues with `ListenMessageQueue=`
+/// in a .socket unit, and pass them to the service when it's started.
+/// The queues are passed as descriptors starting at 3, described by the
+/// `LISTEN_PID`, `LISTEN_FDS` and `LISTEN_FDNAMES` environment variables.
+/// See [`man sd_listen_fds`](https://www.freedesktop.org/software/systemd/man/sd_listen_fds.html)
+/// for details.
+///
+/// This module is only available on Linux.
+///
+/// # Examples
+///
+/// ```no_run
+/// for (name, mq) in posixmq::activation::listen_queues().expect("invalid LISTEN_FDS") {
+///     println!("got queue {}: {:?}", name, mq.attributes());
+/// }
+/// ```
+#[cfg(target_os="linux")]
+pub mod activation {
+    use std::env;
+    use std::io::{self, ErrorKind};
+    use std::os::unix::io::{FromRawFd, RawFd};
+    use std::process;
+
+    use super::{PosixMq, check_queue_fd};
+
+    /// The first descriptor passed by systemd.
+    pub const LISTEN_FDS_START: RawFd = 3;
+
+    /// Take ownership of the message queues passed to this process by systemd,
+    /// together with their names.
+    ///
+    /// Descriptors that are not message queues (such as sockets from the same
+    /// .socket unit) are skipped and left open.
+    /// Returns an empty `Vec` if `LISTEN_PID` is not set or refers to another
+    /// process.
+    ///
+    /// The queues have close-on-exec set, and the environment variables are
+    /// removed, so that child processes don't also try to use the queues.
+    /// This function should therefore only be called once.
+    ///
+    /// # Errors
+    ///
+    /// * `LISTEN_PID` or `LISTEN_FDS` is invalid => `ErrorKind::InvalidData`
+    /// * Setting close-on-exec failed => `ErrorKind::Other`
+    pub fn listen_queues() -> Result<Vec<(String, PosixMq)>, io::Error> {
+        let var = |name| env::var(name).ok();
+        // the descriptors described by the variables belong to this process,
+        // and removing the variables prevents taking them twice
+        let queues = unsafe { listen_queues_from(
+            var("LISTEN_PID").as_ref().map(String::as_str),
+            var("LISTEN_FDS").as_ref().map(String::as_str),
+            var("LISTEN_FDNAMES").as_ref().map(String::as_str),
+            LISTEN_FDS_START,
+        ) };
+        env::remove_var("LISTEN_PID");
+        env::remove_var("LISTEN_FDS");
+        env::remove_var("LISTEN_FDNAMES");
+        queues
+    }
+
+    /// Parse the values of `LISTEN_PID`, `LISTEN_FDS` and `LISTEN_FDNAMES`
+    /// and take ownership of the message queues starting at `first_fd`.
+    ///
+    /// This is [`listen_queues()`](fn.listen_queues.html) without reading or
+    /// modifying the environment, and can be used to test code that expects
+    /// to be started by systemd.
+    ///
+    /// Queues without a name in `LISTEN_FDNAMES` are named `"unknown"`,
+    /// like systemd does for descriptors without `FileDescriptorName=`.
+    ///
+    /// # Safety
+    ///
+    /// If `listen_pid` is the current process, the descriptors from
+    /// `first_fd` up to `first_fd + listen_fds` must be owned by the caller
+    /// and not used or closed by anything else afterwards, as the message
+    /// queues among them are closed when the returned `PosixMq`s are dropped.
+    ///
+    /// # Errors
+    ///
+    /// * `listen_pid` or `listen_fds` is not a number => `ErrorKind::InvalidData`
+    /// * `listen_fds` is so high that the descriptors would overflow => `ErrorKind::InvalidData`
+    /// * Setting close-on-exec failed => `ErrorKind::Other`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::os::unix::io::IntoRawFd;
+    /// let mq = posixmq::OpenOptions::readwrite()
+    ///     .capacity(1)
+    ///     .max_msg_len(1)
+    ///     .create_new()
+    ///     .open("/activated")
+    ///     .unwrap();
+    /// posixmq::remove_queue("/activated").unwrap();
+    /// let fd = mq.into_raw_fd();
+    /// let pid = std::process::id().to_string();
+    /// let queues = unsafe { posixmq::activation::listen_queues_from(
+    ///     Some(&pid), Some("1"), Some("events"), fd
+    /// ) }.unwrap();
+    /// assert_eq!(queues[0].0, "events");
+    /// ```
+    pub unsafe fn listen_queues_from(
+            listen_pid: Option<&str>,  listen_fds: Option<&str>,  listen_fdnames: Option<&str>,
+            first_fd: RawFd,
+    ) -> Result<Vec<(String, PosixMq)>, io::Error> {
+        let invalid = |var: &str| io::Error::new(ErrorKind::InvalidData, format!("invalid {}", var));
+        let listen_pid = match listen_pid {
+            Some(pid) => pid.parse::<u32>().map_err(|_| invalid("LISTEN_PID") )?,
+            None => return Ok(Vec::new()),
+        };
+        if listen_pid != process::id() {
+            return Ok(Vec::new());
+        }
+        let count = match listen_fds {
+            Some(count) => count.parse::<RawFd>().map_err(|_| invalid("LISTEN_FDS") )?,
+            None => return Ok(Vec::new()),
+        };
+        if count < 0  ||  first_fd.checked_add(count).is_none() {
+            return Err(invalid("LISTEN_FDS"));
+        }
+        let mut names = listen_fdnames.unwrap_or("").split(':');
+
+        let mut queues = Vec::new();
+        for fd in first_fd..first_fd+count {
+            let name = match names.next() {
+                Some(name) if !name.is_empty() => name.to_string(),
+                _ => String::from("unknown"),
+            };
+            if check_queue_fd(fd).is_err() {
+                continue;
+            }
+            let mq = PosixMq::from_raw_fd(fd);
+            mq.set_cloexec(true)?;
+            queues.push((name, mq));
+        }
+        Ok(queues)
+    }
+}
+
+
 #[cfg(debug_assertions)]
 mod doctest_md_files {
     macro_rules! mdfile {($content:expr, $(#[$meta:meta])* $attach_to:ident) => {
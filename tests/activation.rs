@@ -0,0 +1,83 @@
+//! Tests of systemd socket activation, with a faked environment.
+
+#![cfg(target_os="linux")]
+
+use std::fs::File;
+use std::io::ErrorKind;
+use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
+use std::process;
+
+extern crate libc;
+extern crate posixmq;
+use posixmq::activation::{listen_queues, listen_queues_from};
+use posixmq::{OpenOptions, remove_queue};
+
+/// Move descriptors to consecutive numbers starting at `first`, like systemd
+/// passes them.
+///
+/// The numbers are high to not collide with other tests.
+fn place_fds(first: RawFd,  fds: Vec<RawFd>) {
+    for (i, fd) in fds.into_iter().enumerate() {
+        let target = first + i as RawFd;
+        assert_eq!(unsafe { libc::dup2(fd, target) }, target);
+        unsafe { libc::close(fd) };
+    }
+}
+
+fn tmp_mq_fd(name: &str) -> RawFd {
+    let mq = OpenOptions::readwrite().capacity(1).max_msg_len(8).create_new().open(name).unwrap();
+    let _ = remove_queue(name);
+    mq.set_cloexec(false).unwrap();
+    mq.into_raw_fd()
+}
+
+#[test]
+fn queues_are_named_and_validated() {
+    let first = 900;
+    let file = File::open("Cargo.toml").unwrap().into_raw_fd();
+    place_fds(first, vec![
+        tmp_mq_fd("/activation_a"),
+        file,
+        tmp_mq_fd("/activation_b"),
+        tmp_mq_fd("/activation_c"),
+    ]);
+    let pid = process::id().to_string();
+    let queues = unsafe { listen_queues_from(Some(&pid), Some("4"), Some("a:file:"), first) }.unwrap();
+    let names = queues.iter().map(|(name, _)| name.as_str() ).collect::<Vec<_>>();
+    assert_eq!(names, vec!["a", "unknown", "unknown"]);
+    let fds = queues.iter().map(|(_, mq)| mq.as_raw_fd() ).collect::<Vec<_>>();
+    assert_eq!(fds, vec![first, first+2, first+3]);
+    for (_, mq) in &queues {
+        assert!(mq.is_cloexec().unwrap());
+        mq.send(0, b"usable").unwrap();
+    }
+    // the file is left open
+    assert_ne!(unsafe { libc::fcntl(first+1, libc::F_GETFD) }, -1);
+    unsafe { libc::close(first+1) };
+}
+
+#[test]
+fn other_process_or_missing() {
+    let first = 910;
+    place_fds(first, vec![tmp_mq_fd("/activation_other")]);
+    let other = (process::id() + 1).to_string();
+    assert!(unsafe { listen_queues_from(Some(&other), Some("1"), None, first) }.unwrap().is_empty());
+    assert!(unsafe { listen_queues_from(None, Some("1"), None, first) }.unwrap().is_empty());
+    let pid = process::id().to_string();
+    assert!(unsafe { listen_queues_from(Some(&pid), None, None, first) }.unwrap().is_empty());
+    // still open
+    assert_eq!(unsafe { listen_queues_from(Some(&pid), Some("1"), None, first) }.unwrap().len(), 1);
+    // this process wasn't started by systemd
+    assert!(listen_queues().unwrap().is_empty());
+}
+
+#[test]
+fn invalid_variables() {
+    let pid = process::id().to_string();
+    let error = unsafe { listen_queues_from(Some("me"), Some("1"), None, 920) }.unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    let error = unsafe { listen_queues_from(Some(&pid), Some("-1"), None, 920) }.unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    let error = unsafe { listen_queues_from(Some(&pid), Some("2147483647"), None, 920) }.unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+}
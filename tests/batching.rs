@@ -0,0 +1,130 @@
+//! Tests of Batcher, unbatch() and Unbatcher.
+
+use std::io::ErrorKind;
+use std::thread;
+use std::time::Duration;
+
+extern crate libc;
+
+extern crate posixmq;
+use posixmq::{Batcher, OpenOptions, PosixMq, Unbatcher, remove_queue, unbatch};
+
+/// Returns a batcher and a nonblocking descriptor for receiving.
+fn tmp_mq(name: &str,  max_msg_len: usize) -> (Batcher, PosixMq) {
+    let mq = OpenOptions::readwrite()
+        .capacity(8)
+        .max_msg_len(max_msg_len)
+        .create_new()
+        .open(name)
+        .unwrap_or_else(|e| panic!("cannot create {}: {}", name, e) );
+    let receiver = OpenOptions::readonly().nonblocking().open(name).unwrap();
+    let _ = remove_queue(name);
+    (Batcher::new(mq).unwrap(), receiver)
+}
+
+fn recv_batches(mq: &PosixMq) -> Vec<(u32, Vec<Vec<u8>>)> {
+    mq.iter().map(|(priority, batch)| {
+        let records = unbatch(&batch).map(|record| record.unwrap().to_vec() ).collect();
+        (priority, records)
+    }).collect()
+}
+
+#[test]
+fn flushes_when_full() {
+    let (mut batcher, receiver) = tmp_mq("/batching_size", 16);
+    batcher.push(0, b"12345").unwrap(); // 9 bytes
+    batcher.push(0, b"123").unwrap(); // 16 bytes
+    assert_eq!(receiver.attributes().unwrap().current_messages, 0);
+    batcher.push(0, b"").unwrap();
+    assert_eq!(batcher.pending_records(), 1);
+    assert_eq!(recv_batches(&receiver), vec![(0, vec![b"12345".to_vec(), b"123".to_vec()])]);
+    batcher.flush().unwrap();
+    assert_eq!(recv_batches(&receiver), vec![(0, vec![Vec::new()])]);
+    batcher.flush().unwrap();
+    assert_eq!(receiver.attributes().unwrap().current_messages, 0, "doesn't send empty batches");
+
+    let error = batcher.push(0, b"123456789abcd").unwrap_err();
+    assert_eq!(error.raw_os_error(), Some(libc::EMSGSIZE));
+    batcher.push(0, b"123456789abc").unwrap();
+    assert_eq!(batcher.pending_records(), 1);
+}
+
+#[test]
+fn flushes_on_count_and_priority_change() {
+    let (mut batcher, receiver) = tmp_mq("/batching_count", 64);
+    batcher.max_records(2);
+    for &(priority, record) in &[(1, b"a"), (1, b"b"), (1, b"c"), (2, b"d"), (2, b"e")] {
+        batcher.push(priority, record).unwrap();
+    }
+    assert_eq!(batcher.pending_records(), 0);
+    assert_eq!(recv_batches(&receiver), vec![
+        (2, vec![b"d".to_vec(), b"e".to_vec()]),
+        (1, vec![b"a".to_vec(), b"b".to_vec()]),
+        (1, vec![b"c".to_vec()]),
+    ]);
+}
+
+#[test]
+fn flushes_when_due() {
+    let (mut batcher, receiver) = tmp_mq("/batching_delay", 64);
+    assert_eq!(batcher.time_until_due(), None);
+    batcher.max_delay(Some(Duration::from_millis(50)));
+    assert_eq!(batcher.time_until_due(), None);
+    batcher.push(0, b"first").unwrap();
+    assert!(batcher.time_until_due().unwrap() > Duration::from_millis(0));
+    assert!(!batcher.flush_if_due().unwrap());
+
+    thread::sleep(Duration::from_millis(60));
+    assert_eq!(batcher.time_until_due(), Some(Duration::from_millis(0)));
+    batcher.push(0, b"second").unwrap();
+    assert_eq!(batcher.pending_records(), 0);
+    assert_eq!(recv_batches(&receiver), vec![(0, vec![b"first".to_vec(), b"second".to_vec()])]);
+
+    batcher.push(0, b"third").unwrap();
+    thread::sleep(Duration::from_millis(60));
+    assert!(batcher.flush_if_due().unwrap());
+    assert_eq!(recv_batches(&receiver), vec![(0, vec![b"third".to_vec()])]);
+}
+
+#[test]
+fn unbatch_errors() {
+    let mut records = unbatch(b"\x00\x00\x00\x01a\x00\x00");
+    assert_eq!(records.next().unwrap().unwrap(), b"a");
+    assert_eq!(records.next().unwrap().unwrap_err().kind(), ErrorKind::InvalidData);
+    assert!(records.next().is_none());
+
+    let mut records = unbatch(b"\x00\x00\x00\x02a");
+    assert_eq!(records.next().unwrap().unwrap_err().kind(), ErrorKind::InvalidData);
+    assert!(records.next().is_none());
+    assert!(unbatch(b"").next().is_none());
+}
+
+#[test]
+fn unbatcher() {
+    let (mut batcher, receiver) = tmp_mq("/batching_unbatcher", 64);
+    batcher.push(1, b"a").unwrap();
+    batcher.push(1, b"b").unwrap();
+    batcher.push(3, b"c").unwrap();
+    batcher.flush().unwrap();
+    batcher.get_ref().send(2, b"\x00\x00\x00\x01d\x00\x00\x00\x09").unwrap();
+
+    let mut records = Unbatcher::new(&receiver);
+    assert_eq!(records.next().unwrap().unwrap(), (3, b"c".to_vec()));
+    assert_eq!(records.next().unwrap().unwrap(), (2, b"d".to_vec()));
+    assert_eq!(records.next().unwrap().unwrap_err().kind(), ErrorKind::InvalidData);
+    assert_eq!(records.next().unwrap().unwrap(), (1, b"a".to_vec()));
+    assert_eq!(records.next().unwrap().unwrap(), (1, b"b".to_vec()));
+    assert!(records.next().is_none());
+}
+
+#[test]
+fn unbatcher_ends_after_receive_error() {
+    let mq = OpenOptions::writeonly().capacity(1).max_msg_len(8).create_new()
+        .open("/batching_unbatcher_error")
+        .unwrap();
+    let _ = remove_queue("/batching_unbatcher_error");
+    // write-only, so receiving fails with EBADF
+    let mut records = Unbatcher::new(&mq);
+    assert!(records.next().unwrap().is_err());
+    assert!(records.next().is_none());
+}
@@ -0,0 +1,112 @@
+//! Tests of BufferedSender.
+
+#![cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+
+use std::fs::{self, OpenOptions as FileOptions};
+use std::io::ErrorKind;
+use std::thread;
+use std::time::Duration;
+
+extern crate libc;
+
+extern crate posixmq;
+use posixmq::{BufferedSender, OpenOptions, PosixMq, remove_queue};
+
+/// Returns a sender and a separate descriptor for receiving.
+fn tmp_mq(name: &str,  max_buffered_bytes: usize) -> (BufferedSender, PosixMq) {
+    let mq = OpenOptions::readwrite()
+        .capacity(2)
+        .max_msg_len(8)
+        .create_new()
+        .open(name)
+        .unwrap_or_else(|e| panic!("cannot create {}: {}", name, e) );
+    let _ = remove_queue(name);
+    let receiver = mq.try_clone().unwrap();
+    (BufferedSender::new(mq, max_buffered_bytes).unwrap(), receiver)
+}
+
+fn recv_all(mq: &PosixMq) -> Vec<(u32, Vec<u8>)> {
+    mq.set_nonblocking(true).unwrap();
+    let received = mq.iter().collect();
+    mq.set_nonblocking(false).unwrap();
+    received
+}
+
+#[test]
+fn sends_buffered_in_priority_order() {
+    let (mut sender, receiver) = tmp_mq("/buffered_priority", 100);
+    for &(priority, msg) in &[(1, b"a"), (1, b"b"), (0, b"c"), (3, b"d"), (1, b"e"), (3, b"f")] {
+        sender.send(priority, msg).unwrap();
+    }
+    assert_eq!(sender.pending(), 4);
+    assert_eq!(sender.buffered_bytes(), 4);
+    assert_eq!(recv_all(&receiver), vec![(1, b"a".to_vec()), (1, b"b".to_vec())]);
+
+    sender.send_buffered().unwrap();
+    assert_eq!(sender.pending(), 2);
+    assert_eq!(recv_all(&receiver), vec![(3, b"d".to_vec()), (3, b"f".to_vec())]);
+    sender.send_buffered().unwrap();
+    assert_eq!(sender.pending(), 0);
+    assert_eq!(sender.buffered_bytes(), 0);
+    assert_eq!(recv_all(&receiver), vec![(1, b"e".to_vec()), (0, b"c".to_vec())]);
+}
+
+#[test]
+fn limits_memory() {
+    let (mut sender, _receiver) = tmp_mq("/buffered_limited", 5);
+    sender.send(0, b"fits").unwrap();
+    sender.send(0, b"fits").unwrap();
+    sender.send(0, b"fits").unwrap();
+    assert_eq!(sender.send(0, b"full").unwrap_err().kind(), ErrorKind::WouldBlock);
+    sender.send(0, b"1").unwrap();
+    assert_eq!(sender.pending(), 2);
+    let error = sender.send(0, b"too long!").unwrap_err();
+    assert_eq!(error.raw_os_error(), Some(libc::EMSGSIZE));
+}
+
+#[test]
+fn spills_to_file() {
+    let path = std::env::temp_dir().join(format!("posixmq_spill_{}", std::process::id()));
+    let file = FileOptions::new().read(true).write(true).create(true).truncate(true)
+        .open(&path)
+        .unwrap();
+    let (mut sender, receiver) = tmp_mq("/buffered_spilling", 2);
+    sender.spill_to(file).unwrap();
+    for &(priority, msg) in &[(0, &b"sent"[..]), (0, b"sent"), (0, b"ab"), (0, b"spilled"), (0, b"c"), (1, b"d")] {
+        sender.send(priority, msg).unwrap();
+    }
+    assert_eq!(sender.pending(), 4);
+    assert_eq!(sender.buffered_bytes(), 2);
+    assert!(fs::metadata(&path).unwrap().len() > 0);
+
+    let mut received = Vec::new();
+    while sender.pending() != 0 {
+        received.extend(recv_all(&receiver));
+        sender.send_buffered().unwrap();
+    }
+    received.extend(recv_all(&receiver));
+    let received = received.into_iter().map(|(_, msg)| msg ).collect::<Vec<_>>();
+    assert_eq!(received, vec![
+        b"sent".to_vec(), b"sent".to_vec(), b"ab".to_vec(), b"spilled".to_vec(), b"d".to_vec(), b"c".to_vec(),
+    ]);
+    assert_eq!(fs::metadata(&path).unwrap().len(), 0, "truncated when empty");
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn flush_waits_for_room() {
+    let (mut sender, receiver) = tmp_mq("/buffered_flush", 100);
+    for n in 0..4 {
+        sender.send(0, &[n]).unwrap();
+    }
+    assert!(!sender.flush(Some(Duration::from_millis(10))).unwrap());
+    let receiving = thread::spawn(move|| {
+        let mut buf = [0; 8];
+        (0..4).map(|_| receiver.recv(&mut buf).unwrap().1 ).sum::<usize>()
+    });
+    assert!(sender.flush(None).unwrap());
+    assert_eq!(receiving.join().unwrap(), 4);
+}
@@ -0,0 +1,226 @@
+//! Tests of the posixmq command line program.
+
+use std::env;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+extern crate posixmq;
+use posixmq::{OpenOptions, remove_queue};
+
+/// Find the binary next to the directory the test executable is in.
+///
+/// (`CARGO_BIN_EXE_<name>` requires Rust 1.43)
+fn posixmq_bin() -> PathBuf {
+    let mut path = env::current_exe().expect("get path of test executable");
+    path.pop();
+    if path.ends_with("deps") {
+        path.pop();
+    }
+    path.push("posixmq");
+    path
+}
+
+fn run(args: &[&str]) -> Output {
+    Command::new(posixmq_bin()).args(args).output().expect("run posixmq")
+}
+
+#[test]
+fn write_stat_read() {
+    let name = "/cli_write_stat_read";
+    let _ = remove_queue(name);
+    let output = run(&["write", name, "wce600,2,100", "3", "hello"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let output = run(&["stat", name, "r"]);
+    let stat = String::from_utf8(output.stdout).unwrap();
+    assert!(stat.contains("capacity: 2\n"), "{}", stat);
+    assert!(stat.contains("max_msg_len: 100\n"), "{}", stat);
+    assert!(stat.contains("current_messages: 1\n"), "{}", stat);
+    let output = run(&["receive", name, "rn"]);
+    assert_eq!(output.stdout, b" 3 hello\n");
+    let output = run(&["ls"]);
+    assert!(String::from_utf8_lossy(&output.stdout).lines().any(|line| line == name ));
+    assert!(run(&["rm", name]).status.success());
+    assert!(!run(&["rm", name]).status.success());
+}
+
+#[test]
+fn timeouts() {
+    let name = "/cli_timeouts";
+    let mq = OpenOptions::readwrite().capacity(1).max_msg_len(10).create_new().open(name).unwrap();
+    mq.send(0, b"full").unwrap();
+    let output = run(&["write", name, "w", "1", "more", "50ms"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("sending failed: "), "{}", stderr);
+    assert_eq!(run(&["read", name, "r", "1s"]).stdout, b" 0 full\n");
+    let output = run(&["read", name, "r", "10ms"]);
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("receiving failed: "));
+    let output = run(&["read", name, "r", "soon"]);
+    let _ = remove_queue(name);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid timeout"));
+}
+
+#[test]
+fn bad_arguments() {
+    assert!(!run(&[]).status.success());
+    assert!(!run(&["stat", "/cli_bad_arguments"]).status.success());
+    let output = run(&["write", "/cli_bad_arguments", "wc1,2,3,4", "0", "m"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Too many numbers"));
+    let output = run(&["write", "/cli_bad_arguments", "w", "high", "m"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid priority"));
+}
+
+#[test]
+fn watch_once() {
+    let name = "/cli_watch_full";
+    let mq = OpenOptions::readwrite().capacity(1).max_msg_len(10).create_new().open(name).unwrap();
+    mq.send(0, b"full").unwrap();
+    let output = run(&["watch", "--once", "--stuck", "0s", "cli_watch"]);
+    let _ = remove_queue(name);
+    let table = String::from_utf8(output.stdout).unwrap();
+    let mut lines = table.lines();
+    assert!(lines.next().unwrap().starts_with("NAME "));
+    let row = lines.next().expect("a row for the queue");
+    let columns = row.split_whitespace().collect::<Vec<_>>();
+    assert_eq!(&columns[..4], &[name, "1/1", "4", "10"]);
+    assert_eq!(columns[5], "600");
+    assert_eq!(columns.last(), Some(&"full,stuck"));
+    assert_eq!(lines.next(), None, "prefix filters out other queues");
+}
+
+#[test]
+fn tail_formats() {
+    let name = "/cli_tail_formats";
+    let mq = OpenOptions::readwrite().capacity(3).max_msg_len(40).create_new().open(name).unwrap();
+    let binary = b"\x1b[2J\xff\0hi\n";
+    for _ in 0..3 {
+        mq.send(2, binary).unwrap();
+    }
+    let output = run(&["tail", name, "--count", "1"]);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), " 2 \\u{1b}[2J\\xff\\u{0}hi\\n\n");
+    let output = run(&["tail", "--hex", name, "--count", "1"]);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "priority 2, 9 bytes:\n00000000  1b 5b 32 4a ff 00 68 69  0a                       |.[2J..hi.|\n"
+    );
+    let output = run(&["tail", "--json", "--nonblocking", name]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, &b"{\"priority\":2,\"len\":9,\"data_b64\":\"G1sySv8AaGkK\"}\n"[..]);
+    let output = run(&["tail", name, "--timeout", "10ms"]);
+    let _ = remove_queue(name);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+fn run_with_stdin(args: &[&str],  input: &[u8]) -> Output {
+    use std::io::Write;
+    use std::process::Stdio;
+    let mut child = Command::new(posixmq_bin())
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("run posixmq");
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().expect("wait for posixmq")
+}
+
+#[test]
+fn send_from_stdin() {
+    let name = "/cli_send_stdin";
+    let mut buf = [0; 20];
+    let args = ["send", name, "--create-new", "--capacity", "4", "--max-msg-len", "20", "--mode", "600"];
+    let output = run_with_stdin(&args, b"first\nsecond\n\nlast");
+    let mq = posixmq::PosixMq::open(name).unwrap();
+    let _ = remove_queue(name);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(mq.attributes().unwrap().current_messages, 4);
+    assert_eq!(mq.recv(&mut buf).unwrap(), (0, 5));
+    assert_eq!(mq.recv(&mut buf).unwrap(), (0, 6));
+    assert_eq!(mq.recv(&mut buf).unwrap(), (0, 0));
+    assert_eq!(mq.recv(&mut buf).unwrap(), (0, 4));
+}
+
+#[test]
+fn send_priorities_and_framing() {
+    let name = "/cli_send_framing";
+    let mq = OpenOptions::readwrite().capacity(4).max_msg_len(20).create_new().open(name).unwrap();
+    let mut buf = [0; 20];
+
+    let output = run_with_stdin(&["send", name, "--nul", "--tab-priority"], b"1\tlow\x002\thigh\0");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(mq.recv(&mut buf).unwrap(), (2, 4));
+    assert_eq!(mq.recv(&mut buf).unwrap(), (1, 3));
+
+    let input = b"\0\0\0\x03a\nb\0\0\0\0";
+    let output = run_with_stdin(&["send", name, "--length-prefixed", "--priority", "7"], input);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(mq.recv(&mut buf).unwrap(), (7, 3));
+    assert_eq!(&buf[..3], b"a\nb");
+    assert_eq!(mq.recv(&mut buf).unwrap(), (7, 0));
+
+    let output = run_with_stdin(&["send", name, "--length-prefixed"], b"\0\0\0\x05abc");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Truncated"));
+    let output = run_with_stdin(&["send", name, "--length-prefixed"], b"\xff\xff\xff\xffabc");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Truncated"));
+    let output = run_with_stdin(&["send", name, "--tab-priority"], b"high\tmessage\n");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid priority"));
+
+    let output = run(&["send", name, "--priority", "3", "Cargo.toml"]);
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("sending failed: "));
+    let output = run(&["send", name, "--nonblocking", "--priority", "3", "LICENSE-MIT", "LICENSE-MIT"]);
+    let _ = remove_queue(name);
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("sending failed: "));
+}
+
+#[test]
+fn send_as_write_alias() {
+    let name = "/cli_send_alias";
+    let output = run(&["send", name, "wce600,2,10", "4", "mq.c"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let output = run(&["send", name, "w", "5", "timed", "1s"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let mq = posixmq::PosixMq::open(name).unwrap();
+    let _ = remove_queue(name);
+    let mut buf = [0; 10];
+    assert_eq!(mq.recv(&mut buf).unwrap(), (5, 5));
+    assert_eq!(mq.recv(&mut buf).unwrap(), (4, 4));
+}
+
+#[test]
+fn bench() {
+    let args = ["bench", "--messages", "1000", "--size", "16", "--capacity", "4",
+                "--producers", "2", "--consumers", "3", "--priorities", "3"];
+    for &extra in &[&[][..], &["--nonblocking"], &["--processes"], &["--processes", "--nonblocking"]] {
+        let output = run(&[&args[..], extra].concat());
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let report = String::from_utf8(output.stdout).unwrap();
+        assert!(report.starts_with("messages: 1000 of 16 bytes, capacity 4,"), "{}", report);
+        let workers = if extra.contains(&"--processes") {", processes\n"} else {", threads\n"};
+        assert!(report.contains(workers), "{}", report);
+        assert!(report.contains(" msgs/s, "), "{}", report);
+        assert!(report.contains("latency: p50 "), "{}", report);
+    }
+    let output = run(&["bench", "--size", "4"]);
+    assert!(!output.status.success());
+    let output = run(&["bench", "--priorities", "0"]);
+    assert!(!output.status.success());
+    let output = run(&["bench", "--priorities", "4294967297"]);
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("Invalid --priorities"));
+}
+
+#[test]
+fn bad_bench_worker_arguments() {
+    for args in &[
+        &["bench-worker", "produce", "8", "1", "0", "0", "10"][..],
+        &["bench-worker", "produce", "8", "0", "0", "1", "10"],
+        &["bench-worker", "produce", "4", "1", "0", "1", "10"],
+        &["bench-worker", "consume", "8"],
+    ] {
+        let output = run(args);
+        assert_eq!(output.status.code(), Some(1), "{:?}", args);
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "Invalid worker arguments\n", "{:?}", args);
+    }
+}
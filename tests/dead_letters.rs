@@ -0,0 +1,106 @@
+//! Tests of DeadLetterRouter and DeadLetter.
+
+use std::cell::Cell;
+use std::io::ErrorKind;
+
+extern crate posixmq;
+use posixmq::{DeadLetter, DeadLetterRouter, OpenOptions, PosixMq, remove_queue};
+
+fn tmp_mq(name: &str,  max_msg_len: usize) -> PosixMq {
+    let mq = OpenOptions::readwrite()
+        .capacity(2)
+        .max_msg_len(max_msg_len)
+        .create_new()
+        .open(name)
+        .unwrap_or_else(|e| panic!("cannot create {}: {}", name, e) );
+    let _ = remove_queue(name);
+    mq
+}
+
+#[test]
+fn succeeds_after_retries() {
+    let router = DeadLetterRouter::new(tmp_mq("/dead_letters_retried", 32), 28).unwrap();
+    let calls = Cell::new(0);
+    let handled = router.handle(1, b"flaky", |priority, msg| {
+        assert_eq!((priority, msg), (1, &b"flaky"[..]));
+        calls.set(calls.get() + 1);
+        if calls.get() < 3 {Err("not yet")} else {Ok(())}
+    }).unwrap();
+    assert!(handled);
+    let stats = router.stats();
+    assert_eq!((stats.handled, stats.failed_attempts, stats.dead_lettered), (1, 2, 0));
+    assert_eq!(router.last_failure(), None);
+    assert_eq!(router.failure_reasons(), vec![("not yet".to_string(), 2)]);
+    assert_eq!(router.dead_letter_queue().attributes().unwrap().current_messages, 0);
+}
+
+#[test]
+fn sends_header_and_payload() {
+    let mq = tmp_mq("/dead_letters_source", 32);
+    let mut router = DeadLetterRouter::new(tmp_mq("/dead_letters_sent", 36), 32).unwrap();
+    router.max_attempts(0); // becomes 1
+    mq.send(7, b"payload").unwrap();
+    let mut buf = [0; 36];
+    let handled = router.recv_and_handle(&mq, &mut buf, |_, _| Err("broken") ).unwrap();
+    assert!(!handled);
+    let stats = router.stats();
+    assert_eq!((stats.handled, stats.failed_attempts, stats.dead_lettered), (0, 1, 1));
+    assert_eq!(router.last_failure(), Some("broken".to_string()));
+
+    let (priority, len) = router.dead_letter_queue().recv(&mut buf).unwrap();
+    assert_eq!(priority, 7);
+    assert_eq!(&buf[..len], b"\x00\x01\x00\x06brokenpayload");
+    let dead = DeadLetter::parse(&buf[..len]).unwrap();
+    assert_eq!((dead.attempts, dead.reason, dead.payload), (1, "broken", &b"payload"[..]));
+}
+
+#[test]
+fn truncates_reason_to_fit() {
+    let router = DeadLetterRouter::new(tmp_mq("/dead_letters_truncated", 11), 4).unwrap();
+    let handled = router.handle(0, b"abcd", |_, _| Err("æøå") ).unwrap();
+    assert!(!handled);
+    assert_eq!(router.last_failure(), Some("æøå".to_string()));
+    let mut buf = [0; 11];
+    let (_, len) = router.dead_letter_queue().recv(&mut buf).unwrap();
+    assert_eq!(len, 10, "reason is cut at a character boundary");
+    let dead = DeadLetter::parse(&buf[..len]).unwrap();
+    assert_eq!((dead.attempts, dead.reason, dead.payload), (3, "æ", &b"abcd"[..]));
+}
+
+#[test]
+fn rejects_too_small_queue() {
+    let dlq = tmp_mq("/dead_letters_same_size", 16);
+    let error = DeadLetterRouter::new(dlq, 16).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    let dlq = tmp_mq("/dead_letters_almost", 19);
+    let error = DeadLetterRouter::new(dlq, 16).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    let dlq = tmp_mq("/dead_letters_enough", 20);
+    let router = DeadLetterRouter::new(dlq, 16).unwrap();
+    let longest = [b'x'; 16];
+    assert!(!router.handle(0, &longest, |_, _| Err("reason") ).unwrap());
+    let mut buf = [0; 20];
+    let (_, len) = router.dead_letter_queue().recv(&mut buf).unwrap();
+    let dead = DeadLetter::parse(&buf[..len]).unwrap();
+    assert_eq!((dead.reason, dead.payload), ("", &longest[..]));
+}
+
+#[test]
+fn returns_send_errors() {
+    // messages longer than the source queue allows
+    let router = DeadLetterRouter::new(tmp_mq("/dead_letters_too_long", 8), 4).unwrap();
+    assert!(router.handle(0, b"too long", |_, _| Err("") ).is_err());
+    assert_eq!(router.stats().dead_lettered, 0);
+    assert_eq!(router.last_failure(), None, "not recorded when sending failed");
+    assert_eq!(router.failure_reasons(), vec![(String::new(), 3)]);
+}
+
+#[test]
+fn parse_errors() {
+    for invalid in &[&b""[..], b"\x00\x01\x00", b"\x00\x01\x00\x05four", b"\x00\x01\x00\x01\xff"] {
+        let error = DeadLetter::parse(invalid).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData, "{:?}", invalid);
+    }
+    let empty = DeadLetter::parse(b"\x01\x00\x00\x00").unwrap();
+    assert_eq!((empty.attempts, empty.reason, empty.payload), (256, "", &b""[..]));
+}
@@ -0,0 +1,126 @@
+//! Tests of dump(), dump_preserving(), restore() and DumpReader.
+
+use std::io::ErrorKind;
+use std::time::{Duration, SystemTime};
+
+extern crate posixmq;
+use posixmq::{DumpReader, OpenOptions, PosixMq, dump, dump_preserving, remove_queue, restore};
+
+fn tmp_mq(name: &str) -> PosixMq {
+    let mq = OpenOptions::readwrite()
+        .capacity(4)
+        .max_msg_len(8)
+        .create_new()
+        .open(name)
+        .unwrap_or_else(|e| panic!("cannot create {}: {}", name, e) );
+    let _ = remove_queue(name);
+    mq
+}
+
+fn fill(mq: &PosixMq) {
+    mq.send(1, b"first").unwrap();
+    mq.send(3, b"").unwrap();
+    mq.send(1, b"second").unwrap();
+}
+
+fn drain(mq: &PosixMq) -> Vec<(u32, Vec<u8>)> {
+    mq.set_nonblocking(true).unwrap();
+    let messages = mq.iter().collect();
+    mq.set_nonblocking(false).unwrap();
+    messages
+}
+
+#[test]
+fn format() {
+    let mq = tmp_mq("/dump_format");
+    fill(&mq);
+    let before = SystemTime::now() - Duration::from_secs(1);
+    let mut dumped = Vec::new();
+    assert_eq!(dump(&mq, &mut dumped).unwrap(), 3);
+    assert_eq!(mq.attributes().unwrap().current_messages, 0, "drains");
+
+    assert_eq!(&dumped[..8], b"PMQDUMP1");
+    assert_eq!(&dumped[8..12], &[0, 0, 0, 3]);
+    assert_eq!(&dumped[24..28], &[0, 0, 0, 0]);
+    assert_eq!(&dumped[28..32], &[0, 0, 0, 1]);
+    assert_eq!(&dumped[44..48], &[0, 0, 0, 5]);
+    assert_eq!(&dumped[48..53], b"first");
+    assert_eq!(dumped.len(), 8 + 3*20 + 5 + 6);
+
+    let messages = DumpReader::new(&dumped[..]).unwrap()
+        .map(|message| message.unwrap() )
+        .collect::<Vec<_>>();
+    assert_eq!(messages.len(), 3);
+    assert_eq!((messages[2].priority, &messages[2].msg[..]), (1, &b"second"[..]));
+    assert!(messages[0].received > before  &&  messages[0].received < SystemTime::now());
+}
+
+#[test]
+fn preserving_and_restoring() {
+    let mq = tmp_mq("/dump_preserving");
+    fill(&mq);
+    let mut dumped = Vec::new();
+    assert_eq!(dump_preserving(&mq, &mut dumped).unwrap(), 3);
+    let expected = vec![(3, Vec::new()), (1, b"first".to_vec()), (1, b"second".to_vec())];
+    assert_eq!(drain(&mq), expected);
+
+    let other = tmp_mq("/dump_restored");
+    assert_eq!(restore(&dumped[..], &other).unwrap(), 3);
+    assert_eq!(drain(&other), expected);
+}
+
+#[test]
+fn empty_queue() {
+    let mq = tmp_mq("/dump_empty");
+    let mut dumped = Vec::new();
+    assert_eq!(dump(&mq, &mut dumped).unwrap(), 0);
+    assert_eq!(dumped, b"PMQDUMP1");
+    assert_eq!(restore(&dumped[..], &mq).unwrap(), 0);
+}
+
+#[test]
+fn invalid_dumps() {
+    let mq = tmp_mq("/dump_invalid");
+    assert_eq!(restore(&b""[..], &mq).unwrap_err().kind(), ErrorKind::InvalidData);
+    assert_eq!(restore(&b"PMQDUMP2"[..], &mq).unwrap_err().kind(), ErrorKind::InvalidData);
+
+    fill(&mq);
+    let mut dumped = Vec::new();
+    dump(&mq, &mut dumped).unwrap();
+    let truncated = &dumped[..dumped.len()-1];
+    let mut reader = DumpReader::new(truncated).unwrap();
+    assert!(reader.next().unwrap().is_ok());
+    assert!(reader.next().unwrap().is_ok());
+    assert_eq!(reader.next().unwrap().unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    assert!(reader.next().is_none());
+
+    let truncated = &dumped[..8+10];
+    let mut reader = DumpReader::new(truncated).unwrap();
+    assert_eq!(reader.next().unwrap().unwrap_err().kind(), ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn hostile_headers() {
+    let record = |secs: &[u8; 8],  nanos: &[u8; 4],  len: &[u8; 4]| {
+        let mut dumped = b"PMQDUMP1\x00\x00\x00\x01".to_vec();
+        dumped.extend_from_slice(secs);
+        dumped.extend_from_slice(nanos);
+        dumped.extend_from_slice(len);
+        dumped.extend_from_slice(b"msg");
+        dumped
+    };
+    let valid = record(b"\x00\x00\x00\x00\x00\x00\x00\x01", b"\x00\x00\x00\x02", b"\x00\x00\x00\x03");
+    let message = DumpReader::new(&valid[..]).unwrap().next().unwrap().unwrap();
+    assert_eq!(message.msg, b"msg");
+
+    let overflowing_secs = record(&[0xff; 8], b"\x00\x00\x00\x00", b"\x00\x00\x00\x03");
+    let overflowing_nanos = record(&[0x7f; 8], &[0xff; 4], b"\x00\x00\x00\x03");
+    let huge = record(b"\x00\x00\x00\x00\x00\x00\x00\x01", b"\x00\x00\x00\x00", &[0xff; 4]);
+    for dumped in &[overflowing_secs, overflowing_nanos, huge] {
+        let error = DumpReader::new(&dumped[..]).unwrap().next().unwrap().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+    let truncated = record(b"\x00\x00\x00\x00\x00\x00\x00\x01", b"\x00\x00\x00\x00", b"\x00\x00\x10\x00");
+    let error = DumpReader::new(&truncated[..]).unwrap().next().unwrap().unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+}
@@ -0,0 +1,76 @@
+//! Tests of FaultyMq.
+
+use std::io::ErrorKind;
+use std::time::{Duration, Instant};
+
+extern crate libc;
+extern crate posixmq;
+use posixmq::{Fault, FaultyMq, MemoryMq, MessageQueue};
+
+fn tmp_mq() -> FaultyMq<MemoryMq> {
+    FaultyMq::new(MemoryMq::new(10, 8).unwrap())
+}
+
+#[test]
+fn scripted_faults_are_injected_in_order() {
+    let mq = tmp_mq();
+    let mut buf = [0; 8];
+    mq.script_sends(vec![Fault::WouldBlock, Fault::TimedOut]);
+    mq.script_sends(vec![Fault::MessageSize]);
+    mq.script_recvs(vec![Fault::BadDescriptor, Fault::Interrupted]);
+
+    assert_eq!(mq.send(0, b"1").unwrap_err().kind(), ErrorKind::WouldBlock);
+    let timeout = Duration::from_secs(1);
+    assert_eq!(mq.send_timeout(0, b"2", timeout).unwrap_err().kind(), ErrorKind::TimedOut);
+    let error = mq.send(0, b"3").unwrap_err();
+    assert_eq!(error.raw_os_error(), Some(libc::EMSGSIZE));
+    mq.send(0, b"4").unwrap();
+    assert_eq!(mq.attributes().unwrap().current_messages, 1, "failed sends have no effect");
+
+    assert_eq!(mq.recv(&mut buf).unwrap_err().raw_os_error(), Some(libc::EBADF));
+    assert_eq!(mq.recv_timeout(&mut buf, timeout).unwrap_err().kind(), ErrorKind::Interrupted);
+    assert_eq!(mq.recv(&mut buf).unwrap(), (0, 1));
+    assert_eq!(buf[0], b'4');
+    assert_eq!(mq.injected(), 5);
+}
+
+#[test]
+fn delays() {
+    let mq = tmp_mq();
+    mq.script_sends(vec![Fault::Delay(Duration::from_millis(50))]);
+    let start = Instant::now();
+    mq.send(1, b"late").unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    assert_eq!(mq.get_ref().attributes().unwrap().current_messages, 1);
+    assert_eq!(mq.injected(), 1);
+}
+
+#[test]
+fn random_faults_are_reproducible() {
+    fn run(seed: u64) -> Vec<Option<ErrorKind>> {
+        let mut mq = tmp_mq();
+        mq.random_faults(0.5, &[Fault::WouldBlock, Fault::Interrupted], seed);
+        let mut buf = [0; 8];
+        (0..100).map(|n| {
+            if n % 2 == 0 {
+                mq.send(0, b"").err().map(|e| e.kind() )
+            } else {
+                mq.recv_timeout(&mut buf, Duration::from_millis(1)).err().map(|e| e.kind() )
+            }
+        }).collect()
+    }
+    let faults = run(7);
+    assert_eq!(faults, run(7));
+    assert_ne!(faults, run(8));
+    let injected = faults.iter()
+        .filter(|&&kind| kind == Some(ErrorKind::WouldBlock)  ||  kind == Some(ErrorKind::Interrupted))
+        .count();
+    assert!(injected > 20  &&  injected < 80, "{} of 100 failed", injected);
+
+    let mut mq = tmp_mq();
+    mq.random_faults(1.0, &[Fault::TimedOut], 0);
+    assert_eq!(mq.send(0, b"").unwrap_err().kind(), ErrorKind::TimedOut);
+    mq.random_faults(0.0, &[Fault::TimedOut], 0);
+    mq.send(0, b"").unwrap();
+    assert_eq!(mq.into_inner().attributes().unwrap().current_messages, 1);
+}
@@ -68,6 +68,153 @@ fn into_fd_doesnt_drop() {
 }
 
 
+#[cfg(not(any(target_os="illumos", target_os="solaris")))]
+#[test]
+fn wait_for_readiness() {
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use posixmq::OpenOptions;
+
+    let mq = OpenOptions::readwrite()
+        .capacity(1)
+        .max_msg_len(10)
+        .create_new()
+        .open("/wait_for_readiness")
+        .unwrap();
+    let _ = remove_queue("/wait_for_readiness");
+
+    let start = Instant::now();
+    assert!(!mq.wait_readable(Some(Duration::from_millis(50))).unwrap());
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    assert!(mq.wait_writable(Some(Duration::from_millis(0))).unwrap());
+
+    mq.send(0, b"full").unwrap();
+    assert!(mq.wait_readable(Some(Duration::from_millis(0))).unwrap());
+    assert!(!mq.wait_writable(Some(Duration::from_millis(10))).unwrap());
+    // waiting doesn't consume the message
+    assert_eq!(mq.attributes().unwrap().current_messages, 1);
+
+    // wakes up when another thread makes room
+    let mq = Arc::new(mq);
+    let receiver = mq.clone();
+    let receiving = thread::spawn(move|| {
+        thread::sleep(Duration::from_millis(50));
+        receiver.recv(&mut[0; 10]).unwrap()
+    });
+    assert!(mq.wait_writable(None).unwrap());
+    assert_eq!(receiving.join().unwrap(), (0, 4));
+}
+
+#[cfg(not(any(target_os="illumos", target_os="solaris")))]
+#[test]
+fn cancellable() {
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+    use posixmq::{CancelToken, OpenOptions};
+
+    let mq = OpenOptions::readwrite()
+        .capacity(1)
+        .max_msg_len(10)
+        .create_new()
+        .open("/cancellable")
+        .unwrap();
+    let _ = remove_queue("/cancellable");
+    let mq = Arc::new(mq);
+    let token = CancelToken::new().unwrap();
+
+    // works like normal when not cancelled
+    mq.send_cancellable(1, b"one", &token).unwrap();
+    assert_eq!(mq.recv_cancellable(&mut[0; 10], &token).unwrap(), (1, 3));
+
+    // a message that arrives later is received
+    let sender = mq.clone();
+    let sending = thread::spawn(move|| {
+        thread::sleep(Duration::from_millis(50));
+        sender.send(2, b"two").unwrap();
+    });
+    assert_eq!(mq.recv_cancellable(&mut[0; 10], &token).unwrap(), (2, 3));
+    sending.join().unwrap();
+
+    // blocked operations are woken up, and fill or drain nothing
+    mq.send(3, b"three").unwrap();
+    let canceller = token.clone();
+    let cancelling = thread::spawn(move|| {
+        thread::sleep(Duration::from_millis(50));
+        canceller.cancel();
+    });
+    let error = mq.send_cancellable(4, b"four", &token).expect_err("cancelled send");
+    assert_eq!(error.kind(), ErrorKind::Interrupted);
+    cancelling.join().unwrap();
+    assert!(token.is_cancelled());
+    assert_eq!(mq.attributes().unwrap().current_messages, 1);
+    let error = mq.recv_cancellable(&mut[0; 10], &token).expect_err("cancelled receive");
+    assert_eq!(error.kind(), ErrorKind::Interrupted);
+    assert_eq!(mq.attributes().unwrap().current_messages, 1);
+}
+
+#[cfg(not(any(target_os="illumos", target_os="solaris")))]
+#[test]
+fn selector() {
+    use std::time::{Duration, Instant};
+    use posixmq::{OpenOptions, Readiness, Selector};
+
+    let mut opts = OpenOptions::readwrite();
+    let opts = opts.nonblocking().capacity(1).max_msg_len(10).create_new();
+    let mq_a = opts.open("/selector_a").unwrap();
+    let mq_b = opts.open("/selector_b").unwrap();
+    let _ = remove_queue("/selector_a");
+    let _ = remove_queue("/selector_b");
+
+    let mut selector = Selector::new().expect("cannot create selector");
+    selector.register(&mq_a, 10, Readiness::READABLE).unwrap();
+    selector.register(&mq_b, 11, Readiness::READABLE).unwrap();
+    assert_eq!(
+        selector.register(&mq_b, 12, Readiness::WRITABLE).unwrap_err().kind(),
+        ErrorKind::AlreadyExists
+    );
+
+    // test timeout
+    let mut events = vec![(0, Readiness::default())];
+    let start = Instant::now();
+    selector.select(&mut events, Some(Duration::from_millis(50))).expect("cannot select");
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    assert_eq!(events, Vec::new());
+
+    // test readable, and that readiness is level-triggered
+    mq_b.send(1, b"b").unwrap();
+    for _ in 0..2 {
+        selector.select(&mut events, None).unwrap();
+        assert_eq!(events, vec![(11, Readiness::READABLE)]);
+    }
+    mq_b.recv(&mut[0; 10]).unwrap();
+
+    // test reregister & writable
+    selector.reregister(&mq_a, 20, Readiness::READABLE | Readiness::WRITABLE).unwrap();
+    selector.select(&mut events, Some(Duration::from_secs(1))).unwrap();
+    assert_eq!(events, vec![(20, Readiness::WRITABLE)]);
+    mq_a.send(0, b"a").unwrap();
+    selector.select(&mut events, Some(Duration::from_secs(1))).unwrap();
+    assert_eq!(events, vec![(20, Readiness::READABLE)]);
+
+    // test deregister
+    selector.deregister(&mq_a).unwrap();
+    assert_eq!(selector.deregister(&mq_a).unwrap_err().kind(), ErrorKind::NotFound);
+    selector.select(&mut events, Some(Duration::from_millis(10))).unwrap();
+    assert_eq!(events, Vec::new());
+
+    // cancel tokens can also be registered
+    let token = posixmq::CancelToken::new().unwrap();
+    selector.register(&token, 30, Readiness::READABLE).unwrap();
+    selector.select(&mut events, Some(Duration::from_millis(10))).unwrap();
+    assert_eq!(events, Vec::new());
+    token.cancel();
+    selector.select(&mut events, Some(Duration::from_secs(1))).unwrap();
+    assert_eq!(events, vec![(30, Readiness::READABLE)]);
+}
+
+
 #[cfg(feature="mio_06")]
 #[test]
 fn mio_06() {
@@ -0,0 +1,124 @@
+//! Tests of Forwarder.
+
+#![cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+
+use std::io::ErrorKind;
+use std::thread;
+use std::time::Duration;
+
+extern crate posixmq;
+use posixmq::{Forwarder, OpenOptions, PosixMq, remove_queue};
+
+/// Create small queues to avoid running into the total queues size limit.
+///
+/// Returns two separately opened descriptors, because the forwarder makes
+/// its descriptors nonblocking, which would also affect clones.
+fn tmp_mq(name: &str,  capacity: usize) -> (PosixMq, PosixMq) {
+    let mq = OpenOptions::readwrite()
+        .capacity(capacity)
+        .max_msg_len(16)
+        .create_new()
+        .open(name)
+        .unwrap_or_else(|e| panic!("cannot create {}: {}", name, e) );
+    let other = PosixMq::open(name).unwrap_or_else(|e| panic!("cannot reopen {}: {}", name, e) );
+    let _ = remove_queue(name);
+    (mq, other)
+}
+
+#[test]
+fn preserves_priority_and_fans_out() {
+    let (src_a, fwd_a) = tmp_mq("/forward_src_a", 4);
+    let (src_b, fwd_b) = tmp_mq("/forward_src_b", 4);
+    let (dst_x, fwd_x) = tmp_mq("/forward_dst_x", 8);
+    let (dst_y, fwd_y) = tmp_mq("/forward_dst_y", 8);
+    src_a.send(1, b"a1").unwrap();
+    src_a.send(5, b"a5").unwrap();
+    src_b.send(3, b"b3").unwrap();
+    src_b.send(7, b"b7").unwrap();
+
+    let mut forwarder = Forwarder::new().unwrap();
+    forwarder.add_source(fwd_a).add_source(fwd_b);
+    forwarder.add_destination(fwd_x).add_destination(fwd_y);
+    let shutdown = forwarder.shutdown_handle();
+    let forwarding = thread::spawn(move|| forwarder.run().map(|()| forwarder.received() ) );
+
+    for dst in &[dst_x, dst_y] {
+        let mut received = Vec::new();
+        let mut buf = [0; 16];
+        for _ in 0..4 {
+            let (priority, len) = dst.recv_timeout(&mut buf, Duration::from_secs(5)).unwrap();
+            assert_eq!(len, 2);
+            assert_eq!(buf[1]-b'0', priority as u8);
+            received.push(priority);
+        }
+        received.sort();
+        assert_eq!(received, vec![1, 3, 5, 7]);
+    }
+
+    shutdown.shutdown();
+    assert_eq!(forwarding.join().unwrap().expect("forwarding failed"), 4);
+}
+
+#[test]
+fn applies_backpressure() {
+    let (src, fwd_src) = tmp_mq("/forward_backpressure_src", 6);
+    let (dst, fwd_dst) = tmp_mq("/forward_backpressure_dst", 1);
+    for n in 0..6 {
+        src.send(0, &[n]).unwrap();
+    }
+
+    let mut forwarder = Forwarder::new().unwrap();
+    forwarder.add_source(fwd_src).add_destination(fwd_dst).max_pending(2);
+    let shutdown = forwarder.shutdown_handle();
+    let forwarding = thread::spawn(move|| forwarder.run() );
+
+    // one message in the destination and two in the buffer
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(src.attributes().unwrap().current_messages, 3);
+    let mut buf = [0; 16];
+    for n in 0..6 {
+        assert_eq!(dst.recv_timeout(&mut buf, Duration::from_secs(5)).unwrap(), (0, 1));
+        assert_eq!(buf[0], n, "order of messages with equal priority");
+    }
+    assert_eq!(src.attributes().unwrap().current_messages, 0);
+
+    shutdown.shutdown();
+    forwarding.join().unwrap().expect("forwarding failed");
+}
+
+#[test]
+fn returns_errors() {
+    let (src, fwd_src) = tmp_mq("/forward_error_src", 2);
+    let dst = OpenOptions::readonly()
+        .capacity(2)
+        .max_msg_len(16)
+        .create_new()
+        .open("/forward_error_dst")
+        .unwrap();
+    let _ = remove_queue("/forward_error_dst");
+    src.send(0, b"undeliverable").unwrap();
+
+    let mut forwarder = Forwarder::new().unwrap();
+    forwarder.add_source(fwd_src).add_destination(dst);
+    let error = forwarder.run().expect_err("destination is read-only");
+    assert!(error.to_string().contains("destination 0"), "{}", error);
+
+    let mut forwarder = Forwarder::new().unwrap();
+    forwarder.add_source(src);
+    assert_eq!(forwarder.run().unwrap_err().kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn shutdown_before_run() {
+    let mut forwarder = Forwarder::new().unwrap();
+    forwarder.add_source(tmp_mq("/forward_idle_src", 1).0);
+    forwarder.add_destination(tmp_mq("/forward_idle_dst", 1).0);
+    let shutdown = forwarder.shutdown_handle();
+    assert!(!shutdown.is_shutdown());
+    shutdown.shutdown();
+    assert!(shutdown.is_shutdown());
+    forwarder.run().expect("returns after shutdown");
+}
@@ -0,0 +1,136 @@
+//! Tests of passing queues to child processes.
+
+#![cfg(any(target_os="linux", target_os="netbsd", target_os="dragonfly"))]
+
+use std::env;
+#[cfg(target_os="linux")]
+use std::fs;
+use std::io::ErrorKind;
+use std::process::{Command, Stdio};
+
+extern crate posixmq;
+use posixmq::{InheritQueues, OpenOptions, PosixMq, remove_queue};
+
+fn tmp_mq(name: &str) -> PosixMq {
+    let mq = OpenOptions::readwrite().capacity(2).max_msg_len(20).create_new().open(name).unwrap();
+    remove_queue(name).unwrap();
+    mq
+}
+
+/// Run this test binary again, with only `child_side` enabled.
+fn child() -> Command {
+    child_running("child_side")
+}
+
+fn child_running(test: &str) -> Command {
+    let mut command = Command::new(env::current_exe().unwrap());
+    command.arg("--exact").arg(test).arg("--quiet").arg("--test-threads=1");
+    command
+}
+
+/// The part of `queues_are_inherited` that runs in the child process.
+#[test]
+fn child_side() {
+    if env::var_os("POSIXMQ_INHERITED").is_none() {
+        return;
+    }
+    let jobs = PosixMq::from_inherited("jobs").unwrap();
+    let results = PosixMq::from_inherited("results").unwrap();
+    assert!(jobs.is_cloexec().unwrap());
+    assert_eq!(PosixMq::from_inherited("jobs").unwrap_err().kind(), ErrorKind::NotFound);
+    let mut buf = [0; 20];
+    let (priority, len) = jobs.recv(&mut buf).unwrap();
+    results.send(priority+1, &buf[..len]).unwrap();
+}
+
+#[test]
+fn queues_are_inherited() {
+    let jobs = tmp_mq("/inherit_jobs");
+    let results = tmp_mq("/inherit_results");
+    jobs.send(3, b"echo").unwrap();
+    let status = child()
+        .inherit_queues(&[("jobs", &jobs), ("results", &results)])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let mut buf = [0; 20];
+    assert_eq!(results.recv(&mut buf).unwrap(), (4, 4));
+    assert_eq!(&buf[..4], b"echo");
+    // only cleared in the child
+    assert!(jobs.is_cloexec().unwrap());
+    assert!(results.is_cloexec().unwrap());
+}
+
+#[test]
+fn originals_can_be_closed_before_spawning() {
+    let jobs = tmp_mq("/inherit_closed_jobs");
+    let results = tmp_mq("/inherit_closed_results");
+    jobs.send(0, b"closed").unwrap();
+    let mut command = child();
+    command.inherit_queues(&[("jobs", &jobs), ("results", &results)]);
+    drop(jobs);
+    assert!(command.status().unwrap().success());
+    let mut buf = [0; 20];
+    assert_eq!(results.recv(&mut buf).unwrap(), (1, 6));
+    assert_eq!(&buf[..6], b"closed");
+}
+
+/// The part of `second_call_replaces_variable` that runs in the child process.
+#[test]
+#[cfg(target_os="linux")]
+fn second_call_child_side() {
+    if env::var_os("POSIXMQ_INHERITED").is_none() {
+        return;
+    }
+    assert_eq!(PosixMq::from_inherited("first").unwrap_err().kind(), ErrorKind::NotFound);
+    let second = PosixMq::from_inherited("second").unwrap();
+    second.send(0, b"listed").unwrap();
+    // the queue from the first call is open but not listed
+    let leaked = fs::read_dir("/proc/self/fd").unwrap()
+        .filter_map(|entry| fs::read_link(entry.unwrap().path()).ok() )
+        .any(|target| target.to_string_lossy().contains("inherit_first") );
+    assert!(leaked);
+}
+
+#[test]
+#[cfg(target_os="linux")]
+fn second_call_replaces_variable() {
+    let first = tmp_mq("/inherit_first");
+    let second = tmp_mq("/inherit_second");
+    let status = child_running("second_call_child_side")
+        .inherit_queues(&[("first", &first)])
+        .inherit_queues(&[("second", &second)])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(second.attributes().unwrap().current_messages, 1);
+}
+
+#[test]
+fn not_inherited_without_helper() {
+    let jobs = tmp_mq("/inherit_not");
+    jobs.send(0, b"stays").unwrap();
+    let status = child()
+        .env("POSIXMQ_INHERITED", format!("jobs={}:results={}", 900, 901))
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(!status.success());
+    assert_eq!(jobs.attributes().unwrap().current_messages, 1);
+}
+
+#[test]
+fn invalid_names() {
+    let mq = tmp_mq("/inherit_invalid");
+    for &name in &["", "a=b", "a:b"] {
+        let error = child().inherit_queues(&[(name, &mq)]).status().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput, "name {:?}", name);
+    }
+}
+
+#[test]
+fn nothing_inherited() {
+    let error = PosixMq::from_inherited("jobs").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+}
@@ -0,0 +1,32 @@
+//! Tests of the io-safety trait impls.
+
+#![cfg(all(feature="io_safety", any(target_os="linux", target_os="netbsd", target_os="dragonfly")))]
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::os::unix::io::{AsFd, AsRawFd, OwnedFd};
+
+extern crate posixmq;
+use posixmq::{OpenOptions, PosixMq, remove_queue};
+
+#[test]
+fn borrow_and_convert() {
+    let mq = OpenOptions::readwrite().capacity(1).max_msg_len(5).create_new().open("/io_safety").unwrap();
+    remove_queue("/io_safety").unwrap();
+    let fd = mq.as_raw_fd();
+    assert_eq!(mq.as_fd().as_raw_fd(), fd);
+    let owned = OwnedFd::from(mq);
+    assert_eq!(owned.as_raw_fd(), fd);
+    let mq = PosixMq::try_from(owned).unwrap();
+    assert_eq!(mq.as_raw_fd(), fd);
+    mq.send(1, b"safe").unwrap();
+    assert_eq!(mq.recv(&mut [0; 5]).unwrap(), (1, 4));
+}
+
+#[test]
+fn not_a_queue() {
+    let file = OwnedFd::from(File::open("Cargo.toml").unwrap());
+    let error = PosixMq::try_from(file).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+}
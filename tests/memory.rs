@@ -0,0 +1,95 @@
+//! Tests of MessageQueue and MemoryMq.
+
+use std::io::ErrorKind;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+extern crate libc;
+extern crate posixmq;
+use posixmq::{MemoryMq, MessageQueue, OpenOptions, remove_queue};
+
+/// Send the same messages through a queue and return the order they're
+/// received in.
+fn roundtrip<Q: MessageQueue>(mq: Q) -> Vec<(u32, Vec<u8>)> {
+    let messages: &[(u32, &[u8])] = &[(1, b"a"), (3, b"b"), (1, b"c"), (2, b""), (3, b"d")];
+    for &(priority, msg) in messages {
+        mq.send(priority, msg).unwrap();
+    }
+    let mut buf = vec![0; mq.attributes().unwrap().max_msg_len];
+    let mut received = Vec::new();
+    for _ in messages {
+        let (priority, len) = mq.recv(&mut buf).unwrap();
+        received.push((priority, buf[..len].to_vec()));
+    }
+    received
+}
+
+#[test]
+fn same_order_as_posixmq() {
+    let name = "/memory_same_order";
+    let posix = OpenOptions::readwrite().capacity(5).max_msg_len(8).create_new().open(name).unwrap();
+    let _ = remove_queue(name);
+    let memory = MemoryMq::new(5, 8).unwrap();
+    assert_eq!(roundtrip(&memory), roundtrip(&posix));
+    assert_eq!(roundtrip(memory)[0], (3, b"b".to_vec()));
+}
+
+#[test]
+fn errors() {
+    let mq = MemoryMq::new(1, 4).unwrap();
+    let mut buf = [0; 4];
+    let error = mq.send(0, b"too long").unwrap_err();
+    assert_eq!(error.raw_os_error(), Some(libc::EMSGSIZE));
+    let error = mq.recv(&mut buf[..3]).unwrap_err();
+    assert_eq!(error.raw_os_error(), Some(libc::EMSGSIZE));
+    assert_eq!(mq.send(32768, b"").unwrap_err().kind(), ErrorKind::InvalidInput);
+    mq.send(32767, b"").unwrap();
+
+    let timeout = Duration::from_millis(10);
+    assert_eq!(mq.send_timeout(0, b"full", timeout).unwrap_err().kind(), ErrorKind::TimedOut);
+    let past = SystemTime::now() - timeout;
+    assert_eq!(mq.send_deadline(0, b"full", past).unwrap_err().kind(), ErrorKind::TimedOut);
+    mq.set_nonblocking(true);
+    assert_eq!(mq.send_timeout(0, b"full", timeout).unwrap_err().kind(), ErrorKind::WouldBlock);
+    assert_eq!(mq.recv(&mut buf).unwrap(), (32767, 0));
+    assert_eq!(mq.recv(&mut buf).unwrap_err().kind(), ErrorKind::WouldBlock);
+    mq.set_nonblocking(false);
+    assert_eq!(mq.recv_timeout(&mut buf, timeout).unwrap_err().kind(), ErrorKind::TimedOut);
+    let forever = Duration::new(!0, 0);
+    assert_eq!(mq.recv_timeout(&mut buf, forever).unwrap_err().kind(), ErrorKind::InvalidInput);
+
+    assert_eq!(MemoryMq::new(0, 1).unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(MemoryMq::new(1, 0).unwrap_err().kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn attributes_and_clones() {
+    let mq = MemoryMq::new(3, 10).unwrap();
+    let clone = mq.clone();
+    clone.set_nonblocking(true);
+    clone.send(0, b"shared").unwrap();
+    let attrs = mq.attributes().unwrap();
+    assert_eq!((attrs.capacity, attrs.max_msg_len, attrs.current_messages), (3, 10, 1));
+    assert!(!attrs.nonblocking);
+    assert!(clone.attributes().unwrap().nonblocking);
+    assert!(clone.clone().is_nonblocking());
+}
+
+#[test]
+fn blocks_until_ready() {
+    let mq = MemoryMq::new(1, 10).unwrap();
+    let receiver = mq.clone();
+    let receiving = thread::spawn(move|| {
+        let mut buf = [0; 10];
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            let (priority, len) = receiver.recv_timeout(&mut buf, Duration::from_secs(5)).unwrap();
+            received.push((priority, buf[..len].to_vec()));
+        }
+        received
+    });
+    for n in 0..3 {
+        mq.send_timeout(n, &[n as u8], Duration::from_secs(5)).unwrap();
+    }
+    assert_eq!(receiving.join().unwrap(), vec![(0, vec![0]), (1, vec![1]), (2, vec![2])]);
+}
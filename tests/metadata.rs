@@ -0,0 +1,73 @@
+//! Tests of metadata(), set_permissions(), set_owner() and ignore_umask().
+
+#![cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+
+use std::time::Duration;
+
+extern crate libc;
+
+extern crate posixmq;
+use posixmq::{OpenOptions, remove_queue};
+
+#[test]
+fn owner_and_permissions() {
+    let mq = OpenOptions::readwrite().mode(0o640).create_new().open("/metadata_owner").unwrap();
+    let _ = remove_queue("/metadata_owner");
+    let metadata = mq.metadata().unwrap();
+    assert_eq!(metadata.uid, unsafe { libc::geteuid() });
+    assert_eq!(metadata.gid, unsafe { libc::getegid() });
+    assert_eq!(metadata.mode & !0o640, 0, "mode {:o}", metadata.mode);
+
+    mq.set_permissions(0o604).unwrap();
+    assert_eq!(mq.metadata().unwrap().mode, 0o604);
+    mq.set_owner(None, None).unwrap();
+    mq.set_owner(Some(metadata.uid), Some(metadata.gid)).unwrap();
+    assert_eq!(mq.metadata().unwrap().uid, metadata.uid);
+}
+
+#[test]
+fn timestamps() {
+    let mq = OpenOptions::readwrite().capacity(1).max_msg_len(1).create_new().open("/metadata_times")
+        .unwrap();
+    let _ = remove_queue("/metadata_times");
+    let before = mq.metadata().unwrap();
+    assert!(before.modified.elapsed().unwrap() < Duration::from_secs(60));
+    mq.send(0, b"t").unwrap();
+    // timestamps might only have second resolution
+    let after = mq.metadata().unwrap();
+    assert!(after.modified >= before.modified);
+    assert!(after.changed >= before.changed);
+}
+
+#[test]
+fn ignore_umask() {
+    let old_umask = unsafe { libc::umask(0o077) };
+    let masked = OpenOptions::readwrite().mode(0o644).create_new().open("/metadata_masked");
+    let exact = OpenOptions::readwrite()
+        .mode(0o644)
+        .ignore_umask()
+        .create_new()
+        .open("/metadata_exact");
+    let existing = OpenOptions::readwrite()
+        .mode(0o666)
+        .ignore_umask()
+        .create()
+        .open("/metadata_exact");
+    let created = OpenOptions::readwrite()
+        .mode(0o604)
+        .ignore_umask()
+        .create()
+        .open("/metadata_created");
+    unsafe { libc::umask(old_umask) };
+    let _ = remove_queue("/metadata_masked");
+    let _ = remove_queue("/metadata_exact");
+    let _ = remove_queue("/metadata_created");
+
+    assert_eq!(masked.unwrap().metadata().unwrap().mode, 0o600);
+    assert_eq!(exact.unwrap().metadata().unwrap().mode, 0o644);
+    assert_eq!(existing.unwrap().metadata().unwrap().mode, 0o644, "existing queues are not changed");
+    assert_eq!(created.unwrap().metadata().unwrap().mode, 0o604);
+}
@@ -0,0 +1,56 @@
+//! Tests of send_queue_over() and recv_queue_from().
+
+#![cfg(any(target_os="linux", target_os="netbsd", target_os="dragonfly"))]
+
+use std::fs::File;
+use std::io::{ErrorKind, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
+use std::os::unix::net::UnixStream;
+
+extern crate posixmq;
+use posixmq::{OpenOptions, PosixMq, recv_queue_from, remove_queue, send_queue_over};
+
+#[test]
+fn queue_survives_transfer() {
+    let (a, b) = UnixStream::pair().unwrap();
+    let name = "/pass_queue_transfer";
+    let mq = OpenOptions::readwrite().capacity(2).max_msg_len(8).create_new().open(name).unwrap();
+    let _ = remove_queue(name);
+    mq.send(3, b"before").unwrap();
+    send_queue_over(&a, &mq).unwrap();
+    send_queue_over(&a, &mq).unwrap();
+    drop(mq);
+
+    let received = recv_queue_from(&b).unwrap();
+    let second = recv_queue_from(&b).unwrap();
+    assert_ne!(received.as_raw_fd(), second.as_raw_fd());
+    assert!(received.is_cloexec().unwrap());
+    let mut buf = [0; 8];
+    assert_eq!(received.recv(&mut buf).unwrap(), (3, 6));
+    second.send(1, b"after").unwrap();
+    assert_eq!(received.attributes().unwrap().current_messages, 1);
+}
+
+#[test]
+fn rejects_other_descriptors() {
+    let (a, b) = UnixStream::pair().unwrap();
+    let file = File::open("Cargo.toml").unwrap();
+    // only for sending; send_queue_over() doesn't look at the descriptor
+    let not_mq = unsafe { PosixMq::from_raw_fd(file.into_raw_fd()) };
+    send_queue_over(&a, &not_mq).unwrap();
+    let error = recv_queue_from(&b).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    assert!(error.to_string().contains("not a message queue"), "{}", error);
+}
+
+#[test]
+fn data_without_descriptor_and_eof() {
+    let (mut a, b) = UnixStream::pair().unwrap();
+    a.write_all(b"x").unwrap();
+    assert_eq!(recv_queue_from(&b).unwrap_err().kind(), ErrorKind::InvalidData);
+    drop(a);
+    assert_eq!(recv_queue_from(&b).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    let (_a, b) = UnixStream::pair().unwrap();
+    b.set_nonblocking(true).unwrap();
+    assert_eq!(recv_queue_from(&b).unwrap_err().kind(), ErrorKind::WouldBlock);
+}
@@ -0,0 +1,106 @@
+//! Tests of resize_queue().
+
+#![cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+
+use std::io::ErrorKind;
+use std::mem;
+use std::os::unix::io::AsRawFd;
+
+extern crate libc;
+
+extern crate posixmq;
+use posixmq::{OpenOptions, PosixMq, remove_queue, resize_queue};
+
+fn mode(mq: &PosixMq) -> u32 {
+    let mut stat: libc::stat = unsafe { mem::zeroed() };
+    assert_eq!(unsafe { libc::fstat(mq.as_raw_fd(), &mut stat) }, 0);
+    stat.st_mode as u32 & 0o7777
+}
+
+fn drain(mq: &PosixMq) -> Vec<(u32, Vec<u8>)> {
+    mq.set_nonblocking(true).unwrap();
+    mq.iter().collect()
+}
+
+#[test]
+fn moves_messages_and_permissions() {
+    let old = OpenOptions::readwrite()
+        .capacity(3)
+        .max_msg_len(8)
+        .create_new()
+        .open("/resize_moves")
+        .unwrap();
+    assert_eq!(unsafe { libc::fchmod(old.as_raw_fd(), 0o666) }, 0);
+    old.send(1, b"first").unwrap();
+    old.send(2, b"too long").unwrap();
+    old.send(1, b"second").unwrap();
+
+    let resized = resize_queue("/resize_moves", 5, 6);
+    let _ = remove_queue("/resize_moves");
+    let resized = resized.unwrap();
+    assert_eq!(resized.moved, 2);
+    assert_eq!(resized.oversized, vec![(2, b"too long".to_vec())]);
+    assert!(resized.overflowed.is_empty());
+    let attrs = resized.queue.attributes().unwrap();
+    assert_eq!((attrs.capacity, attrs.max_msg_len), (5, 6));
+    assert_eq!(mode(&resized.queue), 0o666, "not affected by umask");
+    assert_eq!(drain(&resized.queue), vec![(1, b"first".to_vec()), (1, b"second".to_vec())]);
+    assert_eq!(old.attributes().unwrap().current_messages, 0);
+}
+
+#[test]
+fn reports_overflow() {
+    let old = OpenOptions::readwrite()
+        .capacity(4)
+        .max_msg_len(8)
+        .mode(0o600)
+        .create_new()
+        .open("/resize_overflow")
+        .unwrap();
+    for n in 0..4 {
+        old.send(n, &[n as u8]).unwrap();
+    }
+    let resized = resize_queue("/resize_overflow", 2, 8);
+    let _ = remove_queue("/resize_overflow");
+    let resized = resized.unwrap();
+    assert_eq!(resized.moved, 2);
+    assert_eq!(resized.overflowed, vec![(1, vec![1]), (0, vec![0])]);
+    assert_eq!(mode(&resized.queue), 0o600);
+    assert_eq!(drain(&resized.queue), vec![(3, vec![3]), (2, vec![2])]);
+}
+
+#[test]
+fn restores_queue_on_error() {
+    let old = OpenOptions::readwrite()
+        .capacity(2)
+        .max_msg_len(8)
+        .create_new()
+        .open("/resize_invalid")
+        .unwrap();
+    old.send(0, b"kept").unwrap();
+    let error = resize_queue("/resize_invalid", 1_000_000_000, 8).unwrap_err();
+    let restored = PosixMq::open("/resize_invalid");
+    let _ = remove_queue("/resize_invalid");
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    assert!(error.unmoved.is_empty());
+    assert_eq!(error.restored.unwrap().attributes().unwrap().capacity, 2);
+    assert_eq!(drain(&restored.unwrap()), vec![(0, b"kept".to_vec())]);
+}
+
+#[test]
+fn converts_to_io_error() {
+    fn resize() -> Result<(), std::io::Error> {
+        resize_queue("/resize_missing_io", 1, 1)?;
+        Ok(())
+    }
+    assert_eq!(resize().unwrap_err().kind(), ErrorKind::NotFound);
+}
+
+#[test]
+fn missing_queue() {
+    let error = resize_queue("/resize_missing", 1, 1).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+}
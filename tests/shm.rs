@@ -0,0 +1,184 @@
+//! Tests of ShmMq and AnyMq.
+
+#![cfg(all(feature="shm", target_os="linux"))]
+
+use std::io::{ErrorKind, Seek, SeekFrom, Write};
+use std::thread;
+use std::time::Duration;
+
+extern crate libc;
+extern crate posixmq;
+use posixmq::{AnyMq, MessageQueue, OpenOptions, ShmMq, remove_shm_queue};
+
+fn tmp_shm(name: &str,  capacity: usize,  max_msg_len: usize) -> ShmMq {
+    let _ = remove_shm_queue(name);
+    OpenOptions::readwrite()
+        .capacity(capacity)
+        .max_msg_len(max_msg_len)
+        .create_new()
+        .open_shm(name)
+        .unwrap_or_else(|e| panic!("cannot create {}: {}", name, e) )
+}
+
+#[test]
+fn priority_order_across_mappings() {
+    let sender = tmp_shm("/shm_order", 20, 10);
+    let receiver = OpenOptions::readonly().open_shm("/shm_order").unwrap();
+    remove_shm_queue("/shm_order").unwrap();
+    for (n, &priority) in [1, 5, 1, 32767, 0, 5].iter().enumerate() {
+        sender.send(priority, &[n as u8]).unwrap();
+    }
+    let attrs = receiver.attributes().unwrap();
+    assert_eq!((attrs.capacity, attrs.max_msg_len, attrs.current_messages), (20, 10, 6));
+    let mut buf = [0; 10];
+    let mut received = Vec::new();
+    for _ in 0..6 {
+        let (priority, len) = receiver.recv(&mut buf).unwrap();
+        assert_eq!(len, 1);
+        received.push((priority, buf[0]));
+    }
+    assert_eq!(received, vec![(32767, 3), (5, 1), (5, 5), (1, 0), (1, 2), (0, 4)]);
+}
+
+#[test]
+fn errors() {
+    let mq = tmp_shm("/shm_errors", 1, 4);
+    let timeout = Duration::from_millis(10);
+    let mut buf = [0; 4];
+    assert_eq!(mq.send(0, b"too long").unwrap_err().raw_os_error(), Some(libc::EMSGSIZE));
+    assert_eq!(mq.recv(&mut buf[..3]).unwrap_err().raw_os_error(), Some(libc::EMSGSIZE));
+    assert_eq!(mq.send(32768, b"").unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(mq.recv_timeout(&mut buf, timeout).unwrap_err().kind(), ErrorKind::TimedOut);
+    mq.send(0, b"full").unwrap();
+    assert_eq!(mq.send_timeout(0, b"full", timeout).unwrap_err().kind(), ErrorKind::TimedOut);
+    mq.set_nonblocking(true);
+    assert_eq!(mq.send(0, b"full").unwrap_err().kind(), ErrorKind::WouldBlock);
+
+    let readonly = OpenOptions::readonly().nonblocking().open_shm("/shm_errors").unwrap();
+    assert!(readonly.attributes().unwrap().nonblocking);
+    assert_eq!(readonly.send(0, b"").unwrap_err().raw_os_error(), Some(libc::EBADF));
+    let writeonly = OpenOptions::writeonly().open_shm("/shm_errors").unwrap();
+    assert_eq!(writeonly.recv(&mut buf).unwrap_err().raw_os_error(), Some(libc::EBADF));
+
+    let error = OpenOptions::readwrite().create_new().open_shm("/shm_errors").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+    remove_shm_queue("/shm_errors").unwrap();
+    let error = OpenOptions::readwrite().open_shm("/shm_errors").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+    let error = OpenOptions::readwrite().capacity(1).create().open_shm("/shm_errors").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn defaults_and_create() {
+    let _ = remove_shm_queue("/shm_defaults");
+    let mq = OpenOptions::readwrite().create().open_shm("/shm_defaults").unwrap();
+    // opens the existing queue instead of creating with these capacities
+    let again = OpenOptions::readwrite().capacity(2).max_msg_len(2).create().open_shm("/shm_defaults").unwrap();
+    remove_shm_queue("/shm_defaults").unwrap();
+    let attrs = again.attributes().unwrap();
+    assert_eq!((attrs.capacity, attrs.max_msg_len), (10, 8192));
+    mq.send(3, b"shared").unwrap();
+    assert_eq!(again.attributes().unwrap().current_messages, 1);
+}
+
+#[test]
+fn blocks_until_ready() {
+    let mq = tmp_shm("/shm_blocking", 1, 8);
+    let other = OpenOptions::readwrite().open_shm("/shm_blocking").unwrap();
+    remove_shm_queue("/shm_blocking").unwrap();
+    let receiving = thread::spawn(move|| {
+        let mut buf = [0; 8];
+        let mut sum = 0;
+        for _ in 0..100 {
+            let (priority, _) = other.recv_timeout(&mut buf, Duration::from_secs(5)).unwrap();
+            sum += priority;
+        }
+        sum
+    });
+    for n in 0..100 {
+        mq.send_timeout(n, b"", Duration::from_secs(5)).unwrap();
+    }
+    assert_eq!(receiving.join().unwrap(), 4950);
+}
+
+#[test]
+fn open_any_prefers_posix() {
+    let mq = OpenOptions::readwrite().capacity(1).max_msg_len(1).create_new().open_any("/shm_any").unwrap();
+    let _ = posixmq::remove_queue("/shm_any");
+    match mq {
+        AnyMq::Posix(_) => {}
+        AnyMq::Shm(_) => panic!("posix message queues are supported"),
+    }
+    mq.send(1, b"a").unwrap();
+    assert_eq!(mq.attributes().unwrap().current_messages, 1);
+}
+
+#[test]
+fn interleaved_sends_and_receives_keep_order() {
+    let mq = tmp_shm("/shm_interleaved", 50, 4);
+    remove_shm_queue("/shm_interleaved").unwrap();
+    let mut expected = Vec::<(u32, u32)>::new();
+    let mut buf = [0; 4];
+    for n in 0..400u32 {
+        let priority = n.wrapping_mul(2_654_435_761) % 7;
+        mq.send(priority, &n.to_be_bytes()).unwrap();
+        expected.push((priority, n));
+        if n % 3 == 2  ||  expected.len() == 50 {
+            // highest priority, and oldest among those
+            expected.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)) );
+            let (priority, n) = expected.remove(0);
+            assert_eq!(mq.recv(&mut buf).unwrap(), (priority, 4));
+            assert_eq!(u32::from_be_bytes(buf), n);
+        }
+    }
+    expected.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)) );
+    for (priority, n) in expected {
+        assert_eq!(mq.recv(&mut buf).unwrap(), (priority, 4));
+        assert_eq!(u32::from_be_bytes(buf), n);
+    }
+}
+
+#[test]
+fn read_only_mode() {
+    let _ = remove_shm_queue("/shm_readonly");
+    let mq = OpenOptions::readwrite()
+        .mode(0o444)
+        .capacity(1)
+        .max_msg_len(1)
+        .create_new()
+        .open_shm("/shm_readonly")
+        .unwrap();
+    mq.send(1, b"r").unwrap();
+    let receiver = OpenOptions::readonly().open_shm("/shm_readonly").unwrap();
+    let writer = OpenOptions::writeonly().open_shm("/shm_readonly");
+    remove_shm_queue("/shm_readonly").unwrap();
+    assert_eq!(receiver.recv(&mut [0]).unwrap(), (1, 1));
+    if unsafe { libc::geteuid() } != 0 {
+        assert_eq!(writer.unwrap_err().kind(), ErrorKind::PermissionDenied);
+    }
+}
+
+#[test]
+fn corrupted_queue_is_an_error() {
+    let mq = tmp_shm("/shm_corrupted", 2, 4);
+    mq.send(0, b"good").unwrap();
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .open("/dev/shm/posixmq.shm_corrupted")
+        .unwrap();
+    remove_shm_queue("/shm_corrupted").unwrap();
+    // the length of the message in the first slot, after the header and the
+    // two slot indexes
+    file.seek(SeekFrom::Start(48 + 8 + 4)).unwrap();
+    file.write_all(&[0xff; 4]).unwrap();
+    let mut buf = [0; 4];
+    assert_eq!(mq.recv(&mut buf).unwrap_err().kind(), ErrorKind::InvalidData);
+    // the number of messages
+    file.seek(SeekFrom::Start(32)).unwrap();
+    file.write_all(&[0xff; 4]).unwrap();
+    assert_eq!(mq.recv(&mut buf).unwrap_err().kind(), ErrorKind::InvalidData);
+    // didn't leave the queue locked
+    let timeout = Duration::from_millis(100);
+    assert_eq!(mq.send_timeout(0, b"", timeout).unwrap_err().kind(), ErrorKind::TimedOut);
+}
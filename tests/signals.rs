@@ -0,0 +1,119 @@
+//! Tests of how sending and receiving handles EINTR.
+
+#![cfg(target_os="linux")] // other OSes might not interrupt the calls
+
+use std::io::ErrorKind;
+use std::os::unix::thread::JoinHandleExt;
+#[allow(deprecated)] // ONCE_INIT is needed on 1.31
+use std::sync::{Once, ONCE_INIT};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+use std::{mem, ptr};
+
+extern crate libc;
+use libc::{c_int, pthread_kill, sigaction, sigemptyset, SIGUSR1};
+
+extern crate posixmq;
+use posixmq::{OpenOptions, PosixMq, remove_queue};
+
+extern "C" fn ignore_signal(_: c_int) {}
+
+/// Install a handler without `SA_RESTART`, so that blocking calls are interrupted.
+#[allow(deprecated)]
+fn install_handler() {
+    static INSTALL: Once = ONCE_INIT;
+    INSTALL.call_once(|| unsafe {
+        let mut action: sigaction = mem::zeroed();
+        action.sa_sigaction = ignore_signal as extern "C" fn(c_int) as usize;
+        sigemptyset(&mut action.sa_mask);
+        assert_eq!(sigaction(SIGUSR1, &action, ptr::null_mut()), 0, "install signal handler");
+    });
+}
+
+fn tmp_mq(name: &str) -> PosixMq {
+    let mq = OpenOptions::readwrite()
+        .capacity(1)
+        .max_msg_len(8)
+        .create_new()
+        .open(name)
+        .unwrap_or_else(|e| panic!("cannot create {}: {}", name, e) );
+    let _ = remove_queue(name);
+    mq
+}
+
+/// Run the closure in another thread and signal that thread repeatedly until
+/// the closure returns, in case the first signals arrive before it blocks.
+fn interrupt<T, F>(f: F) -> T
+where T: Send + 'static, F: FnOnce() -> T + Send + 'static {
+    let (sender, receiver) = mpsc::channel();
+    let thread = thread::spawn(move|| sender.send(f()).unwrap() );
+    for _ in 0..100 {
+        match receiver.recv_timeout(Duration::from_millis(20)) {
+            Ok(result) => {
+                thread.join().unwrap();
+                return result;
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                assert_eq!(unsafe { pthread_kill(thread.as_pthread_t(), SIGUSR1) }, 0);
+            }
+            Err(RecvTimeoutError::Disconnected) => panic!("thread panicked"),
+        }
+    }
+    panic!("thread is still blocking");
+}
+
+#[test]
+fn recv_is_interrupted_when_not_retrying() {
+    install_handler();
+    let mq = tmp_mq("/signals_recv_interrupted");
+    mq.set_retry_interrupted(false);
+    let result = interrupt(move|| mq.recv(&mut [0; 8]).map_err(|e| e.kind() ) );
+    assert_eq!(result, Err(ErrorKind::Interrupted));
+}
+
+#[test]
+fn send_timeout_is_interrupted_when_not_retrying() {
+    install_handler();
+    let mq = tmp_mq("/signals_send_interrupted");
+    mq.send(0, b"full").unwrap();
+    mq.set_retry_interrupted(false);
+    let result = interrupt(move|| {
+        mq.send_timeout(0, b"blocks", Duration::from_secs(10)).map_err(|e| e.kind() )
+    });
+    assert_eq!(result, Err(ErrorKind::Interrupted));
+}
+
+#[test]
+fn wait_is_interrupted_when_not_retrying() {
+    install_handler();
+    let mq = tmp_mq("/signals_wait_interrupted");
+    mq.set_retry_interrupted(false);
+    let result = interrupt(move|| mq.wait_readable(None).map_err(|e| e.kind() ) );
+    assert_eq!(result, Err(ErrorKind::Interrupted));
+}
+
+#[test]
+fn retries_by_default() {
+    install_handler();
+    let mq = tmp_mq("/signals_retried");
+    let sender = mq.try_clone().unwrap();
+    let receiver = thread::spawn(move|| {
+        assert!(mq.is_retrying_interrupted());
+        let mut buf = [0; 8];
+        mq.recv(&mut buf).map(|(priority, len)| (priority, buf[..len].to_vec()) )
+    });
+    for _ in 0..5 {
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(unsafe { pthread_kill(receiver.as_pthread_t(), SIGUSR1) }, 0);
+    }
+    sender.send(3, b"after").unwrap();
+    assert_eq!(receiver.join().unwrap().unwrap(), (3, b"after".to_vec()));
+}
+
+#[test]
+fn try_clone_copies_setting() {
+    let mq = tmp_mq("/signals_clone");
+    mq.set_retry_interrupted(false);
+    assert!(!mq.try_clone().unwrap().is_retrying_interrupted());
+}
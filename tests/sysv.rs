@@ -0,0 +1,152 @@
+//! Tests of SysvMq.
+
+#![cfg(target_os="linux")]
+
+use std::io::ErrorKind;
+use std::thread;
+use std::time::{Duration, Instant};
+
+extern crate libc;
+extern crate posixmq;
+use posixmq::{MessageQueue, OpenOptions, SysvMq};
+
+/// Create a private queue, which is removed when the returned value is dropped.
+struct TmpMq(SysvMq);
+impl Drop for TmpMq {
+    fn drop(&mut self) {
+        let _ = self.0.remove();
+    }
+}
+
+fn tmp_mq(capacity: usize,  max_msg_len: usize) -> TmpMq {
+    let mq = OpenOptions::readwrite()
+        .capacity(capacity)
+        .max_msg_len(max_msg_len)
+        .create()
+        .open_sysv(libc::IPC_PRIVATE)
+        .unwrap_or_else(|e| panic!("cannot create private queue: {}", e) );
+    TmpMq(mq)
+}
+
+#[test]
+fn priority_ordering_mode() {
+    let mq = tmp_mq(10, 16);
+    mq.0.set_direct_types(false);
+    assert!(!mq.0.is_using_direct_types());
+    for (n, &priority) in [1, 5, 1, 32767, 0, 5].iter().enumerate() {
+        mq.0.send(priority, &[n as u8]).unwrap();
+    }
+    let attrs = mq.0.attributes().unwrap();
+    assert_eq!((attrs.capacity, attrs.max_msg_len, attrs.current_messages), (10, 16, 6));
+    let mut buf = [0; 16];
+    let mut received = Vec::new();
+    for _ in 0..6 {
+        let (priority, len) = mq.0.recv(&mut buf).unwrap();
+        assert_eq!(len, 1);
+        received.push((priority, buf[0]));
+    }
+    assert_eq!(received, vec![(32767, 3), (5, 1), (5, 5), (1, 0), (1, 2), (0, 4)]);
+    assert_eq!(mq.0.send(32768, b"").unwrap_err().kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn direct_types_by_default() {
+    let mq = tmp_mq(10, 16);
+    assert!(mq.0.is_using_direct_types());
+    for (n, &priority) in [5, 1, 40000, 5].iter().enumerate() {
+        mq.0.send(priority, &[n as u8]).unwrap();
+    }
+    let mut buf = [0; 16];
+    let mut received = Vec::new();
+    for _ in 0..4 {
+        let (priority, _) = mq.0.recv(&mut buf).unwrap();
+        received.push((priority, buf[0]));
+    }
+    assert_eq!(received, vec![(1, 1), (5, 0), (5, 3), (40000, 2)]);
+    assert_eq!(mq.0.send(0, b"").unwrap_err().kind(), ErrorKind::InvalidInput);
+}
+
+/// struct msgbuf {long mtype; char mtext[8];}
+#[repr(C)]
+struct RawMsg {
+    mtype: libc::c_long,
+    mtext: [u8; 8],
+}
+
+#[test]
+fn legacy_processes_see_the_same_types() {
+    let mq = tmp_mq(10, 8);
+    let size = 7;
+    for &mtype in &[3, 1] {
+        let msg = RawMsg { mtype, mtext: *b"legacy\0\0" };
+        let msg_ptr = &msg as *const RawMsg as *const libc::c_void;
+        assert_eq!(unsafe { libc::msgsnd(mq.0.id(), msg_ptr, size, 0) }, 0);
+    }
+    let mut buf = [0; 8];
+    assert_eq!(mq.0.recv(&mut buf).unwrap(), (1, 7));
+    assert_eq!(&buf[..7], b"legacy\0");
+
+    mq.0.send(2, b"posixmq").unwrap();
+    let mut msg = RawMsg { mtype: 0, mtext: [0; 8] };
+    let msg_ptr = &mut msg as *mut RawMsg as *mut libc::c_void;
+    let len = unsafe { libc::msgrcv(mq.0.id(), msg_ptr, 8, 2, libc::IPC_NOWAIT) };
+    assert_eq!((len, msg.mtype), (7, 2));
+    assert_eq!(&msg.mtext[..7], b"posixmq");
+    let len = unsafe { libc::msgrcv(mq.0.id(), msg_ptr, 8, 3, libc::IPC_NOWAIT) };
+    assert_eq!((len, msg.mtype), (7, 3));
+}
+
+#[test]
+fn shared_with_other_handles() {
+    let mq = tmp_mq(2, 8);
+    let other = SysvMq::from_id(mq.0.id(), 8);
+    other.send(2, b"").unwrap();
+    other.send(1, b"12345678").unwrap();
+    let mut buf = [0; 8];
+    assert_eq!(mq.0.recv(&mut buf).unwrap(), (1, 8));
+    assert_eq!(&buf, b"12345678");
+    assert_eq!(mq.0.recv(&mut buf).unwrap(), (2, 0));
+}
+
+#[test]
+fn errors_and_nonblocking() {
+    let mq = tmp_mq(1, 4);
+    let mut buf = [0; 4];
+    assert_eq!(mq.0.send(1, b"too long").unwrap_err().raw_os_error(), Some(libc::EMSGSIZE));
+    assert_eq!(mq.0.recv(&mut buf[..3]).unwrap_err().raw_os_error(), Some(libc::EMSGSIZE));
+
+    mq.0.set_nonblocking(true);
+    assert_eq!(mq.0.recv(&mut buf).unwrap_err().kind(), ErrorKind::WouldBlock);
+    mq.0.send(1, b"full").unwrap();
+    assert_eq!(mq.0.send(1, b"full").unwrap_err().kind(), ErrorKind::WouldBlock);
+    let timeout = Duration::from_millis(20);
+    assert_eq!(mq.0.send_timeout(1, b"full", timeout).unwrap_err().kind(), ErrorKind::WouldBlock);
+    mq.0.set_nonblocking(false);
+    let start = Instant::now();
+    assert_eq!(mq.0.send_timeout(1, b"full", timeout).unwrap_err().kind(), ErrorKind::TimedOut);
+    assert!(start.elapsed() >= timeout);
+
+    let key = 0x706d_7131; // unlikely to exist
+    let error = OpenOptions::readwrite().open_sysv(key).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+    let readonly = OpenOptions::readonly().create_new().open_sysv(key).unwrap();
+    let error = OpenOptions::readwrite().create_new().open_sysv(key).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+    let result = readonly.send(1, b"");
+    readonly.remove().unwrap();
+    assert_eq!(result.unwrap_err().raw_os_error(), Some(libc::EBADF));
+    assert!(readonly.attributes().is_err());
+}
+
+#[test]
+fn timed_recv_waits_for_sender() {
+    let mq = tmp_mq(1, 8);
+    let sender = SysvMq::from_id(mq.0.id(), 8);
+    let sending = thread::spawn(move|| {
+        thread::sleep(Duration::from_millis(30));
+        sender.send(7, b"late").unwrap();
+    });
+    let mut buf = [0; 8];
+    assert_eq!(mq.0.recv_timeout(&mut buf, Duration::from_secs(5)).unwrap(), (7, 4));
+    sending.join().unwrap();
+}
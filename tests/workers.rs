@@ -0,0 +1,140 @@
+//! Tests of WorkerPool.
+
+#![cfg(any(
+    target_os="linux", target_os="freebsd",
+    target_os="netbsd", target_os="dragonfly",
+))]
+
+use std::io::ErrorKind;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+extern crate posixmq;
+use posixmq::{OpenOptions, PosixMq, WorkerEvent, WorkerPool, remove_queue};
+
+fn tmp_mq(name: &str) -> PosixMq {
+    let mq = OpenOptions::readwrite()
+        .capacity(8)
+        .max_msg_len(16)
+        .create_new()
+        .open(name)
+        .unwrap_or_else(|e| panic!("cannot create {}: {}", name, e) );
+    let _ = remove_queue(name);
+    mq
+}
+
+#[test]
+fn handles_messages_on_all_threads() {
+    let mq = tmp_mq("/workers_handle");
+    let sender = mq.try_clone().unwrap();
+    let (handled, results) = mpsc::channel();
+    let handled = Mutex::new(handled);
+    let pool = WorkerPool::spawn(mq, 3, move |priority, msg| {
+        let name = thread::current().name().unwrap().to_string();
+        handled.lock().unwrap().send((priority, msg.to_vec(), name)).map_err(|_| () )
+    }, |event| panic!("unexpected {:?}", event) ).unwrap();
+
+    for n in 0..20u8 {
+        sender.send(n as u32, &[n]).unwrap();
+    }
+    let mut received = (0..20)
+        .map(|_| results.recv_timeout(Duration::from_secs(5)).unwrap() )
+        .inspect(|(_, _, name)| assert!(name.starts_with("posixmq worker "), "{}", name) )
+        .map(|(priority, msg, _)| (priority, msg) )
+        .collect::<Vec<_>>();
+    received.sort();
+    assert_eq!(received, (0..20u8).map(|n| (n as u32, vec![n]) ).collect::<Vec<_>>());
+    pool.shutdown();
+}
+
+#[test]
+fn reports_errors_and_panics() {
+    let mq = tmp_mq("/workers_errors");
+    let sender = mq.try_clone().unwrap();
+    let (reporter, events) = mpsc::channel();
+    let reporter = Mutex::new(reporter);
+    let pool = WorkerPool::spawn(mq, 1, |priority, msg| {
+        match msg {
+            b"fail" => Err(format!("failed with priority {}", priority)),
+            b"panic" => panic!("handler panicked"),
+            _ => Ok(()),
+        }
+    }, move |event| reporter.lock().unwrap().send(event).unwrap() ).unwrap();
+
+    sender.send(1, b"fail").unwrap();
+    match events.recv_timeout(Duration::from_secs(5)).unwrap() {
+        WorkerEvent::HandlerFailed {worker: 0, priority: 1, error} => {
+            assert_eq!(error, "failed with priority 1");
+        }
+        other => panic!("expected HandlerFailed, got {:?}", other),
+    }
+    sender.send(2, b"panic").unwrap();
+    match events.recv_timeout(Duration::from_secs(5)).unwrap() {
+        WorkerEvent::HandlerPanicked {worker: 0, priority: 2, payload} => {
+            assert_eq!(payload.downcast_ref::<&str>(), Some(&"handler panicked"));
+        }
+        other => panic!("expected HandlerPanicked, got {:?}", other),
+    }
+    // the worker continues after a panic
+    sender.send(3, b"fail").unwrap();
+    match events.recv_timeout(Duration::from_secs(5)).unwrap() {
+        WorkerEvent::HandlerFailed {priority: 3, ..} => {}
+        other => panic!("expected HandlerFailed, got {:?}", other),
+    }
+    pool.shutdown();
+}
+
+#[test]
+fn reports_recv_errors() {
+    let mq = OpenOptions::writeonly()
+        .capacity(1)
+        .max_msg_len(16)
+        .create_new()
+        .open("/workers_writeonly")
+        .unwrap();
+    let _ = remove_queue("/workers_writeonly");
+    let (reporter, events) = mpsc::channel();
+    let reporter = Mutex::new(reporter);
+    let pool = WorkerPool::spawn(mq, 2, |_, _| Ok::<(), ()>(()), move |event| {
+        reporter.lock().unwrap().send(event).unwrap()
+    }).unwrap();
+    for _ in 0..2 {
+        match events.recv_timeout(Duration::from_secs(5)).unwrap() {
+            WorkerEvent::RecvFailed {..} => {}
+            other => panic!("expected RecvFailed, got {:?}", other),
+        }
+    }
+    pool.shutdown();
+}
+
+#[test]
+fn shutdown_finishes_in_flight_messages() {
+    let mq = tmp_mq("/workers_shutdown");
+    let sender = mq.try_clone().unwrap();
+    let (started, handling) = mpsc::channel();
+    let started = Mutex::new(started);
+    let finished = Arc::new(AtomicBool::new(false));
+    let handler_finished = finished.clone();
+    let pool = WorkerPool::spawn(mq, 1, move |_, _| {
+        started.lock().unwrap().send(()).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        handler_finished.store(true, Ordering::SeqCst);
+        Ok::<(), ()>(())
+    }, |event| panic!("unexpected {:?}", event) ).unwrap();
+
+    sender.send(0, b"slow").unwrap();
+    handling.recv_timeout(Duration::from_secs(5)).unwrap();
+    pool.shutdown();
+    assert!(finished.load(Ordering::SeqCst));
+    assert_eq!(sender.attributes().unwrap().current_messages, 0);
+}
+
+#[test]
+fn needs_threads() {
+    let mq = tmp_mq("/workers_zero");
+    let error = WorkerPool::spawn(mq, 0, |_, _| Ok::<(), ()>(()), |_| {} ).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+}